@@ -0,0 +1,67 @@
+//! Rule matching on its own, for comparing lookup changes: `cargo bench`
+
+// Built from the binary's sources, which have no library target
+#[allow(dead_code)]
+#[path = "../src/matcher.rs"]
+mod matcher;
+#[allow(dead_code)]
+#[path = "../src/schedule.rs"]
+mod schedule;
+
+use axum::http::HeaderMap;
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use matcher::{MatchOptions, Matcher, ParsedRule, Rule, build_matcher, match_rule};
+use std::hint::black_box;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn rules(count: usize, url: impl Fn(usize) -> String) -> Matcher {
+    build_matcher(
+        (0..count)
+            .map(|i| ParsedRule {
+                url: url(i),
+                rule: Rule::new(format!("https://example.com/{i}"), 301),
+            })
+            .collect(),
+    )
+    .unwrap()
+}
+
+fn bench_group(
+    c: &mut Criterion,
+    name: &str,
+    url: impl Fn(usize) -> String,
+    path: impl Fn(usize) -> String,
+) {
+    let headers = HeaderMap::new();
+    let mut group = c.benchmark_group(name);
+    for size in SIZES {
+        let matcher = rules(size, &url);
+        // The middle rule, so ordered scans do half their work
+        let path = path(size / 2);
+        assert!(match_rule(&matcher, &path, &headers, MatchOptions::default()).is_some());
+        group.bench_with_input(BenchmarkId::from_parameter(size), &path, |b, path| {
+            b.iter(|| match_rule(&matcher, black_box(path), &headers, MatchOptions::default()))
+        });
+    }
+    group.finish();
+}
+
+fn matching(c: &mut Criterion) {
+    bench_group(c, "exact", |i| format!("/s{i}"), |i| format!("/s{i}"));
+    bench_group(
+        c,
+        "prefix",
+        |i| format!("/p{i}/*"),
+        |i| format!("/p{i}/deep/path"),
+    );
+    bench_group(
+        c,
+        "regex",
+        |i| format!("~^/r{i}/(\\d+)$"),
+        |i| format!("/r{i}/42"),
+    );
+}
+
+criterion_group!(benches, matching);
+criterion_main!(benches);
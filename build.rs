@@ -0,0 +1,41 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Allow the SHA to be injected (e.g. Docker builds without .git)
+    let git_sha = std::env::var("DSLF_GIT_SHA").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|sha| sha.trim().to_string())
+    });
+
+    if let Some(sha) = git_sha.filter(|sha| !sha.is_empty()) {
+        println!("cargo:rustc-env=DSLF_GIT_SHA={sha}");
+    }
+
+    // Also injectable, for reproducible builds
+    let timestamp = std::env::var("DSLF_BUILD_TIMESTAMP")
+        .ok()
+        .and_then(|timestamp| timestamp.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+    println!("cargo:rustc-env=DSLF_BUILD_TIMESTAMP={timestamp}");
+
+    println!("cargo:rerun-if-env-changed=DSLF_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=DSLF_BUILD_TIMESTAMP");
+    // Source edits refresh the timestamp, commits the SHA. Paths that don't
+    // exist would make Cargo rerun this on every build.
+    for path in ["src", "Cargo.toml", ".git/HEAD", ".git/refs/heads"] {
+        if std::path::Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
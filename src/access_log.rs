@@ -0,0 +1,263 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use syslog::{Facility, Formatter3164, Logger};
+
+/// How `logging_middleware` writes each request
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `timestamp ip METHOD path status duration`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+    /// Common Log Format
+    Clf,
+    /// Combined Log Format (CLF plus referer and user agent)
+    Combined,
+}
+
+/// What gets logged about one request
+#[derive(Debug)]
+pub struct LogEntry<'a> {
+    /// Unix time the response was sent
+    pub timestamp: u64,
+    pub client_ip: &'a str,
+    pub method: &'a str,
+    /// Path and query as requested
+    pub target: &'a str,
+    /// e.g. `HTTP/1.1`
+    pub version: &'a str,
+    pub status: u16,
+    /// Response `Content-Length`, when known
+    pub bytes: Option<u64>,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub duration: Duration,
+    /// Hash of the path, with `--log-slug-hash`
+    pub shard: Option<String>,
+    /// `note` column of the retired rule that answered
+    pub note: Option<&'a str>,
+    /// `X-Request-Id`, with `--request-id`
+    pub request_id: Option<&'a str>,
+}
+
+impl LogEntry<'_> {
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Text => {
+                let shard = self
+                    .shard
+                    .as_ref()
+                    .map(|shard| format!(" shard={shard}"))
+                    .unwrap_or_default();
+                let request_id = self
+                    .request_id
+                    .map(|id| format!(" request_id={id}"))
+                    .unwrap_or_default();
+                let note = self
+                    .note
+                    .map(|note| format!(" note=\"{}\"", note.replace('"', "\\\"")))
+                    .unwrap_or_default();
+                format!(
+                    "{} {} {} {} {} {:.2}ms{shard}{request_id}{note}",
+                    self.timestamp,
+                    self.client_ip,
+                    self.method,
+                    self.target,
+                    self.status,
+                    self.duration.as_secs_f64() * 1000.0,
+                )
+            }
+            LogFormat::Json => serde_json::json!({
+                "timestamp": self.timestamp,
+                "client_ip": self.client_ip,
+                "method": self.method,
+                "path": self.target,
+                "status": self.status,
+                "bytes": self.bytes,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+                "duration_ms": self.duration.as_secs_f64() * 1000.0,
+                "shard": self.shard,
+                "note": self.note,
+                "request_id": self.request_id,
+            })
+            .to_string(),
+            LogFormat::Clf | LogFormat::Combined => {
+                let bytes = self
+                    .bytes
+                    .map_or_else(|| "-".to_string(), |b| b.to_string());
+                let mut line = format!(
+                    "{} - - [{}] \"{} {} {}\" {} {bytes}",
+                    self.client_ip,
+                    clf_time(self.timestamp),
+                    self.method,
+                    self.target,
+                    self.version,
+                    self.status,
+                );
+                if format == LogFormat::Combined {
+                    line.push_str(&format!(
+                        " \"{}\" \"{}\"",
+                        quoted(self.referer),
+                        quoted(self.user_agent)
+                    ));
+                }
+                line
+            }
+        }
+    }
+}
+
+/// Log lines sent to syslog as `dslf[pid]: LINE` records, with `--log-syslog`
+#[derive(Clone)]
+pub struct Syslog(Arc<Mutex<Logger<Box<dyn Write + Send>, Formatter3164>>>);
+
+impl Syslog {
+    /// Connects to the local syslog socket (`/dev/log` or the platform's)
+    pub fn local(facility: Facility) -> Result<Self, syslog::Error> {
+        let logger = syslog::unix(Formatter3164::default())?;
+        Ok(Self::new(Box::new(logger.backend), facility))
+    }
+
+    /// Writes records to `backend`, one `write_fmt` call per record
+    pub fn new(backend: Box<dyn Write + Send>, facility: Facility) -> Self {
+        let formatter = Formatter3164 {
+            facility,
+            // syslogd stamps records from the local socket with its own host
+            hostname: None,
+            process: "dslf".to_string(),
+            pid: std::process::id(),
+        };
+        Self(Arc::new(Mutex::new(Logger::new(backend, formatter))))
+    }
+
+    pub fn send(&self, line: &str) {
+        let mut logger = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        // Like stdout, a lost line doesn't fail the request
+        let _ = logger.info(line);
+    }
+}
+
+impl fmt::Debug for Syslog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Syslog")
+    }
+}
+
+/// A header value for a quoted log field, `-` when missing
+fn quoted(value: Option<&str>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.replace('"', "\\\""))
+}
+
+/// Formats unix time as `10/Oct/2000:13:55:36 +0000` (always UTC)
+fn clf_time(timestamp: u64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = timestamp / 86_400;
+    let secs = timestamp % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[(month - 1) as usize],
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> LogEntry<'static> {
+        LogEntry {
+            timestamp: 971_186_136,
+            client_ip: "203.0.113.9",
+            method: "GET",
+            target: "/gh?ref=a",
+            version: "HTTP/1.1",
+            status: 301,
+            bytes: Some(0),
+            referer: Some("https://example.com/"),
+            user_agent: Some("curl/8.0 \"test\""),
+            duration: Duration::from_micros(1500),
+            shard: None,
+            note: None,
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn test_clf_and_combined() {
+        assert_eq!(
+            entry().format(LogFormat::Clf),
+            r#"203.0.113.9 - - [10/Oct/2000:13:55:36 +0000] "GET /gh?ref=a HTTP/1.1" 301 0"#
+        );
+        assert_eq!(
+            entry().format(LogFormat::Combined),
+            r#"203.0.113.9 - - [10/Oct/2000:13:55:36 +0000] "GET /gh?ref=a HTTP/1.1" 301 0 "https://example.com/" "curl/8.0 \"test\"""#
+        );
+
+        let bare = LogEntry {
+            bytes: None,
+            referer: None,
+            user_agent: None,
+            ..entry()
+        };
+        assert!(
+            bare.format(LogFormat::Combined)
+                .ends_with(r#"301 - "-" "-""#)
+        );
+        assert_eq!(clf_time(951_782_400), "29/Feb/2000:00:00:00 +0000");
+    }
+
+    #[test]
+    fn test_text_and_json() {
+        let entry = LogEntry {
+            shard: Some("1234abcd".to_string()),
+            request_id: Some("req-1"),
+            ..entry()
+        };
+        assert_eq!(
+            entry.format(LogFormat::Text),
+            "971186136 203.0.113.9 GET /gh?ref=a 301 1.50ms shard=1234abcd request_id=req-1"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&entry.format(LogFormat::Json)).unwrap();
+        assert_eq!(json["path"], "/gh?ref=a");
+        assert_eq!(json["status"], 301);
+        assert_eq!(json["referer"], "https://example.com/");
+        assert_eq!(json["note"], serde_json::Value::Null);
+        assert_eq!(json["request_id"], "req-1");
+
+        let retired = LogEntry {
+            status: 410,
+            note: Some("expired \"spring\" campaign"),
+            ..super::tests::entry()
+        };
+        assert!(
+            retired
+                .format(LogFormat::Text)
+                .ends_with(r#"410 1.50ms note="expired \"spring\" campaign""#)
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use regex::RegexSet;
+use std::error::Error;
+use std::time::Duration;
+
+/// Paths answered with 403 before any rule matching, e.g. scanner probes for
+/// `/wp-admin*` or `/.env`
+#[derive(Debug)]
+pub struct Blocklist {
+    patterns: RegexSet,
+    /// Wait this long before answering, to slow scanners down
+    pub delay: Option<Duration>,
+}
+
+impl Blocklist {
+    /// Parses one glob per line; blank lines and `#` comments are skipped.
+    /// `*` matches any run of characters (including `/`) and `?` a single one.
+    pub fn parse(content: &str, delay: Option<Duration>) -> Result<Self, Box<dyn Error>> {
+        let patterns: Vec<String> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(glob_to_regex)
+            .collect();
+
+        Ok(Self {
+            patterns: RegexSet::new(patterns)?,
+            delay,
+        })
+    }
+
+    pub fn is_blocked(&self, path: &str) -> bool {
+        self.patterns.is_match(path)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist_globs() {
+        let blocklist = Blocklist::parse(
+            "# scanners\n/wp-admin*\n\n/.env\n/*.php\n/backup-????.zip\n",
+            None,
+        )
+        .unwrap();
+
+        assert!(blocklist.is_blocked("/wp-admin"));
+        assert!(blocklist.is_blocked("/wp-admin/setup.php"));
+        assert!(blocklist.is_blocked("/.env"));
+        assert!(blocklist.is_blocked("/deep/path/x.php"));
+        assert!(blocklist.is_blocked("/backup-2024.zip"));
+
+        assert!(!blocklist.is_blocked("/.envrc"));
+        assert!(!blocklist.is_blocked("/xenv"));
+        assert!(!blocklist.is_blocked("/backup-24.zip"));
+        assert!(!blocklist.is_blocked("/gh"));
+    }
+}
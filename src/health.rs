@@ -0,0 +1,123 @@
+use crate::matcher::Matcher;
+use crate::validate::{Probe, check_target};
+use arc_swap::ArcSwap;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Primary targets that failed their latest health check
+#[derive(Debug, Default)]
+pub struct Health {
+    down: RwLock<HashSet<String>>,
+}
+
+impl Health {
+    pub fn is_down(&self, target: &str) -> bool {
+        let down = self.down.read().unwrap_or_else(|e| e.into_inner());
+        down.contains(target)
+    }
+
+    /// Records a check result, returning whether the target's state changed
+    pub fn set_healthy(&self, target: &str, healthy: bool) -> bool {
+        let mut down = self.down.write().unwrap_or_else(|e| e.into_inner());
+        if healthy {
+            down.remove(target)
+        } else {
+            down.insert(target.to_string())
+        }
+    }
+}
+
+/// Primary targets of rules that have a fallback, the only ones worth checking
+pub fn monitored_targets(rules: &Matcher) -> BTreeSet<String> {
+    rules
+        .entries()
+        .into_iter()
+        .filter(|(_, _, rule)| rule.fallback.is_some())
+        .map(|(_, _, rule)| rule.target.clone())
+        .collect()
+}
+
+/// Checks every monitored target once, logging targets that go down or recover
+pub async fn check_all(health: &Health, rules: &Matcher, client: &impl Probe) {
+    for target in monitored_targets(rules) {
+        let (_, error) = check_target(client, &target, &[]).await;
+        let healthy = error.is_none();
+        if health.set_healthy(&target, healthy) {
+            match error {
+                Some(e) => println!("Health check: {target} is down ({e}), using fallback"),
+                None => println!("Health check: {target} recovered"),
+            }
+        }
+    }
+}
+
+/// Re-checks the current rules every `interval` until the process exits
+pub async fn run(rules: Arc<ArcSwap<Matcher>>, health: Arc<Health>, interval: Duration) {
+    let client = reqwest::Client::builder()
+        .timeout(interval)
+        .build()
+        .unwrap_or_default();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        check_all(&health, &rules.load(), &client).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{ParsedRule, Rule, build_matcher};
+    use axum::http::StatusCode;
+
+    /// Reports every URL containing "down" as unreachable
+    struct MockClient;
+
+    impl Probe for MockClient {
+        async fn head(&self, url: &str) -> Result<StatusCode, String> {
+            if url.contains("down") {
+                Ok(StatusCode::SERVICE_UNAVAILABLE)
+            } else {
+                Ok(StatusCode::OK)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_all() {
+        let with_fallback = |url: &str, target: &str| ParsedRule {
+            url: url.to_string(),
+            rule: Rule {
+                fallback: Some("https://backup.example.com".to_string()),
+                ..Rule::new(target, 302)
+            },
+        };
+        let rules = build_matcher(vec![
+            with_fallback("/up", "https://up.example.com"),
+            with_fallback("/down", "https://down.example.com"),
+            ParsedRule {
+                url: "/plain".to_string(),
+                rule: Rule::new("https://down.example.org", 301),
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            monitored_targets(&rules).into_iter().collect::<Vec<_>>(),
+            vec!["https://down.example.com", "https://up.example.com"]
+        );
+
+        let health = Health::default();
+        check_all(&health, &rules, &MockClient).await;
+        assert!(health.is_down("https://down.example.com"));
+        assert!(!health.is_down("https://up.example.com"));
+        // Rules without a fallback aren't checked
+        assert!(!health.is_down("https://down.example.org"));
+
+        assert!(health.set_healthy("https://down.example.com", true));
+        assert!(!health.set_healthy("https://down.example.com", true));
+        assert!(!health.is_down("https://down.example.com"));
+    }
+}
@@ -1,24 +1,23 @@
+use clap::ValueEnum;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::error::Error;
 use std::fs::File;
+use std::io::Write;
 
 #[derive(Debug, Deserialize)]
 struct RebrandlyLink {
     id: String,
-    #[allow(dead_code)]
     title: Option<String>,
     slashtag: String,
     destination: String,
-    #[allow(dead_code)]
     #[serde(rename = "createdAt")]
     created_at: String,
     #[allow(dead_code)]
     #[serde(rename = "updatedAt")]
     updated_at: String,
-    #[allow(dead_code)]
     #[serde(rename = "shortUrl")]
     short_url: String,
     #[allow(dead_code)]
@@ -35,37 +34,474 @@ struct RebrandlyDomain {
     full_name: String,
 }
 
-#[derive(Debug, Serialize)]
-struct DslfRedirect {
-    url: String,
-    target: String,
+#[derive(Debug, Clone, Serialize)]
+pub struct DslfRedirect {
+    pub url: String,
+    pub target: String,
+    pub status: u16,
+    /// Written as extra columns, see [`ImportOptions::keep_metadata`]
+    #[serde(skip)]
+    pub metadata: Option<LinkMetadata>,
+}
+
+/// What the provider knew about a link besides its slug and target
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkMetadata {
+    pub title: String,
+    pub created_at: String,
+    pub short_url: String,
+}
+
+/// A [`DslfRedirect`] with its metadata columns
+#[derive(Serialize)]
+struct MetadataRow<'a> {
+    url: &'a str,
+    target: &'a str,
     status: u16,
+    title: &'a str,
+    created_at: &'a str,
+    short_url: &'a str,
+}
+
+impl<'a> From<&'a DslfRedirect> for MetadataRow<'a> {
+    fn from(redirect: &'a DslfRedirect) -> Self {
+        let metadata = redirect.metadata.as_ref();
+        let text = |get: fn(&LinkMetadata) -> &str| metadata.map_or("", get);
+        Self {
+            url: &redirect.url,
+            target: &redirect.target,
+            status: redirect.status,
+            title: text(|m| &m.title),
+            created_at: text(|m| &m.created_at),
+            short_url: text(|m| &m.short_url),
+        }
+    }
+}
+
+/// File format written by the importer
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One `{"url","target","status"}` JSON object per line
+    Jsonl,
+}
+
+/// Output order, so re-running an import gives a stable diff
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum SortOrder {
+    /// By slug
+    #[default]
+    Slug,
+    /// By target, then slug
+    Target,
+    /// As fetched from the provider
+    None,
+}
+
+/// Sorts redirects in place; `None` keeps the current order
+pub fn sort_redirects(redirects: &mut [DslfRedirect], order: SortOrder) {
+    match order {
+        SortOrder::Slug => redirects.sort_by(|a, b| a.url.cmp(&b.url)),
+        SortOrder::Target => {
+            redirects.sort_by(|a, b| a.target.cmp(&b.target).then_with(|| a.url.cmp(&b.url)));
+        }
+        SortOrder::None => {}
+    }
+}
+
+/// Writes redirects in the given format (CSV includes a header row). If any
+/// redirect carries metadata, every row gets the `title`, `created_at` and
+/// `short_url` columns.
+pub fn write_redirects(
+    writer: impl Write,
+    redirects: &[DslfRedirect],
+    format: ExportFormat,
+) -> Result<(), Box<dyn Error>> {
+    if redirects.iter().any(|r| r.metadata.is_some()) {
+        let rows: Vec<MetadataRow> = redirects.iter().map(MetadataRow::from).collect();
+        write_rows(writer, &rows, format)
+    } else {
+        write_rows(writer, redirects, format)
+    }
+}
+
+fn write_rows(
+    writer: impl Write,
+    redirects: &[impl Serialize],
+    format: ExportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            // Headers are automatically written by csv crate on first serialize
+            for redirect in redirects {
+                writer.serialize(redirect)?;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Jsonl => {
+            let mut writer = std::io::BufWriter::new(writer);
+            for redirect in redirects {
+                serde_json::to_writer(&mut writer, redirect)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Options shared by all import providers
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    pub format: ExportFormat,
+    /// Keep only the shortest slug for each destination
+    pub dedupe_targets: bool,
+    /// Where the `url` provider fetches from
+    pub source: Option<String>,
+    /// How the `url` provider reads each record
+    pub field_map: FieldMap,
+    /// Also export the provider's title, creation time and short URL
+    pub keep_metadata: bool,
+    pub sort: SortOrder,
+    /// Print an [`ImportSummary`] as JSON on stdout, and progress on stderr
+    pub summary_json: bool,
+    /// Rebrandly API root instead of [`REBRANDLY_API_BASE`]
+    pub api_base: Option<String>,
+}
+
+impl ImportOptions {
+    /// Prints a progress line, kept off stdout with `summary_json`
+    fn say(&self, message: &str) {
+        if self.summary_json {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+    }
+}
+
+/// What an import exported, for `--summary-json`
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ImportSummary {
+    /// Redirects written
+    pub total: usize,
+    /// Links per short domain, for providers that have them. Counted before
+    /// `--dedupe-targets`, so they add up to `total + deduped`.
+    pub by_domain: BTreeMap<String, usize>,
+    /// Links left out because the provider reports them inactive
+    pub skipped: usize,
+    /// Slugs dropped by `--dedupe-targets`
+    pub deduped: usize,
+}
+
+/// Dedupes, sorts and writes `redirects`, then reports the result
+fn finish_import(
+    mut redirects: Vec<DslfRedirect>,
+    output_file: &str,
+    options: &ImportOptions,
+    mut summary: ImportSummary,
+) -> Result<ImportSummary, Box<dyn Error>> {
+    if options.dedupe_targets {
+        summary.deduped = dedupe_targets(&mut redirects);
+        options.say(&format!(
+            "Removed {} slugs with duplicate targets",
+            summary.deduped
+        ));
+    }
+    sort_redirects(&mut redirects, options.sort);
+
+    let file = File::create(output_file)?;
+    write_redirects(file, &redirects, options.format)?;
+    summary.total = redirects.len();
+
+    if options.summary_json {
+        println!("{}", serde_json::to_string(&summary)?);
+        return Ok(summary);
+    }
+    println!(
+        "✅ Successfully exported {} redirects to {output_file}",
+        redirects.len()
+    );
+    if !summary.by_domain.is_empty() {
+        println!("\nDomains summary:");
+        for (domain, count) in &summary.by_domain {
+            println!("  - {domain}: {count} links");
+        }
+    }
+    Ok(summary)
+}
+
+/// Which record fields hold the slug, target and (optionally) status for the
+/// `url` provider. Nested fields are written with dots, e.g. `link.href`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldMap {
+    pub slug: String,
+    pub target: String,
+    pub status: Option<String>,
+}
+
+impl Default for FieldMap {
+    fn default() -> Self {
+        Self {
+            slug: "url".to_string(),
+            target: "target".to_string(),
+            status: Some("status".to_string()),
+        }
+    }
 }
 
-pub async fn import_from_rebrandly(output_file: &str) -> Result<(), Box<dyn Error>> {
-    let api_key = env::var("REBRANDLY_API_KEY")
-        .or_else(|_| env::var("REBRANDLY_TOKEN"))
-        .map_err(|_| "REBRANDLY_API_KEY or REBRANDLY_TOKEN environment variable not set")?;
+impl FieldMap {
+    /// Parses `slug=key,target=longUrl[,status=code]`; unnamed fields keep
+    /// their defaults
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, field) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected NAME=FIELD, got {pair}"))?;
+            let field = field.trim().to_string();
+            match name.trim() {
+                "slug" => map.slug = field,
+                "target" => map.target = field,
+                "status" => map.status = Some(field),
+                other => {
+                    return Err(format!(
+                        "unknown mapping {other}, expected slug, target or status"
+                    ));
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// Looks up a dotted field path in a JSON record
+fn field<'a>(record: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .try_fold(record, |value, key| value.get(key))
+}
+
+/// Turns fetched records into redirects. Slugs get a leading `/` if missing,
+/// and records without a status field default to 301. Statuses other than
+/// 301, 302 and 410 are an error.
+pub fn map_records(
+    records: &[serde_json::Value],
+    map: &FieldMap,
+) -> Result<Vec<DslfRedirect>, Box<dyn Error>> {
+    let text = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    };
+
+    records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| {
+            let get = |name: &str| {
+                field(record, name)
+                    .and_then(text)
+                    .ok_or_else(|| format!("record {} has no \"{name}\" field", index + 1))
+            };
+            let slug = get(&map.slug)?;
+            let status = match map.status.as_deref().and_then(|name| field(record, name)) {
+                Some(value) => text(value)
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| format!("record {} has an invalid status", index + 1))?,
+                None => 301,
+            };
+            // Anything else would be rejected when the CSV is loaded
+            if !matches!(status, 301 | 302 | 410) {
+                return Err(format!(
+                    "record {} has status {status}, expected 301, 302 or 410",
+                    index + 1
+                )
+                .into());
+            }
+
+            Ok(DslfRedirect {
+                url: if slug.starts_with('/') {
+                    slug
+                } else {
+                    format!("/{slug}")
+                },
+                target: get(&map.target)?,
+                status,
+                metadata: None,
+            })
+        })
+        .collect()
+}
+
+/// Reads a JSON array of objects, or CSV with a header row
+pub fn parse_records(body: &str) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+    if body.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(body)?);
+    }
 
+    let mut reader = csv::Reader::from_reader(body.as_bytes());
+    let headers = reader.headers()?.clone();
+    reader
+        .records()
+        .map(|record| {
+            let record = record?;
+            let object = headers
+                .iter()
+                .zip(record.iter())
+                .map(|(key, value)| (key.to_string(), value.into()))
+                .collect();
+            Ok(serde_json::Value::Object(object))
+        })
+        .collect()
+}
+
+/// Fetches links from any URL serving a JSON array or CSV and maps them with
+/// [`FieldMap`], for providers without native support
+pub async fn import_from_url(
+    output_file: &str,
+    options: ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let source = options.source.as_deref().ok_or(
+        "The url provider needs a source URL, e.g. dslf import url https://example.com/links.json",
+    )?;
+
+    options.say(&format!("Fetching links from {source}..."));
+
+    let response = Client::new().get(source).send().await?;
+    if !response.status().is_success() {
+        return Err(format!("{source} answered {}", response.status()).into());
+    }
+
+    let records = parse_records(&response.text().await?)?;
+    let redirects = map_records(&records, &options.field_map)?;
+    finish_import(redirects, output_file, &options, ImportSummary::default())?;
+    Ok(())
+}
+
+/// Parses a legacy `slug target` list, one pair per line separated by spaces
+/// or tabs. Blank lines and `#` comments are skipped, slugs get a leading `/`
+/// if missing and every redirect is a 301.
+pub fn parse_text_list(body: &str) -> Result<Vec<DslfRedirect>, Box<dyn Error>> {
+    let mut redirects = Vec::new();
+    for (index, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [slug, target] = fields[..] else {
+            return Err(format!("line {} should be `slug target`, got {line}", index + 1).into());
+        };
+        redirects.push(DslfRedirect {
+            url: if slug.starts_with('/') {
+                slug.to_string()
+            } else {
+                format!("/{slug}")
+            },
+            target: target.to_string(),
+            status: 301,
+            metadata: None,
+        });
+    }
+    Ok(redirects)
+}
+
+/// Reads a local `slug target` list, see [`parse_text_list`]
+pub async fn import_from_text(
+    output_file: &str,
+    options: ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let source = options
+        .source
+        .as_deref()
+        .ok_or("The txt provider needs a file, e.g. dslf import txt links.txt")?;
+
+    let body = tokio::fs::read_to_string(source)
+        .await
+        .map_err(|e| format!("Failed to read {source}: {e}"))?;
+    let redirects = parse_text_list(&body)?;
+    finish_import(redirects, output_file, &options, ImportSummary::default())?;
+    Ok(())
+}
+
+/// Drops slugs whose target is already covered, keeping the shortest slug per
+/// target (the first one on ties) in the original order. Returns the number removed.
+pub fn dedupe_targets(redirects: &mut Vec<DslfRedirect>) -> usize {
+    let mut shortest: HashMap<&str, usize> = HashMap::new();
+    for (index, redirect) in redirects.iter().enumerate() {
+        shortest
+            .entry(redirect.target.as_str())
+            .and_modify(|best| {
+                if redirect.url.len() < redirects[*best].url.len() {
+                    *best = index;
+                }
+            })
+            .or_insert(index);
+    }
+
+    let keep: HashSet<usize> = shortest.into_values().collect();
+    let before = redirects.len();
+    let mut index = 0;
+    redirects.retain(|_| {
+        index += 1;
+        keep.contains(&(index - 1))
+    });
+
+    before - redirects.len()
+}
+
+pub const REBRANDLY_API_BASE: &str = "https://api.rebrandly.com/v1";
+
+pub async fn import_from_rebrandly(
+    output_file: &str,
+    options: ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let api_key = rebrandly_api_key(|name| env::var(name).ok())?;
+    import_rebrandly_links(output_file, options, &api_key).await
+}
+
+/// `REBRANDLY_API_KEY`, or `REBRANDLY_TOKEN`, looked up with `var`
+fn rebrandly_api_key(var: impl Fn(&str) -> Option<String>) -> Result<String, &'static str> {
+    var("REBRANDLY_API_KEY")
+        .or_else(|| var("REBRANDLY_TOKEN"))
+        .filter(|key| !key.is_empty())
+        .ok_or("REBRANDLY_API_KEY or REBRANDLY_TOKEN environment variable not set")
+}
+
+/// Pages through `GET {api_base}/links` and exports the active links
+async fn import_rebrandly_links(
+    output_file: &str,
+    options: ImportOptions,
+    api_key: &str,
+) -> Result<(), Box<dyn Error>> {
+    let api_base = options
+        .api_base
+        .as_deref()
+        .unwrap_or(REBRANDLY_API_BASE)
+        .trim_end_matches('/');
     let client = Client::new();
     let mut all_links = Vec::new();
     let mut last_id: Option<String> = None;
     let limit = 25; // Maximum allowed by Rebrandly API
 
-    println!("Fetching links from Rebrandly...");
+    options.say("Fetching links from Rebrandly...");
 
     loop {
-        let mut url = format!("https://api.rebrandly.com/v1/links?limit={limit}");
+        let mut url = format!("{api_base}/links?limit={limit}");
 
         if let Some(last) = &last_id {
             url.push_str(&format!("&last={last}"));
         }
 
-        println!("Fetching batch (last ID: {last_id:?})");
+        options.say(&format!("Fetching batch (last ID: {last_id:?})"));
 
         let response = client
             .get(&url)
-            .header("apikey", &api_key)
+            .header("apikey", api_key)
             .header("Content-Type", "application/json")
             .send()
             .await?;
@@ -82,11 +518,11 @@ pub async fn import_from_rebrandly(output_file: &str) -> Result<(), Box<dyn Erro
         let links: Vec<RebrandlyLink> = response.json().await?;
 
         if links.is_empty() {
-            println!("No more links to fetch.");
+            options.say("No more links to fetch.");
             break;
         }
 
-        println!("Fetched {} links in this batch", links.len());
+        options.say(&format!("Fetched {} links in this batch", links.len()));
 
         // Store the last ID for pagination
         if let Some(last_link) = links.last() {
@@ -102,71 +538,133 @@ pub async fn import_from_rebrandly(output_file: &str) -> Result<(), Box<dyn Erro
         }
     }
 
-    println!("Total links fetched: {}", all_links.len());
+    options.say(&format!("Total links fetched: {}", all_links.len()));
 
     if all_links.is_empty() {
-        println!("No links found to export.");
+        options.say("No links found to export.");
+        if options.summary_json {
+            println!("{}", serde_json::to_string(&ImportSummary::default())?);
+        }
         return Ok(());
     }
 
-    // Convert to DSLF format
+    let (redirects, summary) = convert_rebrandly_links(all_links, options.keep_metadata);
+    finish_import(redirects, output_file, &options, summary)?;
+    Ok(())
+}
+
+/// Converts the active links, counting them per domain and the inactive ones
+/// as skipped. The summary's `total` is filled in once they are written.
+fn convert_rebrandly_links(
+    links: Vec<RebrandlyLink>,
+    keep_metadata: bool,
+) -> (Vec<DslfRedirect>, ImportSummary) {
     let mut redirects = Vec::new();
-    let mut domain_counts: HashMap<String, usize> = HashMap::new();
+    let mut summary = ImportSummary::default();
 
-    for link in all_links {
-        // Skip inactive links
-        if let Some(status) = &link.status
-            && status != "active"
+    for link in links {
+        if link
+            .status
+            .as_deref()
+            .is_some_and(|status| status != "active")
         {
+            summary.skipped += 1;
             continue;
         }
+        *summary
+            .by_domain
+            .entry(link.domain.full_name.clone())
+            .or_default() += 1;
+        redirects.push(rebrandly_redirect(link, keep_metadata));
+    }
 
-        // Create the short URL path
-        let url_path = if link.slashtag.starts_with('/') {
-            link.slashtag
-        } else {
-            format!("/{}", link.slashtag)
-        };
-
-        // Convert to DSLF redirect
-        let redirect = DslfRedirect {
-            url: url_path,
-            target: link.destination,
-            status: 301, // Default to permanent redirect
-        };
+    (redirects, summary)
+}
 
-        redirects.push(redirect);
+/// Converts a Rebrandly link, with its metadata when `keep_metadata` is set
+fn rebrandly_redirect(link: RebrandlyLink, keep_metadata: bool) -> DslfRedirect {
+    // Create the short URL path
+    let url_path = if link.slashtag.starts_with('/') {
+        link.slashtag
+    } else {
+        format!("/{}", link.slashtag)
+    };
 
-        // Count domains for summary
-        *domain_counts.entry(link.domain.full_name).or_insert(0) += 1;
+    DslfRedirect {
+        url: url_path,
+        target: link.destination,
+        status: 301, // Default to permanent redirect
+        metadata: keep_metadata.then(|| LinkMetadata {
+            title: link.title.unwrap_or_default(),
+            created_at: link.created_at,
+            short_url: link.short_url,
+        }),
     }
+}
 
-    // Write to CSV
-    let mut file = File::create(output_file)?;
-    let mut writer = csv::Writer::from_writer(&mut file);
-
-    // Write redirects (headers are automatically written by csv crate on first serialize)
-    for redirect in &redirects {
-        writer.serialize(redirect)?;
-    }
+/// Checks what [`import_links`] would need, without any network calls: the
+/// provider is known, its credentials (looked up with `var`) and source are
+/// set, and the output directory exists. Returns a one-line description.
+pub fn check_import(
+    provider: &str,
+    output_file: &str,
+    options: &ImportOptions,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<String, Box<dyn Error>> {
+    let http_url = |url: &str, what: &str| match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        _ => Err(format!("{what} must be an http(s) URL, got {url}")),
+    };
 
-    writer.flush()?;
+    let source = match provider {
+        "rebrandly" => {
+            rebrandly_api_key(var)?;
+            let api_base = options.api_base.as_deref().unwrap_or(REBRANDLY_API_BASE);
+            http_url(api_base, "The API base")?;
+            api_base.to_string()
+        }
+        "url" => {
+            let source = options
+                .source
+                .as_deref()
+                .ok_or("The url provider needs a source URL")?;
+            http_url(source, "The source")?;
+            source.to_string()
+        }
+        "txt" => {
+            let source = options
+                .source
+                .as_deref()
+                .ok_or("The txt provider needs a file")?;
+            std::fs::metadata(source).map_err(|e| format!("Can't read {source}: {e}"))?;
+            source.to_string()
+        }
+        _ => return Err(format!("Unsupported import provider: {provider}").into()),
+    };
 
-    println!(
-        "✅ Successfully exported {} redirects to {output_file}",
-        redirects.len()
-    );
-    println!("\nDomains summary:");
-    for (domain, count) in domain_counts {
-        println!("  - {domain}: {count} links");
+    let output_dir = std::path::Path::new(output_file)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = output_dir
+        && !dir.is_dir()
+    {
+        return Err(format!("Output directory {} doesn't exist", dir.display()).into());
     }
 
-    Ok(())
+    Ok(format!(
+        "Import from {provider} ({source}) to {output_file} looks good"
+    ))
 }
 
-pub async fn import_links(provider: &str, output_file: &str) -> Result<(), Box<dyn Error>> {
+pub async fn import_links(
+    provider: &str,
+    output_file: &str,
+    options: ImportOptions,
+) -> Result<(), Box<dyn Error>> {
     match provider {
-        "rebrandly" => import_from_rebrandly(output_file).await,
+        "rebrandly" => import_from_rebrandly(output_file, options).await,
+        "url" => import_from_url(output_file, options).await,
+        "txt" => import_from_text(output_file, options).await,
         _ => Err(format!("Unsupported import provider: {provider}").into()),
     }
 }
@@ -183,6 +681,7 @@ mod tests {
             url: "/test".to_string(),
             target: "https://example.com".to_string(),
             status: 301,
+            metadata: None,
         };
 
         let mut buffer = Vec::new();
@@ -234,6 +733,7 @@ mod tests {
         let result = rt.block_on(import_links(
             "unsupported",
             temp_file.path().to_str().unwrap(),
+            ImportOptions::default(),
         ));
         assert!(result.is_err());
         assert!(
@@ -244,6 +744,167 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_import_summary_json() {
+        let link = |slashtag: &str, domain: &str, status: Option<&str>| {
+            serde_json::from_value::<RebrandlyLink>(serde_json::json!({
+                "id": slashtag,
+                "title": null,
+                "slashtag": slashtag,
+                "destination": format!("https://example.com/{slashtag}"),
+                "createdAt": "2023-01-01T00:00:00.000Z",
+                "updatedAt": "2023-01-01T00:00:00.000Z",
+                "shortUrl": format!("{domain}/{slashtag}"),
+                "domain": { "id": domain, "fullName": domain },
+                "status": status,
+            }))
+            .unwrap()
+        };
+        let links = || {
+            vec![
+                link("a", "rebrand.ly", Some("active")),
+                link("b", "go.example.com", Some("active")),
+                link("c", "rebrand.ly", None),
+                link("d", "rebrand.ly", Some("deleted")),
+                link("e", "go.example.com", Some("trashed")),
+            ]
+        };
+
+        let (redirects, summary) = convert_rebrandly_links(links(), false);
+        assert_eq!(redirects.len(), 3);
+        let output = NamedTempFile::new().unwrap();
+        let options = ImportOptions {
+            summary_json: true,
+            ..Default::default()
+        };
+        let summary = finish_import(
+            redirects,
+            output.path().to_str().unwrap(),
+            &options,
+            summary,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&summary).unwrap(),
+            serde_json::json!({
+                "total": 3,
+                "by_domain": { "go.example.com": 1, "rebrand.ly": 2 },
+                "skipped": 2,
+                "deduped": 0,
+            })
+        );
+
+        // Slugs dropped by --dedupe-targets are counted, so the numbers add up
+        let (mut redirects, summary) = convert_rebrandly_links(links(), false);
+        redirects[2].target = redirects[0].target.clone();
+        let options = ImportOptions {
+            dedupe_targets: true,
+            ..options
+        };
+        let summary = finish_import(
+            redirects,
+            output.path().to_str().unwrap(),
+            &options,
+            summary,
+        )
+        .unwrap();
+        assert_eq!((summary.total, summary.deduped), (2, 1));
+        assert_eq!(
+            summary.by_domain.values().sum::<usize>(),
+            summary.total + summary.deduped
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_from_rebrandly_api_base() {
+        use axum::{Json, Router, http::HeaderMap, routing::get};
+
+        let links = |headers: HeaderMap| async move {
+            assert_eq!(headers["apikey"], "test-key");
+            Json(serde_json::json!([{
+                "id": "1",
+                "title": "Docs",
+                "slashtag": "docs",
+                "destination": "https://example.com/docs",
+                "createdAt": "2023-01-01T00:00:00.000Z",
+                "updatedAt": "2023-01-01T00:00:00.000Z",
+                "shortUrl": "go.example.com/docs",
+                "domain": { "id": "d1", "fullName": "go.example.com" },
+                "status": "active",
+            }]))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/v1/links", get(links));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let output = NamedTempFile::new().unwrap();
+        let options = ImportOptions {
+            api_base: Some(format!("http://{addr}/v1/")),
+            ..Default::default()
+        };
+        import_rebrandly_links(output.path().to_str().unwrap(), options, "test-key")
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output.path()).unwrap(),
+            "url,target,status\n/docs,https://example.com/docs,301\n"
+        );
+    }
+
+    #[test]
+    fn test_check_import() {
+        let with_key = |name: &str| (name == "REBRANDLY_TOKEN").then(|| "token".to_string());
+        let no_key = |_: &str| None;
+        let options = ImportOptions::default();
+
+        let report = check_import("rebrandly", "redirects.csv", &options, with_key).unwrap();
+        assert!(report.contains(REBRANDLY_API_BASE), "{report}");
+        assert_eq!(
+            check_import("rebrandly", "redirects.csv", &options, no_key)
+                .unwrap_err()
+                .to_string(),
+            "REBRANDLY_API_KEY or REBRANDLY_TOKEN environment variable not set"
+        );
+
+        let bad_base = ImportOptions {
+            api_base: Some("api.example.com".to_string()),
+            ..Default::default()
+        };
+        assert!(check_import("rebrandly", "out.csv", &bad_base, with_key).is_err());
+
+        let url = |source: Option<&str>| ImportOptions {
+            source: source.map(str::to_string),
+            ..Default::default()
+        };
+        assert!(
+            check_import(
+                "url",
+                "out.csv",
+                &url(Some("https://example.com/a.json")),
+                no_key
+            )
+            .is_ok()
+        );
+        assert!(check_import("url", "out.csv", &url(None), no_key).is_err());
+        assert!(check_import("url", "out.csv", &url(Some("links.json")), no_key).is_err());
+
+        let list = NamedTempFile::new().unwrap();
+        let txt = url(list.path().to_str());
+        assert!(check_import("txt", "out.csv", &txt, no_key).is_ok());
+        assert!(
+            check_import(
+                "txt",
+                "out.csv",
+                &url(Some("/nonexistent/links.txt")),
+                no_key
+            )
+            .is_err()
+        );
+        assert!(check_import("txt", "/nonexistent/out.csv", &txt, no_key).is_err());
+        assert!(check_import("bitly", "out.csv", &options, with_key).is_err());
+    }
+
     #[test]
     fn test_rebrandly_link_with_complex_urls() {
         // Test URLs with UTM parameters, query strings, fragments, etc.
@@ -288,16 +949,19 @@ mod tests {
                 url: "/utm-test".to_string(),
                 target: "https://example.com/products?utm_source=newsletter&utm_medium=email&utm_campaign=spring_sale&ref=homepage#pricing".to_string(),
                 status: 301,
+                metadata: None,
             },
             DslfRedirect {
                 url: "/special-chars".to_string(),
                 target: "https://example.com/search?q=hello%20world&category=electronics&price=100-500&features=wifi,bluetooth".to_string(),
                 status: 302,
+                metadata: None,
             },
             DslfRedirect {
                 url: "/encoded-url".to_string(),
                 target: "https://example.com/redirect?url=https%3A%2F%2Fother-site.com%2Fpath%3Fparam%3Dvalue".to_string(),
                 status: 301,
+                metadata: None,
             },
         ];
 
@@ -330,6 +994,282 @@ mod tests {
         assert_eq!(lines.len(), 4, "Should have 1 header + 3 data lines");
     }
 
+    #[test]
+    fn test_keep_metadata_columns() {
+        let link: RebrandlyLink = serde_json::from_str(
+            r#"{
+                "id": "abc",
+                "title": "Spring sale, 2024",
+                "slashtag": "sale",
+                "destination": "https://example.com/sale",
+                "createdAt": "2024-03-01T10:00:00.000Z",
+                "updatedAt": "2024-03-02T10:00:00.000Z",
+                "shortUrl": "rebrand.ly/sale",
+                "domain": { "id": "d1", "fullName": "rebrand.ly" }
+            }"#,
+        )
+        .unwrap();
+
+        let mut buffer = Vec::new();
+        let redirects = [rebrandly_redirect(link, true)];
+        write_redirects(&mut buffer, &redirects, ExportFormat::Csv).unwrap();
+        let mut reader = csv::Reader::from_reader(buffer.as_slice());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "url",
+                "target",
+                "status",
+                "title",
+                "created_at",
+                "short_url"
+            ]
+        );
+        let row = reader.records().next().unwrap().unwrap();
+        assert_eq!(
+            row,
+            vec![
+                "/sale",
+                "https://example.com/sale",
+                "301",
+                "Spring sale, 2024",
+                "2024-03-01T10:00:00.000Z",
+                "rebrand.ly/sale"
+            ]
+        );
+
+        // Without the flag the output keeps its three columns
+        let redirects = [DslfRedirect {
+            metadata: None,
+            ..redirects[0].clone()
+        }];
+        let mut buffer = Vec::new();
+        write_redirects(&mut buffer, &redirects, ExportFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "url,target,status\n/sale,https://example.com/sale,301\n"
+        );
+    }
+
+    #[test]
+    fn test_write_redirects_jsonl() {
+        let redirects = vec![
+            DslfRedirect {
+                url: "/gh".to_string(),
+                target: "https://github.com".to_string(),
+                status: 301,
+                metadata: None,
+            },
+            DslfRedirect {
+                url: "/promo".to_string(),
+                target: "https://example.com/offer?utm_source=a,b".to_string(),
+                status: 302,
+                metadata: None,
+            },
+        ];
+
+        let mut buffer = Vec::new();
+        write_redirects(&mut buffer, &redirects, ExportFormat::Jsonl).unwrap();
+
+        let output = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"url":"/gh","target":"https://github.com","status":301}"#,
+                r#"{"url":"/promo","target":"https://example.com/offer?utm_source=a,b","status":302}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_redirects_is_deterministic() {
+        let redirect = |url: &str, target: &str| DslfRedirect {
+            url: url.to_string(),
+            target: target.to_string(),
+            status: 301,
+            metadata: None,
+        };
+        let fetched = || {
+            vec![
+                redirect("/b", "https://example.com/1"),
+                redirect("/c", "https://example.com/0"),
+                redirect("/a", "https://example.com/1"),
+            ]
+        };
+        let csv = |mut redirects: Vec<DslfRedirect>, order| {
+            sort_redirects(&mut redirects, order);
+            let mut buffer = Vec::new();
+            write_redirects(&mut buffer, &redirects, ExportFormat::Csv).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+
+        // The provider returning the same links in another order
+        let mut reordered = fetched();
+        reordered.reverse();
+
+        let by_slug = csv(fetched(), SortOrder::Slug);
+        assert_eq!(by_slug, csv(reordered.clone(), SortOrder::Slug));
+        assert_eq!(
+            by_slug,
+            "url,target,status\n\
+             /a,https://example.com/1,301\n\
+             /b,https://example.com/1,301\n\
+             /c,https://example.com/0,301\n"
+        );
+
+        let by_target = csv(fetched(), SortOrder::Target);
+        assert_eq!(by_target, csv(reordered.clone(), SortOrder::Target));
+        assert!(by_target.starts_with("url,target,status\n/c,"));
+
+        assert_ne!(
+            csv(fetched(), SortOrder::None),
+            csv(reordered, SortOrder::None)
+        );
+    }
+
+    #[test]
+    fn test_dedupe_targets() {
+        let redirect = |url: &str, target: &str| DslfRedirect {
+            url: url.to_string(),
+            target: target.to_string(),
+            status: 301,
+            metadata: None,
+        };
+        let mut redirects = vec![
+            redirect("/github-profile", "https://github.com/user"),
+            redirect("/docs", "https://docs.example.com"),
+            redirect("/gh", "https://github.com/user"),
+            redirect("/git", "https://github.com/user"),
+            redirect("/d1", "https://example.com/d"),
+            redirect("/d2", "https://example.com/d"),
+        ];
+
+        let removed = dedupe_targets(&mut redirects);
+        assert_eq!(removed, 3);
+
+        let urls: Vec<&str> = redirects.iter().map(|r| r.url.as_str()).collect();
+        assert_eq!(urls, vec!["/docs", "/gh", "/d1"]);
+    }
+
+    #[test]
+    fn test_map_records_custom_fields() {
+        let records = parse_records(
+            r#"[
+                {"key": "gh", "longUrl": "https://github.com/user", "meta": {"code": 302}},
+                {"key": "/docs", "longUrl": "https://docs.example.com", "meta": {"code": "410"}},
+                {"key": 42, "longUrl": "https://example.com/42", "meta": {}}
+            ]"#,
+        )
+        .unwrap();
+        let map = FieldMap::parse("slug=key,target=longUrl,status=meta.code").unwrap();
+
+        let redirects = map_records(&records, &map).unwrap();
+        let redirects: Vec<_> = redirects
+            .iter()
+            .map(|r| (r.url.as_str(), r.target.as_str(), r.status))
+            .collect();
+        assert_eq!(
+            redirects,
+            vec![
+                ("/gh", "https://github.com/user", 302),
+                ("/docs", "https://docs.example.com", 410),
+                ("/42", "https://example.com/42", 301),
+            ]
+        );
+
+        let missing = map_records(&records, &FieldMap::parse("target=href").unwrap());
+        assert_eq!(
+            missing.unwrap_err().to_string(),
+            "record 1 has no \"url\" field"
+        );
+
+        // Statuses dslf can't serve are rejected up front
+        let records = parse_records(
+            r#"[
+                {"key": "gh", "longUrl": "https://github.com/user"},
+                {"key": "docs", "longUrl": "https://docs.example.com", "meta": {"code": "308"}}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            map_records(&records, &map).unwrap_err().to_string(),
+            "record 2 has status 308, expected 301, 302 or 410"
+        );
+    }
+
+    #[test]
+    fn test_parse_records_csv_and_field_map() {
+        let records = parse_records("code,href\nabc,https://example.com/abc\n").unwrap();
+        let redirects = map_records(
+            &records,
+            &FieldMap::parse("slug=code, target=href").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(redirects[0].url, "/abc");
+        assert_eq!(redirects[0].target, "https://example.com/abc");
+        assert_eq!(redirects[0].status, 301);
+
+        assert!(FieldMap::parse("slug").is_err());
+        assert!(FieldMap::parse("title=name").is_err());
+        assert_eq!(FieldMap::parse("").unwrap(), FieldMap::default());
+    }
+
+    #[test]
+    fn test_parse_text_list() {
+        let redirects = parse_text_list(
+            "# exported from the old shortener\n\
+             gh https://github.com/vpetersson\n\
+             \n\
+             /docs\thttps://example.com/docs?a=1\n\
+             \t# indented comment\n\
+             blog    https://example.com/blog\n",
+        )
+        .unwrap();
+        let pairs: Vec<(&str, &str, u16)> = redirects
+            .iter()
+            .map(|r| (r.url.as_str(), r.target.as_str(), r.status))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("/gh", "https://github.com/vpetersson", 301),
+                ("/docs", "https://example.com/docs?a=1", 301),
+                ("/blog", "https://example.com/blog", 301),
+            ]
+        );
+
+        for invalid in ["gh", "gh https://a.example https://b.example"] {
+            let error = parse_text_list(&format!("ok https://example.com\n{invalid}\n"))
+                .unwrap_err()
+                .to_string();
+            assert!(error.starts_with("line 2 "), "{error}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_from_text() {
+        let mut source = NamedTempFile::new().unwrap();
+        writeln!(source, "x https://example.com/x").unwrap();
+        writeln!(source, "a https://example.com/a").unwrap();
+        let output = NamedTempFile::new().unwrap();
+
+        import_links(
+            "txt",
+            output.path().to_str().unwrap(),
+            ImportOptions {
+                source: Some(source.path().to_str().unwrap().to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output.path()).unwrap(),
+            "url,target,status\n/a,https://example.com/a,301\n/x,https://example.com/x,301\n"
+        );
+    }
+
     #[test]
     fn test_slashtag_formatting() {
         // Test various slashtag formats
@@ -365,7 +1305,9 @@ mod tests {
         }
 
         let temp_file = NamedTempFile::new().unwrap();
-        let result = import_from_rebrandly(temp_file.path().to_str().unwrap()).await;
+        let result =
+            import_from_rebrandly(temp_file.path().to_str().unwrap(), ImportOptions::default())
+                .await;
 
         // Restore environment variables if they existed
         unsafe {
@@ -1,80 +1,456 @@
+use arc_swap::{ArcSwap, ArcSwapOption};
 use axum::{
     Router,
     body::Body,
     extract::Path,
-    http::{Request, StatusCode, header},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, header},
     middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
 };
-use clap::{Parser, Subcommand};
-use serde::Deserialize;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, parser::ValueSource};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
-    time::{Instant, SystemTime, UNIX_EPOCH},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
 use tokio::net::TcpListener;
 use tower::{ServiceExt, service_fn};
-use tower_http::services::ServeDir;
+use tower_http::{compression::CompressionLayer, services::ServeDir, timeout::TimeoutLayer};
 
+mod access_log;
+mod blocklist;
+mod health;
 mod import;
+mod matcher;
+mod schedule;
+mod stats;
+mod validate;
+mod webhook;
+
+use access_log::{LogEntry, LogFormat, Syslog};
+use blocklist::Blocklist;
+use health::Health;
+use matcher::{
+    MatchOptions, Matched, Matcher, ParsedRule, Rotation, Rule, RuleDiff, RuleKind, build_matcher,
+    find_ancestor, find_rule, host_matches, match_rule, parse_header_condition,
+};
+use schedule::Schedule;
+use stats::{Stats, StatsSnapshot};
+use validate::{
+    CacheFreshness, Probe, SystemResolver, ValidateOptions, ValidationCache, ValidationResult,
+    prewarm_dns, read_target_list, validate_rules, validate_targets, warmup,
+};
+use webhook::{RedirectEvent, Webhook};
+
+/// Shared request-time state. A reload builds a new [`Matcher`] and swaps it
+/// in atomically, so requests never lock or see a half-built ruleset.
+#[derive(Debug, Clone)]
+struct AppState {
+    rules: Arc<ArcSwap<Matcher>>,
+    options: Arc<AppOptions>,
+    stats: Arc<Stats>,
+    /// Where the rules came from, needed to reload them
+    config: Option<Arc<ConfigSource>>,
+    /// Contents of `--notfound-page`, re-read on reload
+    not_found_page: Arc<ArcSwapOption<String>>,
+    /// Primary targets that are down, maintained by `--health-check-interval`
+    health: Arc<Health>,
+    /// Paths rejected before matching, from `--blocklist`
+    blocklist: Option<Arc<Blocklist>>,
+    /// Told about every redirect, from `--webhook`
+    webhook: Option<Arc<Webhook>>,
+    /// Cleared by `POST /_ready/false` to drain the instance, see `/readyz`
+    ready: Arc<AtomicBool>,
+    /// Notified by `POST /_shutdown`, see [`shutdown_signal`]
+    shutdown: Arc<tokio::sync::Notify>,
+    access_log: LogSink,
+}
+
+/// Where `logging_middleware` writes its lines
+#[derive(Debug, Clone, Default)]
+enum LogSink {
+    #[default]
+    Stdout,
+    Syslog(Syslog),
+    /// Collects lines for assertions
+    #[cfg(test)]
+    Channel(tokio::sync::mpsc::UnboundedSender<String>),
+}
+
+impl LogSink {
+    fn write(&self, line: String) {
+        match self {
+            Self::Stdout => println!("{line}"),
+            Self::Syslog(syslog) => syslog.send(&line),
+            #[cfg(test)]
+            Self::Channel(lines) => {
+                let _ = lines.send(line);
+            }
+        }
+    }
+}
+
+impl AppState {
+    fn new(rules: Matcher, options: AppOptions) -> Self {
+        Self {
+            rules: Arc::new(ArcSwap::from_pointee(rules)),
+            options: Arc::new(options),
+            stats: Arc::default(),
+            config: None,
+            not_found_page: Arc::default(),
+            health: Arc::default(),
+            blocklist: None,
+            webhook: None,
+            ready: Arc::new(AtomicBool::new(true)),
+            shutdown: Arc::default(),
+            access_log: LogSink::default(),
+        }
+    }
+
+    /// (Re-)reads the `--notfound-page` template, if one is configured
+    fn load_not_found_page(&self) -> io::Result<()> {
+        if let Some(path) = &self.options.notfound_page {
+            let page = std::fs::read_to_string(path)?;
+            self.not_found_page.store(Some(Arc::new(page)));
+        }
+        Ok(())
+    }
+
+    fn with_blocklist(self, blocklist: Blocklist) -> Self {
+        Self {
+            blocklist: Some(Arc::new(blocklist)),
+            ..self
+        }
+    }
+
+    fn with_webhook(self, webhook: Webhook) -> Self {
+        Self {
+            webhook: Some(Arc::new(webhook)),
+            ..self
+        }
+    }
+
+    fn with_config(self, path: impl Into<String>, load_options: LoadOptions) -> Self {
+        Self {
+            config: Some(Arc::new(ConfigSource {
+                path: path.into(),
+                load_options,
+            })),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ConfigSource {
+    path: String,
+    load_options: LoadOptions,
+}
+
+const DEFAULT_VERSION_PATH: &str = "/_version";
+const DEFAULT_MAX_TARGET_LEN: usize = 8192;
+
+/// Runtime options that shape how requests are answered, reported by
+/// `GET /_config` with secrets redacted
+#[derive(Debug, Clone, Serialize)]
+struct AppOptions {
+    /// Use 307/308 instead of 301/302
+    modern: bool,
+    /// Log each request to stdout
+    logging: bool,
+    /// Directory to serve static files from
+    static_dir: Option<PathBuf>,
+    /// Only match literal paths, without the trailing-slash fallback
+    exact_only: bool,
+    /// Send unmatched paths to their nearest ancestor's exact rule
+    hierarchical: bool,
+    /// Path of the build/version info endpoint
+    version_path: String,
+    /// Answer 408 when a request takes longer than this
+    #[serde(rename = "request_timeout_secs", serialize_with = "serialize_secs")]
+    request_timeout: Option<Duration>,
+    /// Answer 503 instead of queueing once this many requests are in flight
+    max_concurrent_requests: Option<usize>,
+    /// Public origin used for fully-qualified short URLs, e.g. `https://go.example.com`
+    base_url: Option<String>,
+    /// Serve 302 rules as 301 (308 with `modern`) once they have more hits than this
+    promote_after: Option<u64>,
+    /// Bearer token for the admin endpoints, which are only mounted when set
+    #[serde(serialize_with = "serialize_redacted")]
+    admin_token: Option<String>,
+    /// Also mount `POST /_shutdown` with the admin endpoints
+    shutdown_endpoint: bool,
+    /// Base path stripped from requests before matching, e.g. `/go`
+    prefix: Option<String>,
+    /// Append the request's query string to the target
+    forward_query: bool,
+    /// Let `?__status=301|302` pick the redirect status (for debugging)
+    allow_query_override: bool,
+    /// Number of proxies in front of dslf that append to X-Forwarded-For
+    trust_proxy_count: Option<usize>,
+    /// HTML template served for unmatched requests, `{path}` is replaced
+    notfound_page: Option<PathBuf>,
+    /// Set on every response, replacing any existing value
+    #[serde(serialize_with = "serialize_headers")]
+    response_headers: Vec<(HeaderName, HeaderValue)>,
+    /// Strip the `Server` header from every response (`--server-header none`)
+    hide_server_header: bool,
+    /// Compress response bodies (gzip or brotli) for clients that accept it
+    compress: bool,
+    /// Add a `shard=` hash of the path to each log line
+    log_slug_hash: bool,
+    /// Tag requests with an `X-Request-Id`, echoed back and logged
+    request_id: bool,
+    /// Layout of each request log line
+    log_format: LogFormat,
+    /// Send `X-Robots-Tag: noindex` with redirects
+    noindex: bool,
+    /// Send `Link: <origin>; rel=preconnect` for the target with redirects
+    preconnect: bool,
+    /// `Cache-Control: max-age` for permanent redirects, with `Date` and `Age`
+    cache_max_age: Option<u64>,
+    /// Show a "you are leaving" page before redirecting to other hosts
+    interstitial: bool,
+    /// Serve Prometheus metrics at `/metrics`
+    metrics: bool,
+    /// Serve the `/_echo` request introspection endpoint
+    debug: bool,
+    /// Drop any body from 3xx responses, whichever layer produced them
+    strict_empty_body: bool,
+    /// Redirect requests for any other `Host` here first, keeping the path
+    canonical_host: Option<String>,
+    /// Zone that rule schedules are evaluated in; the server's local zone when unset
+    #[serde(serialize_with = "serialize_timezone")]
+    timezone: Option<chrono_tz::Tz>,
+    /// Artificial wait before answering redirect lookups, for testing clients
+    #[serde(rename = "response_delay_secs", serialize_with = "serialize_secs")]
+    response_delay: Option<Duration>,
+}
+
+/// Reports whether a secret is set without revealing it
+fn serialize_redacted<S: serde::Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| "[redacted]").serialize(serializer)
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    value: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.map(|d| d.as_secs_f64()).serialize(serializer)
+}
+
+/// `--header` pairs as `Name: value` strings
+fn serialize_headers<S: serde::Serializer>(
+    headers: &[(HeaderName, HeaderValue)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {}", String::from_utf8_lossy(value.as_bytes()))),
+    )
+}
+
+fn serialize_timezone<S: serde::Serializer>(
+    value: &Option<chrono_tz::Tz>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.map(|tz| tz.name()).serialize(serializer)
+}
+
+impl Default for AppOptions {
+    fn default() -> Self {
+        Self {
+            modern: false,
+            logging: false,
+            static_dir: None,
+            exact_only: false,
+            hierarchical: false,
+            version_path: DEFAULT_VERSION_PATH.to_string(),
+            request_timeout: None,
+            max_concurrent_requests: None,
+            base_url: None,
+            promote_after: None,
+            admin_token: None,
+            shutdown_endpoint: false,
+            prefix: None,
+            forward_query: false,
+            allow_query_override: false,
+            trust_proxy_count: None,
+            notfound_page: None,
+            response_headers: Vec::new(),
+            hide_server_header: false,
+            compress: false,
+            log_slug_hash: false,
+            request_id: false,
+            log_format: LogFormat::Text,
+            noindex: false,
+            preconnect: false,
+            cache_max_age: None,
+            interstitial: false,
+            metrics: false,
+            debug: false,
+            strict_empty_body: false,
+            canonical_host: None,
+            timezone: None,
+            response_delay: None,
+        }
+    }
+}
+
+impl From<&Cli> for AppOptions {
+    fn from(cli: &Cli) -> Self {
+        Self {
+            modern: cli.modern,
+            logging: !cli.silent,
+            static_dir: cli.static_dir.clone(),
+            exact_only: cli.exact_only,
+            hierarchical: cli.hierarchical,
+            version_path: cli.version_path.clone(),
+            request_timeout: cli.request_timeout.map(Duration::from_secs),
+            max_concurrent_requests: cli.max_concurrent_requests.map(|n| n as usize),
+            base_url: cli.base_url.clone(),
+            promote_after: cli.promote_after,
+            admin_token: cli.admin_token.clone(),
+            shutdown_endpoint: cli.shutdown_endpoint,
+            prefix: cli
+                .prefix
+                .as_deref()
+                .map(|p| p.trim_end_matches('/').to_string())
+                .filter(|p| !p.is_empty()),
+            forward_query: cli.forward_query,
+            allow_query_override: cli.allow_query_override,
+            trust_proxy_count: cli.trust_proxy_count,
+            notfound_page: cli.notfound_page.clone(),
+            response_headers: cli
+                .server_header
+                .iter()
+                .filter(|value| !value.as_bytes().eq_ignore_ascii_case(b"none"))
+                .map(|value| (header::SERVER, value.clone()))
+                .chain(cli.headers.iter().cloned())
+                .collect(),
+            hide_server_header: cli
+                .server_header
+                .as_ref()
+                .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"none")),
+            compress: cli.compress,
+            log_slug_hash: cli.log_slug_hash,
+            request_id: cli.request_id,
+            log_format: cli.log_format,
+            noindex: cli.noindex,
+            preconnect: cli.preconnect,
+            cache_max_age: cli.cache_max_age,
+            interstitial: cli.interstitial,
+            metrics: cli.metrics,
+            debug: cli.debug,
+            strict_empty_body: cli.strict_empty_body,
+            canonical_host: cli.canonical_host.as_deref().map(str::to_ascii_lowercase),
+            timezone: cli.tz,
+            response_delay: cli.response_delay.map(Duration::from_millis),
+        }
+    }
+}
+
+/// Extracts the client IP from proxy headers (in order of preference)
+/// 1. CF-Connecting-IP (Cloudflare)
+/// 2. X-Real-IP (nginx)
+/// 3. X-Forwarded-For: the first IP in the chain, or with `trusted_proxies`
+///    set, the entry added by the outermost trusted proxy (Nth from the right)
+fn client_ip(headers: &HeaderMap, trusted_proxies: Option<usize>) -> Option<&str> {
+    if let Some(ip) = headers
+        .get("cf-connecting-ip")
+        .or_else(|| headers.get("x-real-ip"))
+    {
+        return ip.to_str().ok().map(str::trim);
+    }
+
+    let forwarded = headers.get("x-forwarded-for")?.to_str().ok()?;
+    let hops: Vec<&str> = forwarded.split(',').map(str::trim).collect();
+    let index = match trusted_proxies {
+        Some(count) => hops.len().saturating_sub(count.max(1)),
+        None => 0,
+    };
+    hops.get(index).copied()
+}
 
-type AppState = (HashMap<String, (String, u16)>, bool);
+/// First 8 hex digits of a 32-bit FNV-1a hash: the same on every replica,
+/// platform and release, unlike `std`'s hasher
+fn slug_hash(slug: &str) -> String {
+    let hash = slug.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    format!("{hash:08x}")
+}
 
-async fn logging_middleware(request: Request<Body>, next: Next) -> Response {
+async fn logging_middleware(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let options = &state.options;
     let method = request.method().clone();
     let uri = request.uri().clone();
+    let version = request.version();
     let path = uri.path();
     let start = Instant::now();
 
     // Skip logging for favicon.ico requests (reduces noise in logs)
-    let should_log = path != "/favicon.ico";
-
-    // Extract client IP from proxy headers (in order of preference)
-    // 1. CF-Connecting-IP (Cloudflare)
-    // 2. X-Real-IP (nginx)
-    // 3. X-Forwarded-For (first IP in chain)
-    // 4. Fall back to "-" if none available
-    let client_ip = if should_log {
-        request
-            .headers()
-            .get("cf-connecting-ip")
-            .or_else(|| request.headers().get("x-real-ip"))
-            .or_else(|| request.headers().get("x-forwarded-for"))
-            .and_then(|v| v.to_str().ok())
-            .map(|s| {
-                // X-Forwarded-For can contain multiple IPs, take the first one
-                s.split(',').next().unwrap_or(s).trim()
-            })
-            .unwrap_or("-")
-            .to_string()
-    } else {
-        String::new()
-    };
+    if path == "/favicon.ico" {
+        return next.run(request).await;
+    }
 
-    let response = next.run(request).await;
+    let headers = request.headers();
+    // Fall back to "-" if no proxy header is available
+    let client_ip = client_ip(headers, options.trust_proxy_count)
+        .unwrap_or("-")
+        .to_string();
+    let [referer, user_agent, request_id] = [header::REFERER, header::USER_AGENT, X_REQUEST_ID]
+        .map(|name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        });
 
-    if should_log {
-        let duration = start.elapsed();
-        let status = response.status();
+    let response = next.run(request).await;
 
+    let entry = LogEntry {
         // Simple timestamp - seconds since epoch for consistency across platforms
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        println!(
-            "{} {} {} {} {} {:.2}ms",
-            timestamp,
-            client_ip,
-            method,
-            uri.path_and_query().map_or(uri.path(), |pq| pq.as_str()),
-            status.as_u16(),
-            duration.as_secs_f64() * 1000.0
-        );
-    }
+        timestamp: stats::unix_now(),
+        client_ip: &client_ip,
+        method: method.as_str(),
+        target: uri.path_and_query().map_or(uri.path(), |pq| pq.as_str()),
+        version: &format!("{version:?}"),
+        status: response.status().as_u16(),
+        bytes: response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok()),
+        referer: referer.as_deref(),
+        user_agent: user_agent.as_deref(),
+        duration: start.elapsed(),
+        shard: options.log_slug_hash.then(|| slug_hash(uri.path())),
+        note: response
+            .extensions()
+            .get::<RuleNote>()
+            .map(|n| n.0.as_str()),
+        request_id: request_id.as_deref().filter(|_| options.request_id),
+    };
+    state.access_log.write(entry.format(options.log_format));
 
     response
 }
@@ -83,7 +459,132 @@ async fn logging_middleware(request: Request<Body>, next: Next) -> Response {
 struct RedirectRule {
     url: String,
     target: String,
-    status: u16,
+    /// Falls back to [`LoadOptions::default_status`] when the column is omitted.
+    /// A number or a name such as `permanent`, see [`deserialize_status`]
+    #[serde(default, deserialize_with = "deserialize_status")]
+    status: Option<u16>,
+    /// Optional `Header-Name=value` condition the request must satisfy
+    #[serde(default)]
+    when_header: Option<String>,
+    /// Optional body for retired (410) links
+    #[serde(default)]
+    message: Option<String>,
+    /// Optional reason a retired link is gone, e.g. `expired campaign`
+    #[serde(default)]
+    note: Option<String>,
+    /// Optional backup target used while `target` fails its health check
+    #[serde(default, alias = "health_fallback")]
+    fallback: Option<String>,
+    /// `true` keeps 301/302 for this rule under `--modern`
+    #[serde(default)]
+    literal: Option<bool>,
+    /// Comma-separated referer domains allowed to use this rule
+    #[serde(default)]
+    allow_referer: Option<String>,
+    /// Time-based targets, e.g. `mon-fri 09:00-17:00 => URL; else => URL`
+    #[serde(default)]
+    schedule: Option<String>,
+    /// Label for looking the rule up with `GET /_lookup?keyword=`
+    #[serde(default)]
+    keyword: Option<String>,
+    /// `|`-separated targets served in turn after `target`
+    #[serde(default)]
+    rotate: Option<String>,
+}
+
+/// `status` names accepted in place of the number
+const STATUS_NAMES: [(&str, u16); 3] = [("permanent", 301), ("temporary", 302), ("gone", 410)];
+
+/// Reads a `status` cell as a number (`301`, or `"301"` in JSON) or a case-
+/// insensitive name from [`STATUS_NAMES`]. Whether the code is allowed is
+/// checked later, in `parse_row`.
+fn deserialize_status<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u16>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Status {
+        Code(u16),
+        Name(String),
+    }
+
+    let name = match Option::<Status>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Status::Code(code)) => return Ok(Some(code)),
+        Some(Status::Name(name)) => name,
+    };
+    let name = name.trim();
+    if let Ok(code) = name.parse() {
+        return Ok(Some(code));
+    }
+    STATUS_NAMES
+        .iter()
+        .find(|(known, _)| name.eq_ignore_ascii_case(known))
+        .map(|&(_, code)| Some(code))
+        .ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "unknown status {name}, expected a number or one of permanent, temporary, gone"
+            ))
+        })
+}
+
+/// Options applied while parsing the redirect config
+#[derive(Debug, Clone)]
+struct LoadOptions {
+    /// Status used for rows without one
+    default_status: u16,
+    /// Longest target accepted, in bytes
+    max_target_len: usize,
+    /// Reject slugs that don't start with `/` (they can never match)
+    strict: bool,
+    /// Prepend the missing `/` to such slugs instead
+    fix: bool,
+    /// Reject rows with an empty slug rather than loading them unmatched
+    deny_empty_slug: bool,
+    /// Most rows accepted from the config
+    max_rules: Option<usize>,
+    /// `(old, new)` slug prefixes rewritten at load time
+    slug_prefix_map: Vec<(String, String)>,
+    /// Lowercase the scheme and host of targets, keeping the path as written
+    lowercase_host: bool,
+    /// Memoize up to this many template, wildcard and regex matches
+    resolve_cache: Option<usize>,
+    /// Lowercase hex SHA-256 the config file must have
+    config_checksum: Option<String>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            default_status: 301,
+            max_target_len: DEFAULT_MAX_TARGET_LEN,
+            strict: false,
+            fix: false,
+            deny_empty_slug: false,
+            max_rules: None,
+            slug_prefix_map: Vec::new(),
+            lowercase_host: false,
+            resolve_cache: None,
+            config_checksum: None,
+        }
+    }
+}
+
+impl From<&Cli> for LoadOptions {
+    fn from(cli: &Cli) -> Self {
+        Self {
+            default_status: cli.default_rule_status,
+            max_target_len: cli.max_target_len,
+            strict: cli.strict,
+            fix: cli.fix,
+            deny_empty_slug: cli.deny_empty_slug,
+            max_rules: cli.max_rules,
+            slug_prefix_map: cli.slug_prefix_map.clone(),
+            lowercase_host: cli.lowercase_host,
+            resolve_cache: cli.resolve_cache,
+            config_checksum: cli.config_checksum.clone(),
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -97,14 +598,104 @@ struct Cli {
     #[arg(short, long)]
     validate: bool,
 
+    /// Extra statuses that count as reachable when validating, e.g. `403,405`
+    #[arg(long, value_name = "CODES", value_delimiter = ',')]
+    validate_accept: Vec<u16>,
+
+    /// Redirects to follow per destination when validating, reporting the
+    /// hop count and final URL (0 checks the target alone)
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    follow_redirects: usize,
+
+    /// Validate distinct hosts in parallel, with at most N requests in flight
+    /// to each [default: one target at a time]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_per_host: Option<u32>,
+
+    /// With --validate, record results in this JSON file
+    #[arg(long, value_name = "FILE", requires = "validate")]
+    validate_cache: Option<PathBuf>,
+
+    /// With --validate-cache, skip targets that passed within --validate-cache-ttl
+    #[arg(long, requires = "validate_cache")]
+    validate_only_changed: bool,
+
+    /// Seconds a cached passing result is trusted
+    #[arg(long, value_name = "SECS", default_value_t = 86400)]
+    validate_cache_ttl: u64,
+
+    /// Validate all destination URLs, then serve only if they all pass
+    #[arg(long)]
+    preload_validate: bool,
+
+    /// Like --preload-validate, but serve even when destinations fail
+    #[arg(long, conflicts_with = "preload_validate")]
+    preload_validate_warn: bool,
+
+    /// Before serving (and after any preload validation), GET every
+    /// destination once to prime slow-starting targets
+    #[arg(long)]
+    warmup: bool,
+
+    /// Warm-up requests in flight at once
+    #[arg(long, value_name = "N", default_value_t = 8, requires = "warmup")]
+    warmup_concurrency: usize,
+
+    /// Resolve every destination host before serving, warning about those
+    /// that don't resolve
+    #[arg(long)]
+    prewarm_dns: bool,
+
     /// Check configuration file syntax without validating destinations
     #[arg(short = 'k', long)]
     check: bool,
 
-    /// Path to the CSV file containing redirect rules
+    /// Seconds to wait for in-flight requests after SIGTERM/Ctrl-C before
+    /// closing them (0 waits indefinitely)
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    drain_timeout: u64,
+
+    /// Print the rules in lookup order, annotated with their kind, and exit
+    #[arg(long)]
+    dump_routes: bool,
+
+    /// With --check, list every rule with its resolved status and any warnings
+    #[arg(long, requires = "check")]
+    verbose: bool,
+
+    /// With --check, fail when the config has warnings (duplicate slugs,
+    /// targets pointing back at --base-url)
+    #[arg(long, requires = "check")]
+    exit_code_on_warnings: bool,
+
+    /// Reject slugs that don't start with '/'
+    #[arg(long)]
+    strict: bool,
+
+    /// Prepend the missing '/' to slugs instead of rejecting them
+    #[arg(long)]
+    fix: bool,
+
+    /// Reject rows with an empty slug; without it they never match (or serve
+    /// `/` with --fix)
+    #[arg(long)]
+    deny_empty_slug: bool,
+
+    /// TOML file whose `[server]` section supplies defaults for bind, port,
+    /// modern and silent; flags and env vars still take precedence
+    #[arg(long, value_name = "FILE")]
+    server_config: Option<PathBuf>,
+
+    /// Path to the CSV file containing redirect rules (`-` reads from stdin).
+    /// A directory is read like --config-dir
     #[arg(short, long, default_value = "redirects.csv")]
     config: String,
 
+    /// Directory of `.csv`/`.jsonl` rule files, merged in alphabetical order
+    /// (used instead of --config)
+    #[arg(long, value_name = "DIR")]
+    config_dir: Option<String>,
+
     /// Bind address (can also be set via DSLF_BIND_ADDR env var)
     #[arg(short, long, env = "DSLF_BIND_ADDR", default_value = "0.0.0.0")]
     bind: String,
@@ -124,6 +715,201 @@ struct Cli {
     /// Directory to serve static files from (index.html, 404.html, etc.)
     #[arg(long, env = "STATIC_DIR")]
     static_dir: Option<PathBuf>,
+
+    /// Only match paths literally (disables the trailing-slash fallback)
+    #[arg(long)]
+    exact_only: bool,
+
+    /// When nothing matches, use the nearest parent path with an exact rule,
+    /// so `/docs/v2/intro` falls back to `/docs/v2`, then `/docs`
+    #[arg(long, conflicts_with = "exact_only")]
+    hierarchical: bool,
+
+    /// Path of the build/version info endpoint
+    #[arg(long, default_value = DEFAULT_VERSION_PATH)]
+    version_path: String,
+
+    /// Status used for rules that omit the status column
+    #[arg(long, default_value = "301")]
+    default_rule_status: u16,
+
+    /// Answer 408 for requests taking longer than this many seconds
+    #[arg(long, value_name = "SECS")]
+    request_timeout: Option<u64>,
+
+    /// Answer 503 while this many requests are already in flight
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_concurrent_requests: Option<u32>,
+
+    /// Public base URL of the service, used for fully-qualified short URLs
+    #[arg(long, env = "DSLF_BASE_URL")]
+    base_url: Option<String>,
+
+    /// Time zone for `schedule` rules, e.g. `Europe/London` [default: local time]
+    #[arg(long, value_name = "ZONE")]
+    tz: Option<chrono_tz::Tz>,
+
+    /// Answer requests for any other host (e.g. `www.` or the bare IP) with a
+    /// 301 to this host and the same path, before matching rules
+    #[arg(long, value_name = "HOST")]
+    canonical_host: Option<String>,
+
+    /// Serve 302 rules as permanent redirects once they have more than N hits
+    #[arg(long, value_name = "N")]
+    promote_after: Option<u64>,
+
+    /// Longest target accepted in the config, in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_TARGET_LEN)]
+    max_target_len: usize,
+
+    /// Rewrite slugs starting with OLD to start with NEW instead (repeatable),
+    /// e.g. `/old-docs=/docs`
+    #[arg(long, value_name = "OLD=NEW", value_parser = parse_slug_prefix_map)]
+    slug_prefix_map: Vec<(String, String)>,
+
+    /// Lowercase the scheme and host of every target; the path and query keep their case
+    #[arg(long)]
+    lowercase_host: bool,
+
+    /// Refuse configs with more than N rows
+    #[arg(long, value_name = "N")]
+    max_rules: Option<usize>,
+
+    /// Remember the last N paths resolved by template, wildcard or regex
+    /// rules; cleared on reload
+    #[arg(long, value_name = "N")]
+    resolve_cache: Option<usize>,
+
+    /// Refuse to load (or reload) the config unless its SHA-256 is HEX
+    #[arg(long, value_name = "HEX", value_parser = parse_sha256)]
+    config_checksum: Option<String>,
+
+    /// Enable the admin endpoints (POST /_reload), authenticated with this bearer token
+    #[arg(long, env = "DSLF_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// Serve POST /_shutdown (with --admin-token) to stop gracefully, as on SIGTERM
+    #[arg(long, requires = "admin_token")]
+    shutdown_endpoint: bool,
+
+    /// Base path to strip from requests before matching (e.g. /go behind a proxy)
+    #[arg(long, value_name = "PATH")]
+    prefix: Option<String>,
+
+    /// Append the request's query string to the target
+    #[arg(long)]
+    forward_query: bool,
+
+    /// Let a `__status=301|302` query parameter override the rule's status (debugging aid)
+    #[arg(long)]
+    allow_query_override: bool,
+
+    /// Number of trusted proxies appending to X-Forwarded-For; the client IP is
+    /// taken that many entries from the right instead of the first entry
+    #[arg(long, value_name = "N")]
+    trust_proxy_count: Option<usize>,
+
+    /// HTML page served for unmatched requests; `{path}` is replaced with the requested path
+    #[arg(long, value_name = "FILE")]
+    notfound_page: Option<PathBuf>,
+
+    /// Serve GET /_echo, which returns the request's method, path, query and
+    /// headers as JSON (for debugging proxy setups)
+    #[arg(long)]
+    debug: bool,
+
+    /// Serve request and reload counters at /metrics in the Prometheus format
+    #[arg(long)]
+    metrics: bool,
+
+    /// Send `X-Robots-Tag: noindex` with redirects so crawlers don't index short URLs
+    #[arg(long)]
+    noindex: bool,
+
+    /// Send a `Link: <origin>; rel=preconnect` header with redirects so
+    /// browsers warm up the connection to the destination
+    #[arg(long)]
+    preconnect: bool,
+
+    /// Let clients and CDNs cache permanent (301/308) redirects for SECS,
+    /// sent as `Cache-Control: public, max-age=SECS` with `Date` and `Age: 0`
+    #[arg(long, value_name = "SECS")]
+    cache_max_age: Option<u64>,
+
+    /// Answer redirects to other hosts with a page naming the destination and
+    /// a continue link (`?continue=1`) instead of a 3xx
+    #[arg(long)]
+    interstitial: bool,
+
+    /// Guarantee every 3xx response has an empty body and `Content-Length: 0`
+    #[arg(long)]
+    strict_empty_body: bool,
+
+    /// Add a stable hash of the requested slug to each log line, for
+    /// correlating logs across replicas
+    #[arg(long)]
+    log_slug_hash: bool,
+
+    /// Reuse each request's `X-Request-Id`, or assign a UUID, then echo it in
+    /// the response and the access log
+    #[arg(long)]
+    request_id: bool,
+
+    /// Request log layout: the default text line, JSON, or Apache's common or
+    /// combined format
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Send request log lines to the local syslog socket instead of stdout,
+    /// under FACILITY (default `user`, e.g. `daemon` or `local0`)
+    #[arg(
+        long,
+        value_name = "FACILITY",
+        num_args = 0..=1,
+        default_missing_value = "user",
+        value_parser = parse_syslog_facility
+    )]
+    log_syslog: Option<syslog::Facility>,
+
+    /// Compress HTML and JSON responses with gzip or brotli per Accept-Encoding
+    #[arg(long)]
+    compress: bool,
+
+    /// Value of the `Server` header on every response, or `none` to strip it
+    #[arg(long, value_name = "VALUE|none", value_parser = parse_header_value)]
+    server_header: Option<HeaderValue>,
+
+    /// Header added to every response (repeatable)
+    #[arg(long = "header", value_name = "NAME=VALUE", value_parser = parse_response_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// File of path globs (one per line, `*` and `?` wildcards) answered with
+    /// 403 before any rule matching
+    #[arg(long, value_name = "FILE")]
+    blocklist: Option<PathBuf>,
+
+    /// Milliseconds to wait before answering a blocklisted path
+    #[arg(long, value_name = "MS", requires = "blocklist")]
+    blocklist_delay: Option<u64>,
+
+    /// Testing only: wait MS milliseconds before answering each redirect
+    /// lookup, to see how clients cope with a slow server
+    #[arg(long, value_name = "MS", hide = true)]
+    response_delay: Option<u64>,
+
+    /// Seconds between HEAD checks of targets that have a fallback; while a
+    /// check fails the rule redirects to its fallback
+    #[arg(long, value_name = "SECS")]
+    health_check_interval: Option<u64>,
+
+    /// POST each redirect as JSON to this URL in the background, dropping
+    /// events while the queue of pending ones is full
+    #[arg(long, value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Report check, validate and import failures as JSON on stderr
+    #[arg(long)]
+    json_errors: bool,
 }
 
 #[derive(Subcommand)]
@@ -133,23 +919,172 @@ enum Commands {
     /// Requires environment variables:
     /// - REBRANDLY_API_KEY or REBRANDLY_TOKEN for rebrandly provider
     Import {
-        /// Provider to import from (currently supports: rebrandly)
+        /// Provider to import from (currently supports: rebrandly, url, txt)
         provider: String,
+        /// JSON array or CSV to fetch, for the url provider; file to read, for txt
+        source: Option<String>,
+        /// Record fields to read, for the url provider
+        #[arg(long, value_name = "slug=FIELD,target=FIELD[,status=FIELD]", value_parser = import::FieldMap::parse)]
+        map: Option<import::FieldMap>,
         /// Output file path for the imported redirects
         #[arg(short, long, default_value = "imported-redirects.csv")]
         output: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = import::ExportFormat::Csv)]
+        format: import::ExportFormat,
+        /// Keep only the shortest slug for each destination
+        #[arg(long)]
+        dedupe_targets: bool,
+        /// Add title, created_at and short_url columns from the provider
+        #[arg(long)]
+        keep_metadata: bool,
+        /// Order of the written redirects
+        #[arg(long, value_enum, default_value_t = import::SortOrder::Slug)]
+        sort: import::SortOrder,
+        /// Print `{total, by_domain, skipped, deduped}` as JSON on stdout once written
+        #[arg(long)]
+        summary_json: bool,
+        /// Rebrandly API root, e.g. for a mock server
+        #[arg(long, env = "REBRANDLY_API_BASE", default_value = import::REBRANDLY_API_BASE)]
+        api_base: String,
+        /// Check credentials, source and flags offline, then exit without importing
+        #[arg(long)]
+        check: bool,
+    },
+    /// Validate URLs that aren't in the config (use --validate for the config)
+    Validate {
+        /// Read newline-separated URLs or `url,target,status` rows from stdin
+        #[arg(long, required = true)]
+        stdin: bool,
     },
 }
 
-fn create_app(
-    rules: HashMap<String, (String, u16)>,
-    modern: bool,
-    enable_logging: bool,
-    static_dir: Option<PathBuf>,
-) -> Router {
-    let state: AppState = (rules.clone(), modern);
+/// Parses a `--config-checksum`, normalized to lowercase
+fn parse_sha256(hex: &str) -> Result<String, String> {
+    if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hex.to_ascii_lowercase())
+    } else {
+        Err("expected 64 hex digits (a SHA-256)".to_string())
+    }
+}
+
+/// Parses a `--slug-prefix-map /old=/new` argument
+fn parse_slug_prefix_map(spec: &str) -> Result<(String, String), String> {
+    let (old, new) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected OLD=NEW, got {spec}"))?;
+    if !old.starts_with('/') || !new.starts_with('/') {
+        return Err(format!("both prefixes must start with '/', got {spec}"));
+    }
+    Ok((old.to_string(), new.to_string()))
+}
+
+fn parse_header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|_| format!("invalid header value: {value}"))
+}
+
+/// Parses a `--log-syslog` facility name
+fn parse_syslog_facility(name: &str) -> Result<syslog::Facility, String> {
+    name.parse().map_err(|()| {
+        format!("unknown syslog facility {name}, expected e.g. user, daemon or local0")
+    })
+}
+
+/// Parses a `--header Name=value` argument
+fn parse_response_header(spec: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {spec}"))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name: {name}"))?;
+
+    Ok((name, parse_header_value(value.trim())?))
+}
+
+/// The `[server]` section of `--server-config`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerSettings {
+    bind: Option<String>,
+    port: Option<u16>,
+    modern: Option<bool>,
+    silent: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ServerConfigFile {
+    #[serde(default)]
+    server: ServerSettings,
+}
+
+impl ServerSettings {
+    fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str::<ServerConfigFile>(content)?.server)
+    }
+
+    /// Fills in the settings the user didn't pass as a flag or env var
+    fn apply(self, cli: &mut Cli, matches: &clap::ArgMatches) {
+        let unset = |id: &str| {
+            !matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+
+        if let Some(bind) = self.bind.filter(|_| unset("bind")) {
+            cli.bind = bind;
+        }
+        if let Some(port) = self.port.filter(|_| unset("port")) {
+            cli.port = port;
+        }
+        if let Some(modern) = self.modern.filter(|_| unset("modern")) {
+            cli.modern = modern;
+        }
+        if let Some(silent) = self.silent.filter(|_| unset("silent")) {
+            cli.silent = silent;
+        }
+    }
+}
+
+/// Parses the command line, then applies `--server-config` underneath it
+fn parse_cli(matches: clap::ArgMatches) -> Result<Cli, Box<dyn std::error::Error>> {
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    if let Some(path) = &cli.server_config {
+        let content = std::fs::read_to_string(path)?;
+        ServerSettings::parse(&content)?.apply(&mut cli, &matches);
+    }
+    Ok(cli)
+}
+
+/// Built-in endpoints, registered ahead of redirect matching
+fn system_routes(options: &AppOptions) -> Router<AppState> {
+    let mut router = Router::new().route(&options.version_path, get(handle_version));
+    if options.admin_token.is_some() {
+        router = router
+            .route("/_reload", post(handle_reload))
+            .route("/_stats", get(handle_stats))
+            .route("/_config", get(handle_config))
+            .route("/_lookup", get(handle_lookup))
+            .route("/_ready/{ready}", post(handle_set_ready))
+            .route("/readyz", get(handle_readyz));
+        if options.shutdown_endpoint {
+            router = router.route("/_shutdown", post(handle_shutdown));
+        }
+    }
+    if options.metrics {
+        router = router.route("/metrics", get(handle_metrics));
+    }
+    if options.debug {
+        router = router.route("/_echo", get(handle_echo));
+    }
+    router
+}
 
-    let mut app = if let Some(dir) = static_dir {
+fn create_app(state: AppState) -> Router {
+    let options = state.options.clone();
+    let layer_state = state.clone();
+
+    let app = if let Some(dir) = options.static_dir.clone() {
         // Path to custom 404 page
         let not_found_path = dir.join("404.html");
 
@@ -158,10 +1093,15 @@ fn create_app(
             .append_index_html_on_directories(true)
             .not_found_service(service_fn({
                 let not_found_path = not_found_path.clone();
-                move |_req: Request<Body>| {
+                let state = state.clone();
+                move |req: Request<Body>| {
                     let path = not_found_path.clone();
+                    let page = not_found(&state, req.uri().path()).ok();
                     async move {
-                        if let Ok(content) = tokio::fs::read_to_string(&path).await {
+                        // --notfound-page takes precedence over the site's 404.html
+                        if let Some(page) = page {
+                            Ok(page)
+                        } else if let Ok(content) = tokio::fs::read_to_string(&path).await {
                             Ok((
                                 StatusCode::NOT_FOUND,
                                 [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
@@ -175,831 +1115,5991 @@ fn create_app(
                 }
             }));
 
-        // Redirects first, then static files
-        // Check redirect rules before serving static files
-        Router::new().fallback_service(service_fn(move |req: Request<Body>| {
-            let rules = rules.clone();
-            let serve_dir = serve_dir.clone();
-
-            // Helper to check redirects and create response
-            let redirect_if_matches = move |lookup_path: &str| -> Option<Response> {
-                rules.get(lookup_path).map(|(target, status)| {
-                    create_redirect_response(target, *status, modern)
-                        .unwrap_or_else(|e| e.into_response())
-                })
-            };
-
-            async move {
-                let path = req.uri().path().to_owned();
+        // Exact slugs first, then static files, then the other rules
+        let fallback_state = state.clone();
+        system_routes(&options)
+            .with_state(state)
+            .fallback_service(service_fn(move |req: Request<Body>| {
+                let state = fallback_state.clone();
+                let serve_dir = serve_dir.clone();
+                let dir = dir.clone();
+
+                async move {
+                    // Already checked by `percent_encoding_middleware`
+                    let path = decode_path(req.uri().path())
+                        .unwrap_or_else(|| req.uri().path().to_owned());
+
+                    if req.method() != Method::GET && req.method() != Method::HEAD {
+                        return Ok(method_not_allowed());
+                    }
+                    if let Some(delay) = state.options.response_delay {
+                        tokio::time::sleep(delay).await;
+                    }
 
-                // Check redirects first (exact match)
-                if let Some(response) = redirect_if_matches(&path) {
-                    return Ok::<_, std::convert::Infallible>(response);
-                }
+                    // See `Matcher` for the precedence between rules. Template,
+                    // wildcard and regex rules give way to a file at the path,
+                    // so e.g. `/.well-known/*` files survive a catch-all rule.
+                    let (headers, uri) = (req.headers(), req.uri());
+                    let redirect = |exact_only_kind: bool| {
+                        let rules = state.rules.load();
+                        let path = strip_prefix(&state.options, &path)?;
+                        let matched =
+                            match find_rule(&rules, path, headers, state.options.exact_only) {
+                                Some(matched)
+                                    if exact_only_kind && matched.kind != RuleKind::Exact =>
+                                {
+                                    return None;
+                                }
+                                Some(matched) => matched,
+                                // Ancestors give way to files, like dynamic rules
+                                None if !exact_only_kind && state.options.hierarchical => {
+                                    find_ancestor(&rules, path, headers)?
+                                }
+                                None => return None,
+                            };
+                        let response = serve_rule(&state, &matched, headers, uri.query());
+                        Some(response.unwrap_or_else(|e| e.into_response()))
+                    };
+                    if let Some(response) = redirect(true) {
+                        return Ok::<_, std::convert::Infallible>(response);
+                    }
+                    if !static_file_exists(&dir, &path).await
+                        && let Some(response) = redirect(false)
+                    {
+                        return Ok(response);
+                    }
 
-                // Check redirects (without trailing slash)
-                let trimmed = path.trim_end_matches('/');
-                if trimmed != path
-                    && let Some(response) = redirect_if_matches(trimmed)
-                {
-                    return Ok::<_, std::convert::Infallible>(response);
+                    // No redirect match, fall back to static files
+                    let response = match serve_dir.oneshot(req).await {
+                        Ok(res) => res.into_response(),
+                        Err(err) => {
+                            eprintln!("Error serving static file at {path}: {err}");
+                            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                        }
+                    };
+                    Ok(response)
                 }
-
-                // No redirect match, fall back to static files
-                let response = match serve_dir.oneshot(req).await {
-                    Ok(res) => res.into_response(),
-                    Err(err) => {
-                        eprintln!("Error serving static file at {path}: {err}");
-                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                    }
-                };
-                Ok(response)
-            }
-        }))
+            }))
     } else {
         // No static directory, only serve redirects
-        Router::new()
-            .route("/{*path}", get(handle_redirect))
+        system_routes(&options)
+            .route(
+                "/{*path}",
+                get(handle_redirect).fallback(|| async { method_not_allowed() }),
+            )
             .with_state(state)
     };
 
-    if enable_logging {
-        app = app.layer(middleware::from_fn(logging_middleware));
-    }
+    apply_layers(app, &layer_state)
+}
 
-    app
+/// The middleware around every route, outermost first. Each layer sees the
+/// responses of the ones after it, including early answers such as a shed
+/// 503 or a blocklist 403, so layers that observe responses (logging, stats)
+/// come before any that can answer without reaching the handler.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AppLayer {
+    /// `--strict-empty-body`, outermost so nothing can slip a body back in
+    StrictEmptyBody,
+    /// `--request-id`, before logging so the log line sees an assigned ID
+    RequestId,
+    /// Request logging, unless `--silent`
+    Logging,
+    /// `--header` and `--server-header none`
+    ResponseHeaders,
+    /// The `/_stats` and `/metrics` counters
+    Stats,
+    /// `--blocklist`
+    Blocklist,
+    /// `--canonical-host`
+    CanonicalHost,
+    /// `--max-concurrent-requests`
+    ConcurrencyLimit,
+    /// `--request-timeout`
+    Timeout,
+    /// `--compress`, which skips empty and tiny bodies so redirects are sent as-is
+    Compression,
+    /// Rejects malformed percent-encoding before routing
+    PercentEncoding,
 }
 
-async fn validate_destinations(
-    rules: &HashMap<String, (String, u16)>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let mut errors = Vec::new();
+const LAYER_ORDER: [AppLayer; 11] = [
+    AppLayer::StrictEmptyBody,
+    AppLayer::RequestId,
+    AppLayer::Logging,
+    AppLayer::ResponseHeaders,
+    AppLayer::Stats,
+    AppLayer::Blocklist,
+    AppLayer::CanonicalHost,
+    AppLayer::ConcurrencyLimit,
+    AppLayer::Timeout,
+    AppLayer::Compression,
+    AppLayer::PercentEncoding,
+];
+
+/// Wraps the router in the enabled layers of [`LAYER_ORDER`]
+fn apply_layers(app: Router, state: &AppState) -> Router {
+    // `Router::layer` wraps what is there so far, so start with the innermost
+    LAYER_ORDER
+        .iter()
+        .rev()
+        .fold(app, |app, &layer| apply_layer(app, layer, state))
+}
 
-    println!("Validating {} destination URLs...", rules.len());
-
-    for (url, (target, _)) in rules {
-        print!("Checking {url}: {target} ... ");
-
-        match client.head(target).send().await {
-            Ok(response) => {
-                if response.status().is_success() || response.status().is_redirection() {
-                    println!("✓ OK");
-                } else {
-                    println!("✗ HTTP {status}", status = response.status());
-                    errors.push(format!(
-                        "{target}: HTTP {status}",
-                        status = response.status()
-                    ));
-                }
-            }
-            Err(e) => {
-                println!("✗ Error: {e}");
-                errors.push(format!("{target}: {e}"));
-            }
+fn apply_layer(app: Router, layer: AppLayer, state: &AppState) -> Router {
+    let options = &state.options;
+    match layer {
+        AppLayer::StrictEmptyBody if options.strict_empty_body => {
+            app.layer(middleware::from_fn(empty_redirect_body_middleware))
         }
+        AppLayer::RequestId if options.request_id => {
+            app.layer(middleware::from_fn(request_id_middleware))
+        }
+        AppLayer::Logging if options.logging => app.layer(middleware::from_fn_with_state(
+            state.clone(),
+            logging_middleware,
+        )),
+        AppLayer::ResponseHeaders
+            if !options.response_headers.is_empty() || options.hide_server_header =>
+        {
+            app.layer(middleware::from_fn_with_state(
+                options.clone(),
+                response_headers_middleware,
+            ))
+        }
+        AppLayer::Stats => app.layer(middleware::from_fn_with_state(
+            state.stats.clone(),
+            stats_middleware,
+        )),
+        AppLayer::Blocklist => match &state.blocklist {
+            Some(blocklist) => app.layer(middleware::from_fn_with_state(
+                blocklist.clone(),
+                blocklist_middleware,
+            )),
+            None => app,
+        },
+        AppLayer::CanonicalHost if options.canonical_host.is_some() => app.layer(
+            middleware::from_fn_with_state(options.clone(), canonical_host_middleware),
+        ),
+        AppLayer::ConcurrencyLimit => match options.max_concurrent_requests {
+            Some(limit) => app.layer(middleware::from_fn_with_state(
+                Arc::new(tokio::sync::Semaphore::new(limit)),
+                concurrency_limit_middleware,
+            )),
+            None => app,
+        },
+        AppLayer::Timeout => match options.request_timeout {
+            Some(timeout) => app.layer(TimeoutLayer::with_status_code(
+                StatusCode::REQUEST_TIMEOUT,
+                timeout,
+            )),
+            None => app,
+        },
+        AppLayer::Compression if options.compress => app.layer(CompressionLayer::new()),
+        AppLayer::PercentEncoding => app.layer(middleware::from_fn(percent_encoding_middleware)),
+        _ => app,
     }
+}
 
-    if errors.is_empty() {
-        println!("✓ All destinations are reachable!");
-        Ok(())
-    } else {
-        println!("\n✗ Validation failed for {} URLs:", errors.len());
-        for error in &errors {
-            println!("  - {error}");
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest incoming `X-Request-Id` reused as is
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Keeps a sane incoming `X-Request-Id` or assigns a random UUID, for
+/// `--request-id`, and echoes it in the response
+async fn request_id_middleware(mut request: Request<Body>, next: Next) -> Response {
+    let incoming = request.headers().get(X_REQUEST_ID).filter(|id| {
+        !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LEN
+            && id.as_bytes().iter().all(u8::is_ascii_graphic)
+    });
+    let id = match incoming {
+        Some(id) => id.clone(),
+        None => {
+            let id = HeaderValue::try_from(random_uuid()).expect("UUIDs are valid header values");
+            request.headers_mut().insert(X_REQUEST_ID, id.clone());
+            id
         }
-        Err(format!(
-            "Validation failed for {count} destinations",
-            count = errors.len()
+    };
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(X_REQUEST_ID, id);
+    response
+}
+
+/// A random (version 4) UUID
+fn random_uuid() -> String {
+    use ring::rand::SecureRandom;
+
+    let mut bytes = [0u8; 16];
+    ring::rand::SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random source is available");
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Sheds requests beyond `--max-concurrent-requests` with a 503 rather than
+/// letting them queue
+async fn concurrency_limit_middleware(
+    axum::extract::State(permits): axum::extract::State<Arc<tokio::sync::Semaphore>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Ok(_permit) = permits.try_acquire_owned() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "1")],
+            "too many requests in flight",
         )
-        .into())
+            .into_response();
+    };
+    next.run(request).await
+}
+
+/// Replaces the body of 3xx responses with an empty one, for `--strict-empty-body`
+async fn empty_redirect_body_middleware(request: Request<Body>, next: Next) -> Response {
+    let response = next.run(request).await;
+    if !response.status().is_redirection() {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+    for name in [header::CONTENT_TYPE, header::CONTENT_ENCODING] {
+        parts.headers.remove(name);
     }
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+    Response::from_parts(parts, Body::empty())
 }
 
-#[tokio::main]
-async fn main() {
-    let cli = Cli::parse();
+/// Redirects requests for a host other than `--canonical-host` to the same
+/// path and query there. The version, metrics and readiness endpoints are
+/// left alone, so probes by IP keep working, as are requests without a `Host`.
+/// `X-Forwarded-Proto` picks the scheme only when it is `http` or `https`.
+async fn canonical_host_middleware(
+    axum::extract::State(options): axum::extract::State<Arc<AppOptions>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(canonical) = &options.canonical_host else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    let exempt = path == options.version_path
+        || (options.metrics && path == "/metrics")
+        || (options.admin_token.is_some() && (path == "/readyz" || path.starts_with("/_ready/")));
+    let headers = request.headers();
+    if exempt || !headers.contains_key(header::HOST) || host_matches(canonical, headers) {
+        return next.run(request).await;
+    }
 
-    // Handle import command
-    if let Some(Commands::Import { provider, output }) = cli.command {
-        if let Err(e) = import::import_links(&provider, &output).await {
-            eprintln!("Import failed: {e}");
-            std::process::exit(1);
+    let scheme = headers
+        .get(HeaderName::from_static("x-forwarded-proto"))
+        .and_then(|v| v.to_str().ok())
+        .filter(|proto| matches!(*proto, "http" | "https"))
+        .unwrap_or("http");
+    let path_and_query = request.uri().path_and_query().map_or("/", |p| p.as_str());
+    let target = format!("{scheme}://{canonical}{path_and_query}");
+    create_redirect_response(&target, 301, options.modern, options.cache_max_age)
+        .unwrap_or_else(IntoResponse::into_response)
+}
+
+/// Percent-decodes a request path, or `None` if it has a `%` that isn't
+/// followed by two hex digits or decodes to invalid UTF-8
+fn decode_path(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
         }
-        return;
     }
+    String::from_utf8(decoded).ok()
+}
 
-    let rules = load_redirect_rules(&cli.config).expect("Failed to load redirect rules");
+/// Answers 400 for paths with malformed percent-encoding (`%`, `%ZZ`) instead
+/// of letting them fall through to a 404
+async fn percent_encoding_middleware(request: Request<Body>, next: Next) -> Response {
+    if decode_path(request.uri().path()).is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Malformed percent-encoding in path",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
 
-    // Check configuration file syntax if requested
-    if cli.check {
-        println!("✓ Configuration file syntax is valid!");
-        println!("  - File: {}", cli.config);
-        println!("  - Rules loaded: {}", rules.len());
-        return;
+/// Answers blocklisted paths with 403, after the configured delay. Both the
+/// raw and the decoded path are checked, as matching uses the decoded one.
+async fn blocklist_middleware(
+    axum::extract::State(blocklist): axum::extract::State<Arc<Blocklist>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let blocked = blocklist.is_blocked(path)
+        || decode_path(path).is_some_and(|decoded| blocklist.is_blocked(&decoded));
+    if !blocked {
+        return next.run(request).await;
     }
 
-    // Validate destinations if requested
-    if cli.validate {
-        if let Err(e) = validate_destinations(&rules).await {
-            eprintln!("Validation failed: {e}");
-            std::process::exit(1);
-        }
-        return;
+    if let Some(delay) = blocklist.delay {
+        tokio::time::sleep(delay).await;
     }
+    StatusCode::FORBIDDEN.into_response()
+}
 
-    let app = create_app(rules, cli.modern, !cli.silent, cli.static_dir);
+/// Applies `--header` and `--server-header` to every response
+async fn response_headers_middleware(
+    axum::extract::State(options): axum::extract::State<Arc<AppOptions>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if options.hide_server_header {
+        headers.remove(header::SERVER);
+    }
+    for (name, value) in &options.response_headers {
+        headers.insert(name, value.clone());
+    }
+    response
+}
 
-    let bind_addr = format!("{bind}:{port}", bind = cli.bind, port = cli.port);
-    let listener = TcpListener::bind(&bind_addr)
-        .await
-        .unwrap_or_else(|e| panic!("Failed to bind to {bind_addr}: {e}"));
+async fn stats_middleware(
+    axum::extract::State(stats): axum::extract::State<Arc<Stats>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let response = next.run(request).await;
+    if response.status().is_redirection() {
+        stats.latency.record(start.elapsed());
+    }
+    stats.record_response(response.status());
+    response
+}
 
-    println!("Forwarding service running on http://{bind_addr}");
+/// A CLI failure, reported as text or, with `--json-errors`, as JSON on stderr
+#[derive(Debug, Serialize)]
+struct CliError {
+    error: String,
+    details: Vec<String>,
+}
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+impl CliError {
+    fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            details: Vec::new(),
+        }
+    }
 }
 
-async fn handle_redirect(
-    Path(path): Path<String>,
-    axum::extract::State((rules, modern)): axum::extract::State<AppState>,
-) -> Result<Response, StatusCode> {
-    let request_path = format!("/{path}");
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.error)
+    }
+}
+
+impl std::error::Error for CliError {}
 
-    // Try exact match first
-    if let Some((target, status)) = rules.get(&request_path) {
-        create_redirect_response(target, *status, modern)
+impl From<Box<dyn std::error::Error>> for CliError {
+    fn from(error: Box<dyn std::error::Error>) -> Self {
+        Self::new(error.to_string())
+    }
+}
+
+/// Writes a failure as `{context}: {error}`, or as a JSON object when `json` is set
+fn write_cli_error(
+    out: &mut impl Write,
+    context: &str,
+    error: &CliError,
+    json: bool,
+) -> io::Result<()> {
+    if json {
+        serde_json::to_writer(&mut *out, error)?;
+        writeln!(out)
     } else {
-        // If exact match fails, try without trailing slash
-        let trimmed_path = request_path.trim_end_matches('/');
-        if let Some((target, status)) = rules.get(trimmed_path) {
-            create_redirect_response(target, *status, modern)
-        } else {
-            Err(StatusCode::NOT_FOUND)
+        writeln!(out, "{context}: {error}")
+    }
+}
+
+fn exit_with_error(context: &str, error: &CliError, json: bool) -> ! {
+    let _ = write_cli_error(&mut io::stderr().lock(), context, error, json);
+    std::process::exit(1);
+}
+
+/// Checks every destination with a HEAD request and returns how many were
+/// checked. Per-URL results go to stdout when `progress` is set.
+async fn validate_destinations(
+    rules: &Matcher,
+    client: &impl Probe,
+    options: ValidateOptions<'_>,
+    progress: bool,
+) -> Result<usize, CliError> {
+    report_validation(&validate_rules(rules, client, options).await, progress)
+}
+
+/// Reports `--validate` results, exiting with an error if any failed
+fn print_validation(results: &[ValidationResult], json: bool) {
+    match report_validation(results, !json) {
+        Ok(checked) if json => {
+            println!(
+                "{}",
+                serde_json::json!({ "valid": true, "destinations": checked })
+            );
         }
+        Ok(_) => {}
+        Err(e) => exit_with_error("Validation failed", &e, json),
     }
 }
 
-fn create_redirect_response(
-    target: &str,
-    status: u16,
-    modern: bool,
+/// Turns validation results into the CLI outcome, printing them when
+/// `progress` is set
+fn report_validation(results: &[ValidationResult], progress: bool) -> Result<usize, CliError> {
+    let count = results.len();
+    if progress {
+        println!("Validating {count} destination URLs...");
+    }
+
+    let mut errors = Vec::new();
+
+    for result in results {
+        let ValidationResult {
+            slug,
+            target,
+            status,
+            error,
+            cached,
+            redirects,
+            final_url,
+        } = result;
+        if result.is_ok() {
+            if progress {
+                let note = match final_url {
+                    _ if *cached => " (cached)".to_string(),
+                    Some(url) if *redirects == 1 => format!(" (1 redirect to {url})"),
+                    Some(url) => format!(" ({redirects} redirects to {url})"),
+                    None => String::new(),
+                };
+                println!("Checking {slug}: {target} ... ✓ OK{note}");
+            }
+            continue;
+        }
+
+        let error = error.as_deref().unwrap_or_default();
+        if progress {
+            match status {
+                Some(_) => println!("Checking {slug}: {target} ... ✗ {error}"),
+                None => println!("Checking {slug}: {target} ... ✗ Error: {error}"),
+            }
+        }
+        errors.push(format!("{target}: {error}"));
+    }
+
+    if errors.is_empty() {
+        if progress {
+            println!("✓ All destinations are reachable!");
+        }
+        Ok(count)
+    } else {
+        if progress {
+            println!("\n✗ Validation failed for {} URLs:", errors.len());
+            for error in &errors {
+                println!("  - {error}");
+            }
+        }
+        Err(CliError {
+            error: format!(
+                "Validation failed for {count} destinations",
+                count = errors.len()
+            ),
+            details: errors,
+        })
+    }
+}
+
+/// `--preload-validate`: checks destinations before serving. Failures are an
+/// error unless `warn` is set, in which case they are only reported.
+async fn preload_validate(
+    rules: &Matcher,
+    client: &impl Probe,
+    options: ValidateOptions<'_>,
+    warn: bool,
+    progress: bool,
+) -> Result<(), CliError> {
+    match validate_destinations(rules, client, options, progress).await {
+        Err(e) if warn => {
+            eprintln!("Warning: {e}, serving anyway");
+            for detail in &e.details {
+                eprintln!("  - {detail}");
+            }
+            Ok(())
+        }
+        result => result.map(|_| ()),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = Cli::command().get_matches();
+    let json = matches.get_flag("json_errors");
+    let cli = parse_cli(matches)
+        .unwrap_or_else(|e| exit_with_error("Failed to load --server-config", &e.into(), json));
+
+    // Handle import command
+    if let Some(Commands::Import {
+        provider,
+        source,
+        map,
+        output,
+        format,
+        dedupe_targets,
+        keep_metadata,
+        sort,
+        summary_json,
+        api_base,
+        check,
+    }) = &cli.command
+    {
+        let options = import::ImportOptions {
+            format: *format,
+            dedupe_targets: *dedupe_targets,
+            source: source.clone(),
+            field_map: map.clone().unwrap_or_default(),
+            keep_metadata: *keep_metadata,
+            sort: *sort,
+            summary_json: *summary_json,
+            api_base: Some(api_base.clone()),
+        };
+        if *check {
+            match import::check_import(provider, output, &options, |name| std::env::var(name).ok())
+            {
+                Ok(report) => println!("✅ {report}"),
+                Err(e) => exit_with_error("Import check failed", &e.into(), json),
+            }
+            return;
+        }
+        if let Err(e) = import::import_links(provider, output, options).await {
+            exit_with_error("Import failed", &e.into(), json);
+        }
+        return;
+    }
+
+    if let Some(Commands::Validate { stdin: true }) = &cli.command {
+        let targets = read_target_list(io::stdin().lock())
+            .unwrap_or_else(|e| exit_with_error("Failed to read targets", &e.into(), json));
+        let options = ValidateOptions {
+            accept: &cli.validate_accept,
+            max_redirects: cli.follow_redirects,
+            max_per_host: cli.max_per_host.map(|n| n as usize),
+            ..Default::default()
+        };
+        let results = validate_targets(targets, &validate::client(), options).await;
+        print_validation(&results, json);
+        return;
+    }
+
+    let load_options = LoadOptions::from(&cli);
+    let config = cli.config_dir.as_ref().unwrap_or(&cli.config);
+    let parsed = read_redirect_rules(config, &load_options)
+        .unwrap_or_else(|e| exit_with_error("Failed to load redirect rules", &e.into(), json));
+    let report = cli
+        .verbose
+        .then(|| check_report(&parsed, cli.modern, cli.base_url.as_deref()));
+    if cli.check
+        && let Err(e) = check_warnings_gate(
+            check_warnings(&parsed, cli.base_url.as_deref()),
+            cli.exit_code_on_warnings,
+        )
+    {
+        match &report {
+            Some(report) => print!("{report}"),
+            None if !json => e.details.iter().for_each(|w| eprintln!("  ⚠ {w}")),
+            None => {}
+        }
+        exit_with_error("Configuration check failed", &e, json);
+    }
+    let rules = compile_rules(parsed, &load_options)
+        .unwrap_or_else(|e| exit_with_error("Failed to load redirect rules", &e.into(), json));
+
+    // Check configuration file syntax if requested
+    if cli.check {
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({ "valid": true, "file": config, "rules": rules.len() })
+            );
+        } else {
+            println!("✓ Configuration file syntax is valid!");
+            println!("  - File: {config}");
+            println!("  - Rules loaded: {}", rules.len());
+            if let Some(report) = report {
+                print!("\n{report}");
+            }
+        }
+        return;
+    }
+
+    if cli.dump_routes {
+        print!("{}", dump_routes(&rules, &AppOptions::from(&cli)));
+        return;
+    }
+
+    // Validate destinations if requested
+    if cli.validate {
+        let client = validate::client();
+        let cache_path = cli.validate_cache.as_deref();
+        let mut cache = match cache_path.map(ValidationCache::load).transpose() {
+            Ok(cache) => cache,
+            Err(e) => exit_with_error(
+                "Failed to read --validate-cache",
+                &CliError::new(e.to_string()),
+                json,
+            ),
+        };
+        let freshness = CacheFreshness {
+            now: stats::unix_now(),
+            ttl: cli.validate_cache_ttl,
+        };
+        let reuse = cache
+            .as_ref()
+            .filter(|_| cli.validate_only_changed)
+            .map(|cache| (cache, freshness));
+
+        let options = ValidateOptions {
+            accept: &cli.validate_accept,
+            max_redirects: cli.follow_redirects,
+            max_per_host: cli.max_per_host.map(|n| n as usize),
+            cache: reuse,
+        };
+        let results = validate_rules(&rules, &client, options).await;
+        if let (Some(cache), Some(path)) = (&mut cache, cache_path) {
+            cache.update(&results, freshness.now);
+            if let Err(e) = cache.save(path) {
+                eprintln!("Warning: failed to write --validate-cache: {e}");
+            }
+        }
+
+        print_validation(&results, json);
+        return;
+    }
+
+    let options = AppOptions::from(&cli);
+
+    if !options.version_path.starts_with('/') {
+        exit_with_error(
+            "Invalid --version-path",
+            &CliError::new("must start with '/'"),
+            json,
+        );
+    }
+
+    if options.prefix.as_ref().is_some_and(|p| !p.starts_with('/')) {
+        exit_with_error(
+            "Invalid --prefix",
+            &CliError::new("must start with '/'"),
+            json,
+        );
+    }
+
+    if let Some(base_url) = &options.base_url
+        && !(base_url.starts_with("http://") || base_url.starts_with("https://"))
+    {
+        exit_with_error(
+            "Invalid --base-url",
+            &CliError::new("must start with http:// or https://"),
+            json,
+        );
+    }
+
+    if options.logging {
+        println!("{}", startup_summary(&rules, &options));
+    }
+
+    if cli.preload_validate || cli.preload_validate_warn {
+        let progress = options.logging && !json;
+        let validate_options = ValidateOptions {
+            accept: &cli.validate_accept,
+            max_redirects: cli.follow_redirects,
+            max_per_host: cli.max_per_host.map(|n| n as usize),
+            ..Default::default()
+        };
+        if let Err(e) = preload_validate(
+            &rules,
+            &validate::client(),
+            validate_options,
+            cli.preload_validate_warn,
+            progress,
+        )
+        .await
+        {
+            exit_with_error("Preload validation failed", &e, json);
+        }
+    }
+
+    if cli.prewarm_dns {
+        let (failed, total) = prewarm_dns(&rules, &SystemResolver, 16).await;
+        for (host, e) in &failed {
+            eprintln!("Warning: {host} did not resolve: {e}");
+        }
+        if options.logging {
+            println!(
+                "Resolved {}/{total} destination hosts",
+                total - failed.len()
+            );
+        }
+    }
+
+    if cli.warmup {
+        let client = reqwest::Client::new();
+        let (answered, total) = warmup(&rules, &client, cli.warmup_concurrency).await;
+        if options.logging {
+            println!("Warmed up {answered}/{total} destinations");
+        }
+    }
+
+    let mut state = AppState::new(rules, options).with_config(config, load_options);
+    if let Some(path) = &cli.blocklist {
+        let delay = cli.blocklist_delay.map(Duration::from_millis);
+        let blocklist = std::fs::read_to_string(path)
+            .map_err(Into::into)
+            .and_then(|content| Blocklist::parse(&content, delay));
+        match blocklist {
+            Ok(blocklist) => state = state.with_blocklist(blocklist),
+            Err(e) => exit_with_error("Failed to load --blocklist", &e.into(), json),
+        }
+    }
+    if let Some(url) = &cli.webhook {
+        state = state.with_webhook(Webhook::spawn(url.clone(), webhook::QUEUE_CAPACITY));
+    }
+    if let Some(facility) = cli.log_syslog {
+        match Syslog::local(facility) {
+            Ok(syslog) => state.access_log = LogSink::Syslog(syslog),
+            Err(e) => exit_with_error(
+                "Failed to connect to syslog",
+                &CliError::new(e.to_string()),
+                json,
+            ),
+        }
+    }
+    if let Err(e) = state.load_not_found_page() {
+        exit_with_error(
+            "Failed to read --notfound-page",
+            &CliError::new(e.to_string()),
+            json,
+        );
+    }
+
+    if let Some(secs) = cli.health_check_interval.filter(|&s| s > 0) {
+        tokio::spawn(health::run(
+            state.rules.clone(),
+            state.health.clone(),
+            Duration::from_secs(secs),
+        ));
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(dump_stats_on_sigusr1(state.stats.clone()));
+
+    let shutdown = state.shutdown.clone();
+    let app = create_app(state);
+
+    let bind_addr = bind_address(&cli.bind, cli.port);
+    let listener = TcpListener::bind(&bind_addr)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to bind to {bind_addr}: {e}"));
+
+    println!("Forwarding service running on http://{bind_addr}");
+
+    // Stop accepting on SIGTERM/Ctrl-C (or POST /_shutdown), then give in-flight requests up to
+    // --drain-timeout to finish
+    let stop = Arc::new(tokio::sync::Notify::new());
+    let server = axum::serve(listener, app)
+        .with_graceful_shutdown({
+            let stop = stop.clone();
+            async move { stop.notified().await }
+        })
+        .into_future();
+    let mut server = std::pin::pin!(server);
+
+    let result = tokio::select! {
+        result = &mut server => result,
+        () = shutdown_signal(&shutdown) => {
+            println!("Shutting down, draining connections...");
+            stop.notify_one();
+            let timeout = Some(Duration::from_secs(cli.drain_timeout)).filter(|t| !t.is_zero());
+            match drain(server, timeout).await {
+                Some(result) => result,
+                None => {
+                    println!("Drain timeout reached, closing remaining connections");
+                    Ok(())
+                }
+            }
+        }
+    };
+    result.expect("Failed to start server");
+}
+
+/// Resolves on Ctrl-C, on SIGTERM on Unix, and when `requested` is notified
+/// by `POST /_shutdown`
+async fn shutdown_signal(requested: &tokio::sync::Notify) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {},
+        () = terminate => {},
+        () = requested.notified() => {},
+    }
+}
+
+/// Prints the counters to stderr on every SIGUSR1, for debugging without the
+/// admin endpoints
+#[cfg(unix)]
+async fn dump_stats_on_sigusr1(stats: Arc<Stats>) {
+    let Ok(mut signal) =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())
+    else {
+        return;
+    };
+    while signal.recv().await.is_some() {
+        eprint!("{}", stats.render_text());
+    }
+}
+
+/// Waits for a shutting-down server to finish, up to `timeout` if one is set.
+/// `None` means the timeout hit with requests still in flight.
+async fn drain<F: std::future::Future>(server: F, timeout: Option<Duration>) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, server).await.ok(),
+        None => Some(server.await),
+    }
+}
+
+/// Joins the bind host and port, bracketing IPv6 literals (`[::1]:3000`)
+fn bind_address(bind: &str, port: u16) -> String {
+    let host = bind.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{bind}:{port}"),
+    }
+}
+
+/// One-line description of the loaded rules and the options in effect
+fn startup_summary(rules: &Matcher, options: &AppOptions) -> String {
+    let mut flags = Vec::new();
+    if options.modern {
+        flags.push("modern".to_string());
+    }
+    if options.logging {
+        flags.push("logging".to_string());
+    }
+    if let Some(dir) = &options.static_dir {
+        flags.push(format!("static-dir={}", dir.display()));
+    }
+    if options.exact_only {
+        flags.push("exact-only".to_string());
+    }
+    if options.compress {
+        flags.push("compress".to_string());
+    }
+    if options.forward_query {
+        flags.push("forward-query".to_string());
+    }
+    if options.allow_query_override {
+        flags.push("allow-query-override".to_string());
+    }
+    if let Some(prefix) = &options.prefix {
+        flags.push(format!("prefix={prefix}"));
+    }
+    if let Some(promote_after) = options.promote_after {
+        flags.push(format!("promote-after={promote_after}"));
+    }
+    if let Some(timeout) = options.request_timeout {
+        flags.push(format!("request-timeout={}s", timeout.as_secs()));
+    }
+
+    let flags = if flags.is_empty() {
+        "none".to_string()
+    } else {
+        flags.join(", ")
+    };
+
+    format!(
+        "Loaded {} redirect rules ({} template, {} wildcard, {} regex, options: {flags})",
+        rules.len(),
+        rules.template.len(),
+        rules.wildcard.len(),
+        rules.regex.len()
+    )
+}
+
+/// The status a rule is served with, after the `--modern` remapping
+fn resolved_status(status: u16, modern: bool) -> u16 {
+    match (status, modern) {
+        (301, true) => 308,
+        (302, true) => 307,
+        (status, _) => status,
+    }
+}
+
+/// ` [when Name=value] [host example.com]` for conditional rules, else empty
+fn rule_conditions(rule: &Rule) -> String {
+    let mut conditions = String::new();
+    if let Some((name, value)) = &rule.when_header {
+        conditions.push_str(&format!(" [when {name}={value}]"));
+    }
+    if let Some(host) = &rule.host {
+        conditions.push_str(&format!(" [host {host}]"));
+    }
+    conditions
+}
+
+/// `--dump-routes` listing: every rule in the order [`find_rule`] tries them,
+/// numbered and annotated with its kind and served status
+fn dump_routes(rules: &Matcher, options: &AppOptions) -> String {
+    let mut dump = String::from(if options.exact_only {
+        "# exact slugs match as written (--exact-only)\n"
+    } else {
+        "# exact slugs match as written, then without trailing slashes\n"
+    });
+
+    for (index, (slug, kind, rule)) in rules.entries().into_iter().enumerate() {
+        let kind = match kind {
+            RuleKind::Exact => "exact",
+            RuleKind::Template => "template",
+            RuleKind::Wildcard => "wildcard",
+            RuleKind::Regex => "regex",
+        };
+        let status = resolved_status(rule.status, options.modern && !rule.literal);
+        dump.push_str(&format!(
+            "{number:>4}. {kind:<8} {slug} -> {target} ({status}){conditions}\n",
+            number = index + 1,
+            target = rule.target,
+            conditions = rule_conditions(rule),
+        ));
+    }
+    dump
+}
+
+/// `--check --verbose` listing: one line per row in file order with its
+/// resolved status, followed by the [`check_warnings`]
+fn check_report(parsed: &[ParsedRule], modern: bool, base_url: Option<&str>) -> String {
+    let mut report = String::new();
+
+    for ParsedRule { url, rule } in parsed {
+        let status = resolved_status(rule.status, modern && !rule.literal);
+        let condition = rule_conditions(rule);
+        report.push_str(&format!(
+            "  {url} -> {} ({status}){condition}\n",
+            rule.target
+        ));
+    }
+
+    for warning in check_warnings(parsed, base_url) {
+        report.push_str(&format!("  ⚠ {warning}\n"));
+    }
+    report
+}
+
+/// Rows that are shadowed by a later duplicate or that point back at
+/// `base_url`, in file order
+fn check_warnings(parsed: &[ParsedRule], base_url: Option<&str>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut seen = HashSet::new();
+    let base_url = base_url.map(|url| url.trim_end_matches('/'));
+
+    for ParsedRule { url, rule } in parsed {
+        if url.is_empty() {
+            warnings.push(format!(
+                "The row for {} has an empty slug and never matches",
+                rule.target
+            ));
+        }
+        if !seen.insert((url.as_str(), &rule.when_header, &rule.host)) {
+            warnings.push(format!(
+                "{url} is defined more than once, the last row wins"
+            ));
+        }
+        if let Some(base_url) = base_url
+            && rule
+                .target
+                .strip_prefix(base_url)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        {
+            warnings.push(format!("{url} redirects back to {base_url}"));
+        }
+    }
+    warnings
+}
+
+/// Fails `--check` for `--exit-code-on-warnings` when there are any warnings
+fn check_warnings_gate(warnings: Vec<String>, fail_on_warnings: bool) -> Result<(), CliError> {
+    if !fail_on_warnings || warnings.is_empty() {
+        return Ok(());
+    }
+    Err(CliError {
+        error: format!("Config has {} warnings", warnings.len()),
+        details: warnings,
+    })
+}
+
+async fn handle_redirect(
+    Path(path): Path<String>,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    axum::extract::RawQuery(query): axum::extract::RawQuery,
 ) -> Result<Response, StatusCode> {
-    let actual_status = match (status, modern) {
-        (301, false) => StatusCode::MOVED_PERMANENTLY, // 301
-        (301, true) => StatusCode::PERMANENT_REDIRECT, // 308
-        (302, false) => StatusCode::FOUND,             // 302
-        (302, true) => StatusCode::TEMPORARY_REDIRECT, // 307
-        _ => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    if let Some(delay) = state.options.response_delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    let request_path = format!("/{path}");
+    let Some(match_path) = strip_prefix(&state.options, &request_path) else {
+        return not_found(&state, &request_path);
+    };
+
+    let options = MatchOptions {
+        exact_only: state.options.exact_only,
+        hierarchical: state.options.hierarchical,
+    };
+    match match_rule(&state.rules.load(), match_path, &headers, options) {
+        Some(matched) => serve_rule(&state, &matched, &headers, query.as_deref()),
+        None => not_found(&state, &request_path),
+    }
+}
+
+/// The `--notfound-page` with `{path}` filled in, or a bare 404 when unset
+fn not_found(state: &AppState, path: &str) -> Result<Response, StatusCode> {
+    let page = state.not_found_page.load();
+    let page = page.as_deref().ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        StatusCode::NOT_FOUND,
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        page.replace("{path}", &escape_html(path)),
+    )
+        .into_response())
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The path to match with `--prefix` removed, or `None` when it is outside the prefix
+fn strip_prefix<'a>(options: &AppOptions, path: &'a str) -> Option<&'a str> {
+    let Some(prefix) = &options.prefix else {
+        return Some(path);
+    };
+
+    match path.strip_prefix(prefix.as_str())? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// Counts the hit and answers with the rule, promoting a 302 to 301 once it
+/// has more than `--promote-after` hits
+fn serve_rule(
+    state: &AppState,
+    matched: &Matched,
+    headers: &HeaderMap,
+    query: Option<&str>,
+) -> Result<Response, StatusCode> {
+    if !matched.rule.referer_allowed(headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let hits = state.stats.hits.record(&matched.slug);
+
+    let mut status = match (matched.rule.status, state.options.promote_after) {
+        (302, Some(promote_after)) if hits > promote_after => 301,
+        (status, _) => status,
+    };
+
+    let mut query = query.map(Cow::Borrowed);
+    if state.options.allow_query_override
+        && let Some(q) = &query
+    {
+        let (override_status, rest) = take_status_override(q);
+        if let Some(override_status) = override_status
+            && status != 410
+        {
+            status = override_status;
+        }
+        query = Some(Cow::Owned(rest));
+    }
+
+    let mut confirmed = false;
+    if state.options.interstitial
+        && let Some(q) = &query
+    {
+        let (continued, rest) = take_continue(q);
+        confirmed = continued;
+        query = Some(Cow::Owned(rest));
+    }
+
+    let scheduled = matched
+        .rule
+        .schedule
+        .as_ref()
+        .and_then(|s| s.target_at(schedule::now(state.options.timezone)));
+    let target = match (scheduled, &matched.rule.fallback, &matched.rule.rotation) {
+        (Some(scheduled), _, _) => scheduled,
+        (None, Some(fallback), _) if state.health.is_down(&matched.rule.target) => {
+            // A maintenance detour must not be cached as the link's new home
+            if status == 301 {
+                status = 302;
+            }
+            fallback.as_str()
+        }
+        (None, _, Some(rotation)) => rotation.next(),
+        _ => matched.target.as_ref(),
+    };
+
+    let show_interstitial = state.options.interstitial
+        && status != 410
+        && !confirmed
+        && is_external(target, headers, state.options.base_url.as_deref());
+    if show_interstitial {
+        return Ok(interstitial(target, query.as_deref()));
+    }
+
+    let target = match query.filter(|q| state.options.forward_query && !q.is_empty()) {
+        Some(query) => Cow::Owned(append_query(target, &query)),
+        None => Cow::Borrowed(target),
+    };
+
+    if let Some(webhook) = &state.webhook
+        && status != 410
+    {
+        let event = RedirectEvent {
+            slug: matched.slug.to_string(),
+            target: target.to_string(),
+            status,
+            ts: stats::unix_now(),
+            ua: headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            ip: client_ip(headers, state.options.trust_proxy_count).map(str::to_string),
+        };
+        if !webhook.send(event) {
+            state.stats.record_webhook_drop();
+        }
+    }
+
+    rule_response(matched.rule, &target, status, &state.options)
+}
+
+const CONTINUE_PARAM: &str = "continue";
+
+/// Splits `continue=1` off the query, returning whether it was there and the
+/// remaining query
+fn take_continue(query: &str) -> (bool, String) {
+    let mut continued = false;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|pair| match pair.split_once('=') {
+            Some((CONTINUE_PARAM, value)) => {
+                continued |= value == "1";
+                false
+            }
+            _ => true,
+        })
+        .collect();
+
+    (continued, rest.join("&"))
+}
+
+/// Whether `target` leaves this service: an absolute URL for a host other than
+/// the request's `Host` and `--base-url`'s
+fn is_external(target: &str, headers: &HeaderMap, base_url: Option<&str>) -> bool {
+    let Some(host) = reqwest::Url::parse(target)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    else {
+        return false;
+    };
+    let base_host = base_url
+        .and_then(|url| reqwest::Url::parse(url).ok())
+        .and_then(|url| url.host_str().map(str::to_string));
+    !host_matches(&host, headers) && base_host.is_none_or(|base| !base.eq_ignore_ascii_case(&host))
+}
+
+/// The `--interstitial` page for `target`. The continue link keeps the rest of
+/// the query, so `--forward-query` still passes it on.
+fn interstitial(target: &str, query: Option<&str>) -> Response {
+    let continue_href = match query.filter(|q| !q.is_empty()) {
+        Some(query) => format!("?{CONTINUE_PARAM}=1&{query}"),
+        None => format!("?{CONTINUE_PARAM}=1"),
+    };
+    let page = format!(
+        "<!doctype html>\n\
+         <html><head><meta charset=\"utf-8\"><meta name=\"robots\" content=\"noindex\">\
+         <title>Leaving this site</title></head>\n\
+         <body><p>You are leaving and going to <strong>{target}</strong></p>\n\
+         <p><a href=\"{href}\">Continue</a></p></body></html>\n",
+        target = escape_html(target),
+        href = escape_html(&continue_href),
+    );
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        page,
+    )
+        .into_response()
+}
+
+const STATUS_OVERRIDE_PARAM: &str = "__status";
+
+/// Splits a valid `__status=301|302` parameter off the query, returning it
+/// and the remaining query
+fn take_status_override(query: &str) -> (Option<u16>, String) {
+    let mut status = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|pair| match pair.split_once('=') {
+            Some((STATUS_OVERRIDE_PARAM, value)) => {
+                status = value.parse().ok().filter(|s| matches!(s, 301 | 302));
+                false
+            }
+            _ => true,
+        })
+        .collect();
+
+    (status, rest.join("&"))
+}
+
+/// Merges `query` into the target's query string, keeping any `#fragment` last
+fn append_query(target: &str, query: &str) -> String {
+    let (base, fragment) = match target.split_once('#') {
+        Some((base, fragment)) => (base, Some(fragment)),
+        None => (target, None),
+    };
+
+    let separator = if !base.contains('?') {
+        "?"
+    } else if base.ends_with('?') || base.ends_with('&') {
+        ""
+    } else {
+        "&"
     };
 
-    Ok(Response::builder()
-        .status(actual_status)
-        .header(header::LOCATION, target)
-        .body(axum::body::Body::empty())
-        .unwrap())
-}
+    let mut url = format!("{base}{separator}{query}");
+    if let Some(fragment) = fragment {
+        url.push('#');
+        url.push_str(fragment);
+    }
+    url
+}
+
+/// Build information for deployment verification
+async fn handle_version(
+    axum::extract::State(AppState { options, .. }): axum::extract::State<AppState>,
+    headers: HeaderMap,
+) -> axum::Json<serde_json::Value> {
+    axum::Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": option_env!("DSLF_GIT_SHA"),
+        "build_timestamp": env!("DSLF_BUILD_TIMESTAMP").parse::<u64>().ok(),
+        "base_url": public_base_url(&options, &headers),
+    }))
+}
+
+/// The public origin: `--base-url` when set, otherwise derived from the
+/// request's `Host` (and `X-Forwarded-Proto`, defaulting to http)
+fn public_base_url(options: &AppOptions, headers: &HeaderMap) -> Option<String> {
+    if let Some(base_url) = &options.base_url {
+        return Some(base_url.trim_end_matches('/').to_string());
+    }
+
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let host = header(header::HOST)?;
+    let scheme = header(HeaderName::from_static("x-forwarded-proto")).unwrap_or("http");
+    Some(format!("{scheme}://{host}"))
+}
+
+/// Checks the `Authorization: Bearer <token>` header against `--admin-token`
+fn is_admin(options: &AppOptions, headers: &HeaderMap) -> bool {
+    let Some(expected) = &options.admin_token else {
+        return false;
+    };
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    // Compare without short-circuiting on the first differing byte
+    provided.is_some_and(|provided| {
+        provided.len() == expected.len()
+            && provided
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    })
+}
+
+/// Reloads the rules from the config file, answering with what changed
+async fn handle_reload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+) -> Result<axum::Json<RuleDiff>, (StatusCode, String)> {
+    if !is_admin(&state.options, &headers) {
+        return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()));
+    }
+
+    let diff =
+        reload_rules(&state).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if state.options.logging {
+        println!(
+            "Reloaded redirect rules ({} added, {} removed, {} modified)",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.modified.len()
+        );
+    }
+
+    Ok(axum::Json(diff))
+}
+
+/// Request totals, uptime and per-slug hits since boot
+async fn handle_stats(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+) -> Result<axum::Json<StatsSnapshot>, StatusCode> {
+    if !is_admin(&state.options, &headers) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(axum::Json(state.stats.snapshot()))
+}
+
+/// The effective [`AppOptions`], for checking a deploy picked up its flags
+async fn handle_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state.options, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    axum::Json(state.options.as_ref()).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupQuery {
+    keyword: String,
+}
+
+/// A rule found by `GET /_lookup`
+#[derive(Debug, Serialize)]
+struct LookupMatch {
+    slug: String,
+    target: String,
+    status: u16,
+    keyword: String,
+}
+
+/// Rules whose `keyword` column matches, ignoring case, in lookup order
+async fn handle_lookup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(query): axum::extract::Query<LookupQuery>,
+) -> Result<axum::Json<Vec<LookupMatch>>, StatusCode> {
+    if !is_admin(&state.options, &headers) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let wanted = query.keyword.trim().to_lowercase();
+    let rules = state.rules.load();
+    let matches = rules
+        .entries()
+        .into_iter()
+        .filter_map(|(slug, _, rule)| {
+            let keyword = rule.keyword.as_ref()?;
+            (keyword.to_lowercase() == wanted).then(|| LookupMatch {
+                slug: slug.into_owned(),
+                target: rule.target.clone(),
+                status: rule.status,
+                keyword: keyword.clone(),
+            })
+        })
+        .collect();
+    Ok(axum::Json(matches))
+}
+
+/// `POST /_ready/false` takes the instance out of `/readyz` (e.g. to drain it
+/// during a deploy), `POST /_ready/true` puts it back
+async fn handle_set_ready(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    Path(ready): Path<bool>,
+) -> StatusCode {
+    if !is_admin(&state.options, &headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    state.ready.store(ready, Ordering::Relaxed);
+    if state.options.logging {
+        println!("Readiness set to {ready}");
+    }
+    StatusCode::NO_CONTENT
+}
+
+/// Starts the same graceful shutdown as SIGTERM, for `--shutdown-endpoint`
+async fn handle_shutdown(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if !is_admin(&state.options, &headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    // `notify_one` keeps the permit if the server isn't waiting yet
+    state.shutdown.notify_one();
+    StatusCode::ACCEPTED
+}
+
+/// Load balancer readiness probe; redirects are served either way
+async fn handle_readyz(axum::extract::State(state): axum::extract::State<AppState>) -> Response {
+    if state.ready.load(Ordering::Relaxed) {
+        (StatusCode::OK, "ready").into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready").into_response()
+    }
+}
+
+/// The request as dslf received it, for `--debug`
+async fn handle_echo(
+    method: Method,
+    uri: axum::http::Uri,
+    headers: HeaderMap,
+) -> axum::Json<serde_json::Value> {
+    let mut echoed: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (name, value) in &headers {
+        echoed
+            .entry(name.as_str())
+            .or_default()
+            .push(String::from_utf8_lossy(value.as_bytes()).into_owned());
+    }
+
+    axum::Json(serde_json::json!({
+        "method": method.as_str(),
+        "path": uri.path(),
+        "query": uri.query(),
+        "headers": echoed,
+    }))
+}
+
+async fn handle_metrics(axum::extract::State(state): axum::extract::State<AppState>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&state),
+    )
+        .into_response()
+}
+
+/// The [`Stats`] metrics, plus `--resolve-cache` hits when enabled
+fn render_metrics(state: &AppState) -> String {
+    let mut metrics = state.stats.render_prometheus();
+    if let Some(cache) = &state.rules.load().resolve_cache {
+        metrics.push_str(&format!(
+            "# HELP dslf_resolve_cache_hits_total Lookups answered by --resolve-cache since the last reload\n\
+             # TYPE dslf_resolve_cache_hits_total counter\n\
+             dslf_resolve_cache_hits_total {}\n",
+            cache.hits()
+        ));
+    }
+    metrics
+}
+
+/// Loads the config again and swaps it in. On error the live rules are kept.
+/// Either way the attempt is counted in the reload metrics.
+fn reload_rules(state: &AppState) -> Result<RuleDiff, Box<dyn std::error::Error>> {
+    let result = swap_rules(state);
+    state.stats.record_reload(result.is_ok());
+    result
+}
+
+/// All or nothing: the rules are only swapped once everything they depend on
+/// has loaded. Nothing is ever written back to the config.
+fn swap_rules(state: &AppState) -> Result<RuleDiff, Box<dyn std::error::Error>> {
+    let config = state.config.as_ref().ok_or("No config file to reload")?;
+    if config.path == "-" {
+        return Err("Rules read from stdin cannot be reloaded".into());
+    }
+
+    let rules = load_redirect_rules(&config.path, &config.load_options)?;
+    state.load_not_found_page()?;
+    let diff = state.rules.load().diff(&rules);
+    state.rules.store(Arc::new(rules));
+
+    Ok(diff)
+}
+
+/// Whether `ServeDir` would answer `path` from `dir`: a file, or a directory
+/// with an `index.html`
+async fn static_file_exists(dir: &std::path::Path, path: &str) -> bool {
+    let relative = path.trim_start_matches('/');
+    if relative.split('/').any(|segment| segment == "..") {
+        return false;
+    }
+    let candidate = dir.join(relative);
+    match tokio::fs::metadata(&candidate).await {
+        Ok(metadata) if metadata.is_dir() => tokio::fs::metadata(candidate.join("index.html"))
+            .await
+            .is_ok_and(|index| index.is_file()),
+        Ok(metadata) => metadata.is_file(),
+        Err(_) => false,
+    }
+}
+
+/// Redirects are only served for GET (and HEAD, which axum derives from GET)
+fn method_not_allowed() -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(header::ALLOW, "GET, HEAD")],
+    )
+        .into_response()
+}
+
+const DEFAULT_GONE_MESSAGE: &str = "This link is no longer available.";
+
+/// A retired rule's `note`, passed from the handler to `logging_middleware`
+#[derive(Debug, Clone)]
+struct RuleNote(String);
+
+/// Builds the response for a matched rule: a redirect with `status`, or a 410
+/// with its message and note
+fn rule_response(
+    rule: &Rule,
+    target: &str,
+    status: u16,
+    options: &AppOptions,
+) -> Result<Response, StatusCode> {
+    if status == 410 {
+        let message = rule
+            .message
+            .as_deref()
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or(DEFAULT_GONE_MESSAGE);
+        let body = match &rule.note {
+            Some(note) => format!("{message}\nReason: {note}"),
+            None => message.to_string(),
+        };
+        let mut response = (
+            StatusCode::GONE,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            body,
+        )
+            .into_response();
+        if let Some(note) = &rule.note {
+            response.extensions_mut().insert(RuleNote(note.clone()));
+        }
+        return Ok(response);
+    }
+
+    let mut response = create_redirect_response(
+        target,
+        status,
+        options.modern && !rule.literal,
+        options.cache_max_age,
+    )?;
+    if options.noindex {
+        response.headers_mut().insert(
+            HeaderName::from_static("x-robots-tag"),
+            HeaderValue::from_static("noindex"),
+        );
+    }
+    if options.preconnect
+        && let Some(link) = preconnect_link(target)
+    {
+        response.headers_mut().insert(header::LINK, link);
+    }
+    Ok(response)
+}
+
+/// `<origin>; rel=preconnect` for an absolute http(s) target; relative
+/// targets stay on this host and need no hint
+fn preconnect_link(target: &str) -> Option<HeaderValue> {
+    let url = reqwest::Url::parse(target).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    let origin = url.origin().ascii_serialization();
+    HeaderValue::from_str(&format!("<{origin}>; rel=preconnect")).ok()
+}
+
+/// A bodiless redirect. With `cache_max_age`, permanent ones also carry
+/// `Cache-Control`, plus `Date` and `Age: 0` so caches measure freshness from
+/// when dslf answered.
+fn create_redirect_response(
+    target: &str,
+    status: u16,
+    modern: bool,
+    cache_max_age: Option<u64>,
+) -> Result<Response, StatusCode> {
+    let actual_status = match (status, modern) {
+        (301, false) => StatusCode::MOVED_PERMANENTLY, // 301
+        (301, true) => StatusCode::PERMANENT_REDIRECT, // 308
+        (302, false) => StatusCode::FOUND,             // 302
+        (302, true) => StatusCode::TEMPORARY_REDIRECT, // 307
+        _ => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let mut response = Response::builder()
+        .status(actual_status)
+        .header(header::LOCATION, target)
+        .header(header::CONTENT_LENGTH, 0);
+    if let Some(max_age) = cache_max_age
+        && status == 301
+    {
+        response = response
+            .header(header::CACHE_CONTROL, format!("public, max-age={max_age}"))
+            .header(header::DATE, http_date(chrono::Utc::now()))
+            .header(header::AGE, 0);
+    }
+    response
+        .body(axum::body::Body::empty())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT` (RFC 9110 §5.6.7)
+fn http_date(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Loads rules from a CSV file (JSON Lines for `.jsonl`, stdin for `-`) and
+/// compiles them, see [`build_matcher`] for the wildcard (`*` suffix) and
+/// regex (`~` prefix) url syntax.
+fn load_redirect_rules(
+    file_path: &str,
+    load_options: &LoadOptions,
+) -> Result<Matcher, Box<dyn std::error::Error>> {
+    compile_rules(read_redirect_rules(file_path, load_options)?, load_options)
+}
+
+/// [`build_matcher`] plus the `--resolve-cache`, if enabled
+fn compile_rules(
+    parsed: Vec<ParsedRule>,
+    load_options: &LoadOptions,
+) -> Result<Matcher, Box<dyn std::error::Error>> {
+    let matcher = build_matcher(parsed)?;
+    Ok(match load_options.resolve_cache {
+        Some(capacity) => matcher.with_resolve_cache(capacity),
+        None => matcher,
+    })
+}
+
+/// Reads and validates the rows of the config, in file order. A directory is
+/// read as the concatenation of its `.csv` and `.jsonl` files sorted by name,
+/// so later files override earlier ones.
+fn read_redirect_rules(
+    file_path: &str,
+    load_options: &LoadOptions,
+) -> Result<Vec<ParsedRule>, Box<dyn std::error::Error>> {
+    let is_dir = std::path::Path::new(file_path).is_dir();
+    if is_dir && load_options.config_checksum.is_some() {
+        return Err("--config-checksum needs a single config file, not a directory".into());
+    }
+
+    let parsed = if is_dir {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(file_path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        files.retain(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext == "csv" || ext == "jsonl")
+        });
+        files.sort();
+        if files.is_empty() {
+            return Err(
+                format!("{file_path} is a directory with no .csv or .jsonl rule files").into(),
+            );
+        }
+
+        let mut parsed = Vec::new();
+        for path in files {
+            let path = path.to_string_lossy();
+            let rules =
+                read_config_file(&path, load_options).map_err(|e| format!("{path}: {e}"))?;
+            parsed.extend(rules);
+        }
+        parsed
+    } else {
+        read_config_file(file_path, load_options)?
+    };
+
+    let mut parsed = parsed;
+    let collisions = apply_slug_prefix_map(&mut parsed, &load_options.slug_prefix_map);
+    if load_options.strict {
+        for collision in collisions {
+            eprintln!("Warning: {collision}");
+        }
+    }
+
+    if let Some(max_rules) = load_options.max_rules
+        && parsed.len() > max_rules
+    {
+        return Err(format!(
+            "Config has {count} rules, more than the --max-rules limit of {max_rules}",
+            count = parsed.len()
+        )
+        .into());
+    }
+    Ok(parsed)
+}
+
+/// Rewrites slugs under the first matching `old` prefix to `new`. A prefix
+/// matches whole path segments, so `/old` covers `/old/x` but not `/older`.
+/// Returns a warning for each rewritten slug that now collides with another row.
+fn apply_slug_prefix_map(parsed: &mut [ParsedRule], map: &[(String, String)]) -> Vec<String> {
+    if map.is_empty() {
+        return Vec::new();
+    }
+
+    let mut rewritten = Vec::new();
+    for (index, ParsedRule { url, .. }) in parsed.iter_mut().enumerate() {
+        let mapped = map.iter().find_map(|(old, new)| {
+            let rest = url.strip_prefix(old.as_str())?;
+            let boundary = rest.is_empty() || rest.starts_with('/') || old.ends_with('/');
+            boundary.then(|| format!("{new}{rest}"))
+        });
+        if let Some(mapped) = mapped {
+            rewritten.push((index, std::mem::replace(url, mapped)));
+        }
+    }
+
+    let mut counts: HashMap<_, usize> = HashMap::new();
+    for p in parsed.iter() {
+        *counts
+            .entry((p.url.as_str(), &p.rule.when_header, &p.rule.host))
+            .or_default() += 1;
+    }
+    rewritten
+        .into_iter()
+        .filter_map(|(index, old)| {
+            let p = &parsed[index];
+            (counts[&(p.url.as_str(), &p.rule.when_header, &p.rule.host)] > 1).then(|| {
+                format!(
+                    "{old} is mapped to {new}, which is defined more than once",
+                    new = p.url
+                )
+            })
+        })
+        .collect()
+}
+
+fn read_config_file(
+    file_path: &str,
+    load_options: &LoadOptions,
+) -> Result<Vec<ParsedRule>, Box<dyn std::error::Error>> {
+    let file_content = read_config(file_path, io::stdin())?;
+    if let Some(expected) = &load_options.config_checksum {
+        let actual = sha256_hex(file_content.as_bytes());
+        if actual != *expected {
+            return Err(
+                format!("Config checksum mismatch: expected {expected}, got {actual}").into(),
+            );
+        }
+    }
+    if file_path.ends_with(".jsonl") {
+        parse_jsonl_rules(&file_content, load_options)
+    } else {
+        parse_redirect_rules(&file_content, load_options)
+    }
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, content)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Reads the config file, or all of `stdin` when the path is `-`
+fn read_config(file_path: &str, mut stdin: impl io::Read) -> io::Result<String> {
+    if file_path == "-" {
+        let mut content = String::new();
+        stdin.read_to_string(&mut content)?;
+        Ok(content)
+    } else {
+        std::fs::read_to_string(file_path)
+    }
+}
+
+/// Parses and validates CSV rows without compiling them
+fn parse_redirect_rules(
+    file_content: &str,
+    load_options: &LoadOptions,
+) -> Result<Vec<ParsedRule>, Box<dyn std::error::Error>> {
+    // Filter out empty lines and lines with only whitespace
+    let filtered_content: String = file_content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| format!("{line}\n"))
+        .collect();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(filtered_content.as_bytes());
+    let mut parsed = Vec::new();
+
+    for result in reader.deserialize() {
+        parsed.push(parse_row(result?, load_options)?);
+    }
+
+    Ok(parsed)
+}
+
+/// Parses one `{"url","target","status"}` object per line, as written by
+/// `import --format jsonl`. Blank lines and `#` comments are skipped.
+fn parse_jsonl_rules(
+    file_content: &str,
+    load_options: &LoadOptions,
+) -> Result<Vec<ParsedRule>, Box<dyn std::error::Error>> {
+    let mut parsed = Vec::new();
+
+    for (index, line) in file_content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let rule: RedirectRule = serde_json::from_str(line)
+            .map_err(|e| format!("Invalid JSON on line {}: {e}", index + 1))?;
+        parsed.push(parse_row(rule, load_options)?);
+    }
+
+    Ok(parsed)
+}
+
+/// Splits a `go.example.com/x` slug into its host and path. Only a leading
+/// segment that looks like an authority (a dot or a port) counts as a host.
+fn split_host_slug(url: &str) -> Option<(String, String)> {
+    if url.starts_with('/') || url.starts_with('~') {
+        return None;
+    }
+    let (authority, path) = url.split_at(url.find('/')?);
+    let valid = !authority.is_empty()
+        && (authority.contains('.') || authority.contains(':'))
+        && authority
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'));
+    valid.then(|| (authority.to_ascii_lowercase(), path.to_string()))
+}
+
+/// Lowercases the scheme and host of an absolute URL, leaving userinfo, path,
+/// query and fragment exactly as written. Anything that isn't a URL with a
+/// host (such as an `@alias`) is returned unchanged.
+fn lowercase_host(target: &str) -> String {
+    let has_host = reqwest::Url::parse(target).is_ok_and(|url| url.host_str().is_some());
+    let Some((scheme, rest)) = target.split_once("://").filter(|_| has_host) else {
+        return target.to_string();
+    };
+
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, tail) = rest.split_at(authority_end);
+    let (userinfo, host) = match authority.rsplit_once('@') {
+        Some((userinfo, host)) => (&authority[..=userinfo.len()], host),
+        None => ("", authority),
+    };
+
+    format!(
+        "{}://{userinfo}{}{tail}",
+        scheme.to_ascii_lowercase(),
+        host.to_lowercase()
+    )
+}
+
+/// Validates a config row and turns it into a [`ParsedRule`]
+fn parse_row(
+    mut rule: RedirectRule,
+    load_options: &LoadOptions,
+) -> Result<ParsedRule, Box<dyn std::error::Error>> {
+    let host = split_host_slug(&rule.url).map(|(host, path)| {
+        rule.url = path;
+        host
+    });
+
+    // Usually a row missing its first cell. `--fix` below maps it to the root.
+    if rule.url.trim().is_empty() {
+        rule.url.clear();
+        if load_options.deny_empty_slug {
+            return Err(format!(
+                "Row for {} has an empty slug; use / for the root",
+                rule.target
+            )
+            .into());
+        }
+    }
+
+    // Requests always start with `/`, so such slugs would silently never match
+    if !rule.url.starts_with('/') && !rule.url.starts_with('~') {
+        if load_options.fix {
+            rule.url.insert(0, '/');
+        } else if load_options.strict {
+            return Err(format!("Slug {url} must start with '/'", url = rule.url).into());
+        }
+    }
+
+    let status = rule.status.unwrap_or(load_options.default_status);
+
+    // Validate status code
+    if !matches!(status, 301 | 302 | 410) {
+        return Err(format!(
+            "Invalid status code: {status}. Must be 301 or 302 (or 410 for retired links)"
+        )
+        .into());
+    }
+
+    // Blank fallback cells mean no fallback
+    rule.fallback = rule.fallback.filter(|f| !f.is_empty());
+
+    let mut schedule = rule
+        .schedule
+        .as_deref()
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| Schedule::parse(s).map_err(|e| format!("Invalid schedule for {}: {e}", rule.url)))
+        .transpose()?;
+
+    if load_options.lowercase_host {
+        rule.target = lowercase_host(&rule.target);
+        rule.fallback = rule.fallback.as_deref().map(lowercase_host);
+        for target in schedule.iter_mut().flat_map(Schedule::targets_mut) {
+            *target = lowercase_host(target);
+        }
+    }
+
+    // `|` can't appear unescaped in a URL, so it safely separates targets
+    let rotate: Vec<String> = rule
+        .rotate
+        .iter()
+        .flat_map(|targets| targets.split('|'))
+        .map(|target| target.trim().to_string())
+        .filter(|target| !target.is_empty())
+        .collect();
+    let rotate = if rotate.is_empty() {
+        None
+    } else if rule.url.ends_with('*') || rule.url.starts_with('~') || rule.url.contains('{') {
+        return Err(format!(
+            "Rotating targets need an exact slug, {} isn't one",
+            rule.url
+        )
+        .into());
+    } else {
+        Some(rotate)
+    };
+
+    // Targets end up in the Location header
+    let rotated = rotate.iter().flatten();
+    let scheduled = schedule.iter().flat_map(Schedule::targets);
+    for target in std::iter::once(&rule.target)
+        .chain(&rule.fallback)
+        .chain(rotated)
+        .chain(scheduled)
+    {
+        if target.len() > load_options.max_target_len {
+            return Err(format!(
+                "Target for {url} is {len} bytes, longer than the {max} byte limit",
+                url = rule.url,
+                len = target.len(),
+                max = load_options.max_target_len
+            )
+            .into());
+        }
+        if let Some((at, c)) = target.char_indices().find(|(_, c)| c.is_control()) {
+            let url = &rule.url;
+            return Err(match c {
+                // Usually a quoted CSV field spanning lines in a bad export
+                '\r' | '\n' => format!(
+                    "Target for {url} contains a line break at byte {at}; targets must fit on one line"
+                ),
+                _ => format!(
+                    "Target for {url} contains control character U+{:04X} at byte {at}",
+                    u32::from(c)
+                ),
+            }
+            .into());
+        }
+    }
+
+    let when_header = rule
+        .when_header
+        .as_deref()
+        .map(parse_header_condition)
+        .transpose()?;
+    Ok(ParsedRule {
+        url: rule.url,
+        rule: Rule {
+            when_header,
+            message: rule.message,
+            note: rule
+                .note
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty()),
+            fallback: rule.fallback,
+            literal: rule.literal.unwrap_or(false),
+            host,
+            schedule,
+            rotation: rotate.map(|rotate| {
+                Rotation::new(std::iter::once(rule.target.clone()).chain(rotate).collect())
+            }),
+            keyword: rule
+                .keyword
+                .map(|k| k.trim().to_string())
+                .filter(|k| !k.is_empty()),
+            allow_referer: rule
+                .allow_referer
+                .iter()
+                .flat_map(|spec| spec.split(','))
+                .map(|domain| domain.trim().to_ascii_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+            ..Rule::new(rule.target, status)
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{RegexRule, add_rule};
+    use axum::http::HeaderName;
+    use axum::http::StatusCode;
+    use regex::Regex;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_load_redirect_rules() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/old,https://example.com/new,301").unwrap();
+        writeln!(temp_file, "/temp,https://example.com/temp,302").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            rules.exact.get("/old"),
+            Some(&vec![Rule::new("https://example.com/new", 301)])
+        );
+        assert_eq!(
+            rules.exact.get("/temp"),
+            Some(&vec![Rule::new("https://example.com/temp", 302)])
+        );
+    }
+
+    #[test]
+    fn test_invalid_status_code() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/invalid,https://example.com,200").unwrap();
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_load_redirect_rules_file_not_found() {
+        let result = load_redirect_rules("nonexistent.csv", &LoadOptions::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_load_redirect_rules_empty_file() {
+        let temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file.as_file(), "url,target,status").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(rules.len(), 0);
+    }
+
+    #[test]
+    fn test_load_redirect_rules_invalid_csv() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/invalid,https://example.com").unwrap(); // Missing status column
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_301() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/old".to_string(),
+            vec![Rule::new("https://example.com/new", 301)],
+        );
+
+        let result = handle_redirect(
+            axum::extract::Path("old".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        // The redirect is created successfully - we can't easily test the exact type
+        // without more complex setup, but we know it worked based on the Ok result
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_302() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/temp".to_string(),
+            vec![Rule::new("https://example.com/temp", 302)],
+        );
+
+        let result = handle_redirect(
+            axum::extract::Path("temp".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        // The redirect is created successfully - we can't easily test the exact type
+        // without more complex setup, but we know it worked based on the Ok result
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_not_found() {
+        let rules = HashMap::new();
+
+        let result = handle_redirect(
+            axum::extract::Path("nonexistent".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_invalid_status() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/invalid".to_string(),
+            vec![Rule::new("https://example.com", 200)],
+        );
+
+        let result = handle_redirect(
+            axum::extract::Path("invalid".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_redirect_rule_deserialize() {
+        let csv_data = "url,target,status\n/test,https://example.com,301";
+        let mut reader = csv::Reader::from_reader(csv_data.as_bytes());
+        let mut rules = Vec::new();
+
+        for result in reader.deserialize() {
+            let rule: RedirectRule = result.unwrap();
+            rules.push(rule);
+        }
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].url, "/test");
+        assert_eq!(rules[0].target, "https://example.com");
+        assert_eq!(rules[0].status, Some(301));
+    }
+
+    #[test]
+    fn test_multiple_rules_same_url() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/same,https://example.com/first,301").unwrap();
+        writeln!(temp_file, "/same,https://example.com/second,302").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        // Should have only one entry (the last one overwrites the first)
+        assert_eq!(rules.len(), 1);
+        assert_eq!(
+            rules.exact.get("/same"),
+            Some(&vec![Rule::new("https://example.com/second", 302)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_server_redirect() {
+        // Create a test CSV file
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/test,https://example.com/redirect,301").unwrap();
+        writeln!(temp_file, "/temp,https://example.com/temp,302").unwrap();
+
+        // Load the rules
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        // Create the app using the new function
+        let app = create_app(AppState::new(rules, AppOptions::default()));
+
+        // Test redirect for /test
+        let request = axum::http::Request::builder()
+            .uri("/test")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let response = tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::MOVED_PERMANENTLY);
+
+        // Test redirect for /temp
+        let request = axum::http::Request::builder()
+            .uri("/temp")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let response = tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::FOUND);
+
+        // Test 404 for unknown path
+        let request = axum::http::Request::builder()
+            .uri("/unknown")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let response = tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_match_rule_agrees_with_router() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(
+            temp_file,
+            "url,target,status\n\
+             /gh,https://github.com,301\n\
+             go.example.com/gh,https://github.com/go,302\n\
+             /docs,https://docs.example.com,301\n\
+             /u/{{user}},https://profiles.example.com/{{user}},302\n\
+             /blog/*,https://blog.example.com/*,301\n\
+             ~^/p/(\\d+)$,https://example.com/posts/$1,302"
+        )
+        .unwrap();
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        for hierarchical in [false, true] {
+            let options = AppOptions {
+                hierarchical,
+                ..Default::default()
+            };
+            let app = create_app(AppState::new(rules.clone(), options));
+            for (host, path) in [
+                ("localhost", "/gh"),
+                ("go.example.com", "/gh"),
+                ("localhost", "/gh/"),
+                ("localhost", "/docs/intro/setup"),
+                ("localhost", "/u/alice"),
+                ("localhost", "/blog/2024/post"),
+                ("localhost", "/p/42"),
+                ("localhost", "/p/abc"),
+                ("localhost", "/missing"),
+            ] {
+                let request = Request::builder()
+                    .uri(path)
+                    .header(header::HOST, host)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = tower::ServiceExt::oneshot(app.clone(), request)
+                    .await
+                    .unwrap();
+                let routed = response
+                    .headers()
+                    .get(header::LOCATION)
+                    .map(|location| location.to_str().unwrap().to_string());
+
+                let mut headers = HeaderMap::new();
+                headers.insert(header::HOST, host.parse().unwrap());
+                let matched = match_rule(
+                    &rules,
+                    path,
+                    &headers,
+                    MatchOptions {
+                        exact_only: false,
+                        hierarchical,
+                    },
+                );
+                assert_eq!(
+                    matched.map(|m| m.target.into_owned()),
+                    routed,
+                    "{host}{path} with hierarchical={hierarchical}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_integration_method_not_allowed() {
+        use tempfile::TempDir;
+
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/test".to_string(),
+            vec![Rule::new("https://example.com", 301)],
+        );
+
+        let temp_dir = TempDir::new().unwrap();
+        let apps = [
+            create_app(AppState::new(rules.clone().into(), AppOptions::default())),
+            create_app(AppState::new(
+                rules.into(),
+                AppOptions {
+                    static_dir: Some(temp_dir.path().to_path_buf()),
+                    ..Default::default()
+                },
+            )),
+        ];
+
+        for app in apps {
+            let request = axum::http::Request::builder()
+                .method("POST")
+                .uri("/test")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = tower::ServiceExt::oneshot(app.clone(), request)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(response.headers().get("allow").unwrap(), "GET, HEAD");
+
+            // HEAD still redirects
+            let request = axum::http::Request::builder()
+                .method("HEAD")
+                .uri("/test")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_integration_version_endpoint() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/test".to_string(),
+            vec![Rule::new("https://example.com", 301)],
+        );
+
+        let app = create_app(AppState::new(rules.clone().into(), AppOptions::default()));
+        let request = axum::http::Request::builder()
+            .uri("/_version")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["build_timestamp"].is_u64());
+
+        // Custom path, while redirects keep working
+        let app = create_app(AppState::new(
+            rules.into(),
+            AppOptions {
+                version_path: "/internal/version".to_string(),
+                ..Default::default()
+            },
+        ));
+        let request = axum::http::Request::builder()
+            .uri("/internal/version")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = axum::http::Request::builder()
+            .uri("/test")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    }
+
+    #[test]
+    fn test_public_base_url() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "localhost:3000".parse().unwrap());
+
+        // Derived from the request when unset
+        let options = AppOptions::default();
+        let base = public_base_url(&options, &headers).unwrap();
+        assert_eq!(base, "http://localhost:3000");
+
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let base = public_base_url(&options, &headers).unwrap();
+        assert_eq!(base, "https://localhost:3000");
+
+        // --base-url wins over the request
+        let options = AppOptions {
+            base_url: Some("https://go.example.com/".to_string()),
+            ..Default::default()
+        };
+        let base = public_base_url(&options, &headers).unwrap();
+        assert_eq!(base, "https://go.example.com");
+
+        assert_eq!(
+            public_base_url(&AppOptions::default(), &HeaderMap::new()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version_endpoint_base_url() {
+        let options = AppOptions {
+            base_url: Some("https://go.example.com".to_string()),
+            ..Default::default()
+        };
+        let app = create_app(AppState::new(Matcher::default(), options));
+        let request = Request::builder()
+            .uri("/_version")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["base_url"], "https://go.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_promote_after() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/promo".to_string(),
+            vec![Rule::new("https://example.com", 302)],
+        );
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let state = AppState::new(
+            rules.into(),
+            AppOptions {
+                promote_after: Some(2),
+                ..Default::default()
+            },
+        );
+
+        let get = |path: &str| {
+            handle_redirect(
+                Path(path.to_string()),
+                axum::extract::State(state.clone()),
+                HeaderMap::new(),
+                axum::extract::RawQuery(None),
+            )
+        };
+
+        // The first N hits stay temporary, the (N+1)th is promoted
+        assert_eq!(get("promo").await.unwrap().status(), StatusCode::FOUND);
+        assert_eq!(get("promo").await.unwrap().status(), StatusCode::FOUND);
+        assert_eq!(
+            get("promo").await.unwrap().status(),
+            StatusCode::MOVED_PERMANENTLY
+        );
+
+        // Hits are counted per slug
+        assert_eq!(
+            get("gh").await.unwrap().status(),
+            StatusCode::MOVED_PERMANENTLY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_promote_after_modern() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/promo".to_string(),
+            vec![Rule::new("https://example.com", 302)],
+        );
+        let state = AppState::new(
+            rules.into(),
+            AppOptions {
+                modern: true,
+                promote_after: Some(0),
+                ..Default::default()
+            },
+        );
+
+        let response = handle_redirect(
+            Path("promo".to_string()),
+            axum::extract::State(state),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+
+    #[tokio::test]
+    async fn test_literal_status_under_modern() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/legacy".to_string(),
+            vec![Rule {
+                literal: true,
+                ..Rule::new("https://legacy.example.com", 302)
+            }],
+        );
+        rules.insert(
+            "/app".to_string(),
+            vec![Rule::new("https://app.example.com", 302)],
+        );
+        let state = AppState::new(
+            rules.into(),
+            AppOptions {
+                modern: true,
+                ..Default::default()
+            },
+        );
+
+        let status = |path: &str| {
+            let state = state.clone();
+            let path = path.to_string();
+            async move {
+                handle_redirect(
+                    Path(path),
+                    axum::extract::State(state),
+                    HeaderMap::new(),
+                    axum::extract::RawQuery(None),
+                )
+                .await
+                .unwrap()
+                .status()
+            }
+        };
+        assert_eq!(status("legacy").await, StatusCode::FOUND);
+        assert_eq!(status("app").await, StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    #[test]
+    fn test_load_literal_column() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,literal").unwrap();
+        writeln!(temp_file, "/legacy,https://legacy.example.com,302,true").unwrap();
+        writeln!(temp_file, "/app,https://app.example.com,302,").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        assert!(rules.exact["/legacy"][0].literal);
+        assert!(!rules.exact["/app"][0].literal);
+    }
+
+    #[tokio::test]
+    async fn test_no_promotion_by_default() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/promo".to_string(),
+            vec![Rule::new("https://example.com", 302)],
+        );
+        let state = AppState::new(rules.into(), AppOptions::default());
+
+        for _ in 0..5 {
+            let response = handle_redirect(
+                Path("promo".to_string()),
+                axum::extract::State(state.clone()),
+                HeaderMap::new(),
+                axum::extract::RawQuery(None),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::FOUND);
+        }
+    }
+
+    #[test]
+    fn test_reload_rules_diff() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301").unwrap();
+        writeln!(temp_file, "/old,https://old.example.com,301").unwrap();
+        writeln!(temp_file, "/docs,https://docs.example.com,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let load_options = LoadOptions::default();
+        let rules = load_redirect_rules(path, &load_options).unwrap();
+        let state = AppState::new(rules, AppOptions::default()).with_config(path, load_options);
+
+        std::fs::write(
+            path,
+            "url,target,status\n/gh,https://github.com,301\n/docs,https://docs.example.com,302\n/new,https://new.example.com,301\n",
+        )
+        .unwrap();
+
+        let diff = reload_rules(&state).unwrap();
+        assert_eq!(diff.added, vec!["/new"]);
+        assert_eq!(diff.removed, vec!["/old"]);
+        assert_eq!(diff.modified, vec!["/docs"]);
+        assert!(state.rules.load().exact.contains_key("/new"));
+    }
+
+    #[test]
+    fn test_reload_rules_keeps_rules_on_error() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let load_options = LoadOptions::default();
+        let rules = load_redirect_rules(path, &load_options).unwrap();
+        let state = AppState::new(rules, AppOptions::default()).with_config(path, load_options);
+
+        std::fs::write(path, "url,target,status\n/gh,https://github.com,999\n").unwrap();
+        assert!(reload_rules(&state).is_err());
+        assert_eq!(state.rules.load().exact["/gh"][0].status, 301);
+        assert_eq!(state.stats.snapshot().reload_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reload_partial_failure_changes_nothing() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        let page_dir = tempfile::TempDir::new().unwrap();
+        let page = page_dir.path().join("404.html");
+        std::fs::write(&page, "old page").unwrap();
+
+        let load_options = LoadOptions::default();
+        let rules = load_redirect_rules(path, &load_options).unwrap();
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            notfound_page: Some(page.clone()),
+            ..Default::default()
+        };
+        let state = AppState::new(rules, options).with_config(path, load_options);
+        state.load_not_found_page().unwrap();
+        let app = create_app(state.clone());
+
+        // The new rules parse, but the page they'd be served with is gone
+        std::fs::write(path, "url,target,status\n/gh,https://example.com,302\n").unwrap();
+        std::fs::remove_file(&page).unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/_reload")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert_eq!(
+            state.rules.load().exact["/gh"][0].target,
+            "https://github.com"
+        );
+        assert_eq!(state.not_found_page.load().as_deref().unwrap(), "old page");
+        assert_eq!(state.stats.snapshot().reload_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_cache_cleared_on_reload() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "~^/blog/(.+)$,https://old.example.com/$1,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let load_options = LoadOptions {
+            resolve_cache: Some(16),
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(path, &load_options).unwrap();
+        let state = AppState::new(rules, AppOptions::default()).with_config(path, load_options);
+        let app = create_app(state.clone());
+        let location = |app: Router| async move {
+            let request = Request::builder()
+                .uri("/blog/hello")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            response.headers()[header::LOCATION].clone()
+        };
+
+        assert_eq!(location(app.clone()).await, "https://old.example.com/hello");
+        assert_eq!(location(app.clone()).await, "https://old.example.com/hello");
+        let hits = |state: &AppState| state.rules.load().resolve_cache.as_ref().unwrap().hits();
+        assert_eq!(hits(&state), 1);
+
+        std::fs::write(
+            path,
+            "url,target,status\n~^/blog/(.+)$,https://new.example.com/$1,301\n",
+        )
+        .unwrap();
+        reload_rules(&state).unwrap();
+        assert_eq!(location(app.clone()).await, "https://new.example.com/hello");
+        assert_eq!(hits(&state), 0);
+        assert!(render_metrics(&state).contains("\ndslf_resolve_cache_hits_total 0\n"));
+        assert_eq!(location(app).await, "https://new.example.com/hello");
+        assert_eq!(hits(&state), 1);
+    }
+
+    #[tokio::test]
+    async fn test_echo_endpoint() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/_echo".to_string(),
+            vec![Rule::new("https://example.com", 301)],
+        );
+        let app = |debug| {
+            create_app(AppState::new(
+                rules.clone().into(),
+                AppOptions {
+                    debug,
+                    ..Default::default()
+                },
+            ))
+        };
+        let request = || {
+            Request::builder()
+                .uri("/_echo?a=1")
+                .header("x-forwarded-for", "203.0.113.7")
+                .header("x-custom", "one")
+                .header("x-custom", "two")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app(true).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["method"], "GET");
+        assert_eq!(json["path"], "/_echo");
+        assert_eq!(json["query"], "a=1");
+        assert_eq!(json["headers"]["x-forwarded-for"][0], "203.0.113.7");
+        assert_eq!(
+            json["headers"]["x-custom"],
+            serde_json::json!(["one", "two"])
+        );
+
+        // Without --debug the path is left to the rules
+        let response = app(false).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let options = AppOptions {
+            metrics: true,
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
+        let state = AppState::new(rules, options).with_config(path, LoadOptions::default());
+        let app = create_app(state.clone());
+
+        std::fs::write(path, "not,a,valid\nconfig").unwrap();
+        assert!(reload_rules(&state).is_err());
+
+        let request = Request::builder().uri("/gh").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("\ndslf_reload_failures_total 1\n"), "{body}");
+        assert!(body.contains("\ndslf_redirects_total 1\n"), "{body}");
+        assert!(body.contains("dslf_last_reload_success_timestamp_seconds "));
+
+        // Not mounted unless enabled
+        let app = create_app(AppState::new(Matcher::default(), AppOptions::default()));
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reload_endpoint_requires_token() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
+        let state = AppState::new(rules, options).with_config(path, LoadOptions::default());
+        let app = create_app(state);
+
+        let request = |token: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/_reload")
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.oneshot(request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "added": [], "removed": [], "modified": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readiness_toggle() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let app = create_app(AppState::new(rules.into(), options));
+
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let set_ready = |ready: &str, token: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri(format!("/_ready/{ready}"))
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(get("/readyz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(set_ready("false", "wrong"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(get("/readyz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(set_ready("false", "secret"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app.clone().oneshot(get("/readyz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Draining doesn't stop redirects
+        let response = app.clone().oneshot(get("/gh")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+        let response = app
+            .clone()
+            .oneshot(set_ready("true", "secret"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app.oneshot(get("/readyz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_endpoint() {
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            shutdown_endpoint: true,
+            ..Default::default()
+        };
+        let state = AppState::new(Matcher::default(), options);
+        let shutdown = state.shutdown.clone();
+        let app = create_app(state);
+
+        let request = |token: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/_shutdown")
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let pending = tokio::time::timeout(Duration::from_millis(50), shutdown_signal(&shutdown));
+        assert!(pending.await.is_err());
+
+        let response = app.oneshot(request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        tokio::time::timeout(Duration::from_secs(1), shutdown_signal(&shutdown))
+            .await
+            .expect("shutdown future should resolve");
+
+        // Not mounted without --shutdown-endpoint
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let app = create_app(AppState::new(Matcher::default(), options));
+        let response = app.oneshot(request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_config_endpoint() {
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            modern: true,
+            request_timeout: Some(Duration::from_millis(1500)),
+            response_headers: vec![(
+                HeaderName::from_static("x-frame-options"),
+                HeaderValue::from_static("DENY"),
+            )],
+            timezone: Some(chrono_tz::Europe::Stockholm),
+            ..Default::default()
+        };
+        let app = create_app(AppState::new(Matcher::default(), options));
+
+        let request = |token: &str| {
+            Request::builder()
+                .uri("/_config")
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.oneshot(request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert!(!String::from_utf8_lossy(&body).contains("secret"));
+
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["admin_token"], "[redacted]");
+        assert_eq!(json["modern"], true);
+        assert_eq!(json["logging"], false);
+        assert_eq!(json["version_path"], "/_version");
+        assert_eq!(json["request_timeout_secs"], 1.5);
+        assert_eq!(
+            json["response_headers"],
+            serde_json::json!(["x-frame-options: DENY"])
+        );
+        assert_eq!(json["log_format"], "text");
+        assert_eq!(json["timezone"], "Europe/Stockholm");
+    }
+
+    #[tokio::test]
+    async fn test_lookup_by_keyword() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,keyword").unwrap();
+        writeln!(
+            temp_file,
+            "/spring,https://example.com/sale,302,Spring Sale"
+        )
+        .unwrap();
+        writeln!(temp_file, "/docs/*,https://docs.example.com,301,docs").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301,").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
+        assert_eq!(rules.exact["/gh"][0].keyword, None);
+
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let app = create_app(AppState::new(rules, options));
+        let lookup = |keyword: &str| {
+            Request::builder()
+                .uri(format!("/_lookup?keyword={keyword}"))
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let json = |response: Response| async {
+            let body = http_body_util::BodyExt::collect(response.into_body())
+                .await
+                .unwrap()
+                .to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let response = app.clone().oneshot(lookup("spring%20SALE")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json(response).await,
+            serde_json::json!([{
+                "slug": "/spring",
+                "target": "https://example.com/sale",
+                "status": 302,
+                "keyword": "Spring Sale"
+            }])
+        );
+
+        let response = app.clone().oneshot(lookup("Docs")).await.unwrap();
+        assert_eq!(json(response).await[0]["slug"], "/docs/*");
+
+        let response = app.clone().oneshot(lookup("nothing")).await.unwrap();
+        assert_eq!(json(response).await, serde_json::json!([]));
+
+        let request = Request::builder()
+            .uri("/_lookup?keyword=docs")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_reload_endpoint_disabled_without_token() {
+        let app = create_app(AppState::new(Matcher::default(), AppOptions::default()));
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/_reload")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_prefix() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/github".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let state = AppState::new(
+            rules.into(),
+            AppOptions {
+                prefix: Some("/go".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let get = |path: &str| {
+            handle_redirect(
+                Path(path.to_string()),
+                axum::extract::State(state.clone()),
+                HeaderMap::new(),
+                axum::extract::RawQuery(None),
+            )
+        };
+
+        let response = get("go/github").await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(get("github").await.unwrap_err(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            get("gopher/github").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        let options = AppOptions {
+            prefix: Some("/go".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(strip_prefix(&options, "/go/gh"), Some("/gh"));
+        assert_eq!(strip_prefix(&options, "/go"), Some("/"));
+        assert_eq!(strip_prefix(&options, "/gopher"), None);
+        assert_eq!(strip_prefix(&options, "/gh"), None);
+
+        assert_eq!(strip_prefix(&AppOptions::default(), "/gh"), Some("/gh"));
+    }
+
+    #[test]
+    fn test_append_query() {
+        assert_eq!(
+            append_query("https://example.com", "a=1"),
+            "https://example.com?a=1"
+        );
+        assert_eq!(
+            append_query("https://example.com/p?x=1", "a=1&b=2"),
+            "https://example.com/p?x=1&a=1&b=2"
+        );
+        assert_eq!(
+            append_query("https://example.com/p?", "a=1"),
+            "https://example.com/p?a=1"
+        );
+        // The fragment stays last
+        assert_eq!(
+            append_query("https://example.com/docs#install", "a=1"),
+            "https://example.com/docs?a=1#install"
+        );
+        assert_eq!(
+            append_query("https://example.com/docs?x=1#install", "a=1"),
+            "https://example.com/docs?x=1&a=1#install"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_query() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/docs".to_string(),
+            vec![Rule::new("https://example.com/docs?v=2#install", 301)],
+        );
+        let redirect = |options: AppOptions| {
+            handle_redirect(
+                Path("docs".to_string()),
+                axum::extract::State(AppState::new(rules.clone().into(), options)),
+                HeaderMap::new(),
+                axum::extract::RawQuery(Some("utm_source=x".to_string())),
+            )
+        };
+
+        let response = redirect(AppOptions {
+            forward_query: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://example.com/docs?v=2&utm_source=x#install"
+        );
+
+        // Off by default
+        let response = redirect(AppOptions::default()).await.unwrap();
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://example.com/docs?v=2#install"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_allow_query_override() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let redirect = |options: AppOptions, query: &str| {
+            handle_redirect(
+                Path("gh".to_string()),
+                axum::extract::State(AppState::new(rules.clone().into(), options)),
+                HeaderMap::new(),
+                axum::extract::RawQuery(Some(query.to_string())),
+            )
+        };
+        let enabled = || AppOptions {
+            allow_query_override: true,
+            forward_query: true,
+            ..Default::default()
+        };
+
+        // Ignored unless enabled
+        let response = redirect(AppOptions::default(), "__status=302")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+        let response = redirect(enabled(), "a=1&__status=302").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        // The reserved parameter is not forwarded
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://github.com?a=1"
+        );
+
+        // Only redirect statuses are accepted
+        let response = redirect(enabled(), "__status=500").await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    }
+
+    #[tokio::test]
+    async fn test_stats_endpoint_totals() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        rules.insert(
+            "/promo".to_string(),
+            vec![Rule::new("https://example.com", 302)],
+        );
+        let options = AppOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let app = create_app(AppState::new(rules.into(), options));
+
+        for path in ["/gh", "/gh", "/promo", "/missing"] {
+            let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let request = Request::builder()
+            .uri("/_stats")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        // The stats request itself is only counted once it has been answered
+        assert_eq!(json["requests"], 4);
+        assert_eq!(json["redirects"], 3);
+        assert_eq!(json["not_found"], 1);
+        assert_eq!(json["hits"], serde_json::json!({ "/gh": 2, "/promo": 1 }));
+        assert!(json["uptime_seconds"].is_u64());
+        for percentile in ["p50_ms", "p95_ms", "p99_ms"] {
+            assert!(json["latency"][percentile].as_f64().unwrap() > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_slug_hash() {
+        assert_eq!(slug_hash("/gh"), slug_hash("/gh"));
+        assert_eq!(slug_hash("/gh").len(), 8);
+        assert_ne!(slug_hash("/gh"), slug_hash("/docs"));
+        // Pinned so the value stays the same across releases
+        assert_eq!(slug_hash(""), "811c9dc5");
+        assert_eq!(slug_hash("a"), "e40c292c");
+    }
+
+    #[test]
+    fn test_client_ip_trust_proxy_count() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "203.0.113.9, 198.51.100.7, 10.0.0.2".parse().unwrap(),
+        );
+
+        assert_eq!(client_ip(&headers, None), Some("203.0.113.9"));
+        assert_eq!(client_ip(&headers, Some(1)), Some("10.0.0.2"));
+        assert_eq!(client_ip(&headers, Some(2)), Some("198.51.100.7"));
+        assert_eq!(client_ip(&headers, Some(3)), Some("203.0.113.9"));
+        // More proxies than hops falls back to the leftmost entry
+        assert_eq!(client_ip(&headers, Some(5)), Some("203.0.113.9"));
+
+        // Dedicated headers still take precedence
+        headers.insert("x-real-ip", "192.0.2.1".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(2)), Some("192.0.2.1"));
+
+        assert_eq!(client_ip(&HeaderMap::new(), Some(1)), None);
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_fallback() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/app".to_string(),
+            vec![Rule {
+                fallback: Some("https://backup.example.com".to_string()),
+                ..Rule::new("https://primary.example.com", 302)
+            }],
+        );
+        rules.insert(
+            "/docs".to_string(),
+            vec![Rule {
+                fallback: Some("https://status.example.com".to_string()),
+                ..Rule::new("https://docs.example.com", 301)
+            }],
+        );
+        let state = AppState::new(rules.into(), AppOptions::default());
+        let redirect = |state: &AppState, slug: &str| {
+            let (state, slug) = (state.clone(), slug.to_string());
+            async move {
+                let response = handle_redirect(
+                    Path(slug),
+                    axum::extract::State(state),
+                    HeaderMap::new(),
+                    axum::extract::RawQuery(None),
+                )
+                .await
+                .unwrap();
+                (
+                    response.status(),
+                    response.headers()[header::LOCATION].clone(),
+                )
+            }
+        };
+        let location = |state: &AppState| {
+            let request = redirect(state, "app");
+            async move { request.await.1 }
+        };
+
+        assert_eq!(location(&state).await, "https://primary.example.com");
+
+        // Primary down: the fallback is served until it recovers
+        state
+            .health
+            .set_healthy("https://primary.example.com", false);
+        assert_eq!(location(&state).await, "https://backup.example.com");
+
+        state
+            .health
+            .set_healthy("https://primary.example.com", true);
+        assert_eq!(location(&state).await, "https://primary.example.com");
+
+        // A permanent rule detours with a temporary redirect, so browsers
+        // don't cache the maintenance page
+        let (status, location) = redirect(&state, "docs").await;
+        assert_eq!(
+            (status, location),
+            (
+                StatusCode::MOVED_PERMANENTLY,
+                "https://docs.example.com".parse().unwrap()
+            )
+        );
+        state.health.set_healthy("https://docs.example.com", false);
+        let (status, location) = redirect(&state, "docs").await;
+        assert_eq!(
+            (status, location),
+            (
+                StatusCode::FOUND,
+                "https://status.example.com".parse().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_load_fallback_column() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,fallback").unwrap();
+        writeln!(
+            temp_file,
+            "/app,https://primary.example.com,302,https://backup.example.com"
+        )
+        .unwrap();
+        writeln!(temp_file, "/plain,https://example.com,301,").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        assert_eq!(
+            rules.exact["/app"][0].fallback.as_deref(),
+            Some("https://backup.example.com")
+        );
+        assert_eq!(rules.exact["/plain"][0].fallback, None);
+
+        // `health_fallback` is accepted as the column name too
+        let mut aliased = NamedTempFile::new().unwrap();
+        writeln!(aliased, "url,target,status,health_fallback").unwrap();
+        writeln!(
+            aliased,
+            "/app,https://primary.example.com,301,https://status.example.com"
+        )
+        .unwrap();
+        let rules =
+            load_redirect_rules(aliased.path().to_str().unwrap(), &LoadOptions::default()).unwrap();
+        assert_eq!(
+            rules.exact["/app"][0].fallback.as_deref(),
+            Some("https://status.example.com")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_allow_referer() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/private".to_string(),
+            vec![Rule {
+                allow_referer: vec!["example.com".to_string()],
+                ..Rule::new("https://files.example.org/report.pdf", 302)
+            }],
+        );
+        rules.insert(
+            "/public".to_string(),
+            vec![Rule::new("https://example.org", 302)],
+        );
+        let state = AppState::new(rules.into(), AppOptions::default());
+        let redirect = |path: &str, referer: Option<&str>| {
+            let state = state.clone();
+            let path = path.to_string();
+            let mut headers = HeaderMap::new();
+            if let Some(referer) = referer {
+                headers.insert(header::REFERER, referer.parse().unwrap());
+            }
+            async move {
+                handle_redirect(
+                    Path(path),
+                    axum::extract::State(state),
+                    headers,
+                    axum::extract::RawQuery(None),
+                )
+                .await
+                .map(|response| response.status())
+            }
+        };
+
+        assert_eq!(
+            redirect("private", Some("https://www.example.com/post")).await,
+            Ok(StatusCode::FOUND)
+        );
+        assert_eq!(
+            redirect("private", Some("https://elsewhere.net/")).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(redirect("private", None).await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(redirect("public", None).await, Ok(StatusCode::FOUND));
+        assert_eq!(
+            redirect("public", Some("https://elsewhere.net/")).await,
+            Ok(StatusCode::FOUND)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blocklist() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let blocklist = Blocklist::parse("/wp-admin*\n/.env\n", None).unwrap();
+        let app = create_app(
+            AppState::new(rules.into(), AppOptions::default()).with_blocklist(blocklist),
+        );
+
+        let status = |path: &str| {
+            let app = app.clone();
+            let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        assert_eq!(status("/wp-admin/install.php").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("/.env").await, StatusCode::FORBIDDEN);
+        // Percent-encoding doesn't get around an entry
+        assert_eq!(status("/%2Eenv").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("/wp%2Dadmin/").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("/gh").await, StatusCode::MOVED_PERMANENTLY);
+    }
+
+    #[tokio::test]
+    async fn test_response_headers() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let cli = Cli::parse_from([
+            "dslf",
+            "--server-header",
+            "dslf",
+            "--header",
+            "X-Frame-Options=DENY",
+            "--header",
+            "X-Content-Type-Options = nosniff",
+        ]);
+        let app = create_app(AppState::new(rules.into(), AppOptions::from(&cli)));
+
+        for (path, status) in [
+            ("/gh", StatusCode::MOVED_PERMANENTLY),
+            ("/missing", StatusCode::NOT_FOUND),
+        ] {
+            let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()[header::SERVER], "dslf");
+            assert_eq!(response.headers()["x-frame-options"], "DENY");
+            assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+        }
+
+        let options = AppOptions::from(&Cli::parse_from(["dslf", "--server-header", "None"]));
+        assert!(options.hide_server_header);
+        assert!(options.response_headers.is_empty());
+        assert!(Cli::try_parse_from(["dslf", "--header", "missing-equals"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_notfound_page() {
+        let mut page = NamedTempFile::new().unwrap();
+        write!(page, "<h1>No link at {{path}}</h1>").unwrap();
+
+        let options = AppOptions {
+            notfound_page: Some(page.path().to_path_buf()),
+            ..Default::default()
+        };
+        let state = AppState::new(Matcher::default(), options);
+        state.load_not_found_page().unwrap();
+
+        let response = handle_redirect(
+            Path("missing<b>".to_string()),
+            axum::extract::State(state),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, "<h1>No link at /missing&lt;b&gt;</h1>");
+    }
+
+    #[tokio::test]
+    async fn test_notfound_page_unset() {
+        let state = AppState::new(Matcher::default(), AppOptions::default());
+        state.load_not_found_page().unwrap();
+
+        let result = handle_redirect(
+            Path("missing".to_string()),
+            axum::extract::State(state),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_create_app() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/test".to_string(),
+            vec![Rule::new("https://example.com", 301)],
+        );
+
+        let app = create_app(AppState::new(rules.into(), AppOptions::default()));
+
+        // We can't test much about the router without running it,
+        // but we can verify it was created successfully
+        assert!(format!("{app:?}").contains("Router"));
+    }
+
+    #[test]
+    fn test_redirect_rule_debug() {
+        let rule = RedirectRule {
+            url: "/test".to_string(),
+            target: "https://example.com".to_string(),
+            status: Some(301),
+            when_header: None,
+            message: None,
+            note: None,
+            fallback: None,
+            literal: None,
+            allow_referer: None,
+            schedule: None,
+            keyword: None,
+            rotate: None,
+        };
+
+        let debug_str = format!("{rule:?}");
+        assert!(debug_str.contains("/test"));
+        assert!(debug_str.contains("https://example.com"));
+        assert!(debug_str.contains("301"));
+    }
+
+    #[test]
+    fn test_config_checksum() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "url,target,status\n/gh,https://github.com,301\n").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        // SHA-256 of "abc"
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_hex(b"abc"), digest);
+        let actual = sha256_hex(&std::fs::read(path).unwrap());
+
+        let pinned = |checksum: &str| LoadOptions {
+            config_checksum: Some(parse_sha256(checksum).unwrap()),
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(path, &pinned(&actual.to_uppercase())).unwrap();
+        assert_eq!(rules.len(), 1);
+
+        let error = load_redirect_rules(path, &pinned(digest)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Config checksum mismatch: expected {digest}, got {actual}")
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let error =
+            load_redirect_rules(dir.path().to_str().unwrap(), &pinned(&actual)).unwrap_err();
+        assert!(error.to_string().contains("single config file"));
+
+        assert!(parse_sha256("abc").is_err());
+        assert!(parse_sha256(&"g".repeat(64)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_targets() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,rotate").unwrap();
+        writeln!(
+            temp_file,
+            "/dl,https://a.example.com/f,302,https://b.example.com/f | https://c.example.com/f"
+        )
+        .unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301,").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
+        assert!(rules.exact["/gh"][0].rotation.is_none());
+        let state = AppState::new(rules, AppOptions::default());
+        let location = |slug: &str| {
+            let state = state.clone();
+            let slug = slug.to_string();
+            async move {
+                let response = handle_redirect(
+                    Path(slug),
+                    axum::extract::State(state),
+                    HeaderMap::new(),
+                    axum::extract::RawQuery(None),
+                )
+                .await
+                .unwrap();
+                response.headers()[header::LOCATION].clone()
+            }
+        };
+
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            seen.push(location("dl").await);
+        }
+        assert_eq!(
+            seen,
+            [
+                "https://a.example.com/f",
+                "https://b.example.com/f",
+                "https://c.example.com/f",
+                "https://a.example.com/f"
+            ]
+        );
+        assert_eq!(location("gh").await, "https://github.com");
+
+        std::fs::write(
+            path,
+            "url,target,status,rotate\n/docs/*,https://a.example.com/*,301,https://b.example.com/*\n",
+        )
+        .unwrap();
+        let error = load_redirect_rules(path, &LoadOptions::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Rotating targets need an exact slug, /docs/* isn't one"
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_schedule() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,schedule").unwrap();
+        writeln!(
+            temp_file,
+            "/help,https://example.com/contact,302,mon-fri 09:00-17:00 => https://chat.example.com"
+        )
+        .unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301,").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
+        assert!(rules.exact["/gh"][0].schedule.is_none());
+        let schedule = rules.exact["/help"][0].schedule.as_ref().unwrap();
+        // Thursday 2024-06-06, in and out of hours
+        let at = |hour| {
+            chrono::NaiveDate::from_ymd_opt(2024, 6, 6)
+                .unwrap()
+                .and_hms_opt(hour, 30, 0)
+                .unwrap()
+        };
+        assert_eq!(schedule.target_at(at(10)), Some("https://chat.example.com"));
+        assert_eq!(schedule.target_at(at(18)), None);
+
+        std::fs::write(
+            path,
+            "url,target,status,schedule\n/help,https://example.com,302,weekdays 9-5 => x\n",
+        )
+        .unwrap();
+        let error = load_redirect_rules(path, &LoadOptions::default()).unwrap_err();
+        assert!(
+            error.to_string().contains("Invalid schedule for /help"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_malformed_status() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/test,https://example.com,not_a_number").unwrap();
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_load_redirect_rules_extra_columns() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,extra").unwrap();
+        writeln!(temp_file, "/test,https://example.com,301,ignored").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(
+            rules.exact.get("/test"),
+            Some(&vec![Rule::new("https://example.com", 301)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_path_formatting() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/test/path".to_string(),
+            vec![Rule::new("https://example.com", 301)],
+        );
+
+        let result = handle_redirect(
+            axum::extract::Path("test/path".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_load_redirect_rules_status_validation() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/test1,https://example.com,301").unwrap();
+        writeln!(temp_file, "/test2,https://example.com,302").unwrap();
+        writeln!(temp_file, "/test3,https://example.com,303").unwrap();
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid status code: 303")
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_status_names() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/a,https://example.com,permanent").unwrap();
+        writeln!(temp_file, "/b,https://example.com,Temporary").unwrap();
+        writeln!(temp_file, "/c,,gone").unwrap();
+        writeln!(temp_file, "/d,https://example.com,").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        let status = |slug: &str| rules.exact[slug][0].status;
+        assert_eq!(
+            [status("/a"), status("/b"), status("/c"), status("/d")],
+            [301, 302, 410, 301]
+        );
+
+        let json = |line: &str| {
+            let rule: RedirectRule = serde_json::from_str(line).unwrap();
+            rule.status
+        };
+        assert_eq!(json(r#"{"url":"/a","target":"x","status":302}"#), Some(302));
+        assert_eq!(
+            json(r#"{"url":"/a","target":"x","status":"302"}"#),
+            Some(302)
+        );
+        assert_eq!(
+            json(r#"{"url":"/a","target":"x","status":"permanent"}"#),
+            Some(301)
+        );
+
+        // Unknown names fail to parse, and unsupported codes fail validation
+        for (status, error) in [
+            ("forever", "unknown status forever"),
+            ("see-other", "unknown status see-other"),
+            ("303", "Invalid status code: 303"),
+        ] {
+            let mut temp_file = NamedTempFile::new().unwrap();
+            writeln!(temp_file, "url,target,status").unwrap();
+            writeln!(temp_file, "/a,https://example.com,{status}").unwrap();
+            let result =
+                load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+            assert!(result.unwrap_err().to_string().contains(error), "{status}");
+        }
+    }
+
+    #[test]
+    fn test_empty_slug() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, ",https://example.com,301").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        // Loaded by default, but flagged by --check since it can't match
+        let parsed = read_redirect_rules(path, &LoadOptions::default()).unwrap();
+        assert_eq!(parsed[0].url, "");
+        assert_eq!(
+            check_warnings(&parsed, None),
+            ["The row for https://example.com has an empty slug and never matches"]
+        );
+
+        let error = load_redirect_rules(
+            path,
+            &LoadOptions {
+                deny_empty_slug: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Row for https://example.com has an empty slug; use / for the root"
+        );
+
+        // --fix serves it at the root instead
+        let rules = load_redirect_rules(
+            path,
+            &LoadOptions {
+                fix: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(rules.exact["/"][0].target, "https://example.com");
+    }
+
+    #[test]
+    fn test_empty_hashmap() {
+        let rules = HashMap::new();
+        let app = create_app(AppState::new(rules.into(), AppOptions::default()));
+        assert!(format!("{app:?}").contains("Router"));
+    }
+
+    #[test]
+    fn test_create_app_with_logging() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/test".to_string(),
+            vec![Rule::new("https://example.com", 301)],
+        );
+
+        // Test app with logging enabled
+        let app_with_logging = create_app(AppState::new(
+            rules.clone().into(),
+            AppOptions {
+                logging: true,
+                ..Default::default()
+            },
+        ));
+        assert!(format!("{app_with_logging:?}").contains("Router"));
+
+        // Test app without logging
+        let app_without_logging = create_app(AppState::new(rules.into(), AppOptions::default()));
+        assert!(format!("{app_without_logging:?}").contains("Router"));
+    }
+
+    #[test]
+    fn test_check_report() {
+        let parsed = vec![
+            ParsedRule {
+                url: "/gh".to_string(),
+                rule: Rule::new("https://github.com/a", 301),
+            },
+            ParsedRule {
+                url: "/promo".to_string(),
+                rule: Rule::new("https://example.com/offer", 302),
+            },
+            ParsedRule {
+                url: "/gh".to_string(),
+                rule: Rule::new("https://github.com/b", 301),
+            },
+            ParsedRule {
+                url: "/loop".to_string(),
+                rule: Rule::new("https://go.example.com/gh", 301),
+            },
+            ParsedRule {
+                url: "/old".to_string(),
+                rule: Rule::new("", 410),
+            },
+        ];
+
+        let report = check_report(&parsed, false, None);
+        assert!(report.contains("  /gh -> https://github.com/a (301)\n"));
+        assert!(report.contains("  /promo -> https://example.com/offer (302)\n"));
+        assert!(report.contains("  /old ->  (410)\n"));
+        assert!(report.contains("⚠ /gh is defined more than once"));
+        assert!(!report.contains("redirects back"));
+
+        let report = check_report(&parsed, true, Some("https://go.example.com/"));
+        assert!(report.contains("  /gh -> https://github.com/a (308)\n"));
+        assert!(report.contains("  /promo -> https://example.com/offer (307)\n"));
+        assert!(report.contains("  /old ->  (410)\n"));
+        assert!(report.contains("⚠ /loop redirects back to https://go.example.com"));
+        assert!(!report.contains("⚠ /promo"));
+    }
+
+    #[test]
+    fn test_exit_code_on_warnings() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/gh,https://github.com,301").unwrap();
+        writeln!(temp_file, "/gh,https://github.com/vpetersson,301").unwrap();
+        writeln!(temp_file, "/ok,https://example.com,301").unwrap();
+        let parsed =
+            read_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        let warnings = check_warnings(&parsed, None);
+        assert_eq!(
+            warnings,
+            ["/gh is defined more than once, the last row wins"]
+        );
+        // Warnings are advisory by default
+        assert!(check_warnings_gate(warnings.clone(), false).is_ok());
+
+        let error = check_warnings_gate(warnings, true).unwrap_err();
+        assert_eq!(error.error, "Config has 1 warnings");
+        assert_eq!(
+            error.details,
+            ["/gh is defined more than once, the last row wins"]
+        );
+        assert!(check_warnings_gate(Vec::new(), true).is_ok());
+
+        assert!(Cli::try_parse_from(["dslf", "--check", "--exit-code-on-warnings"]).is_ok());
+        assert!(Cli::try_parse_from(["dslf", "--exit-code-on-warnings"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_verbose_requires_check() {
+        assert!(Cli::try_parse_from(["dslf", "--verbose"]).is_err());
+        assert!(Cli::parse_from(["dslf", "--check", "--verbose"]).verbose);
+    }
+
+    #[test]
+    fn test_dump_routes() {
+        let parsed = vec![
+            ParsedRule {
+                url: "/docs/*".to_string(),
+                rule: Rule::new("https://docs.example.com/*", 301),
+            },
+            ParsedRule {
+                url: "~^/blog/(.+)$".to_string(),
+                rule: Rule::new("https://blog.example.com/$1", 302),
+            },
+            ParsedRule {
+                url: "/docs/intro".to_string(),
+                rule: Rule::new("https://docs.example.com/start", 301),
+            },
+            ParsedRule {
+                url: "/docs/intro".to_string(),
+                rule: Rule {
+                    host: Some("go.example.com".to_string()),
+                    ..Rule::new("https://go.example.com/intro", 302)
+                },
+            },
+        ];
+        let rules = build_matcher(parsed).unwrap();
+
+        let dump = dump_routes(&rules, &AppOptions::default());
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "# exact slugs match as written, then without trailing slashes",
+                "   1. exact    /docs/intro -> https://go.example.com/intro (302) [host go.example.com]",
+                "   2. exact    /docs/intro -> https://docs.example.com/start (301)",
+                "   3. wildcard /docs/* -> https://docs.example.com/* (301)",
+                "   4. regex    ~^/blog/(.+)$ -> https://blog.example.com/$1 (302)",
+            ]
+        );
+
+        let options = AppOptions {
+            modern: true,
+            exact_only: true,
+            ..Default::default()
+        };
+        let dump = dump_routes(&rules, &options);
+        assert!(dump.starts_with("# exact slugs match as written (--exact-only)"));
+        assert!(dump.contains("/docs/intro -> https://docs.example.com/start (308)"));
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        // Nothing in flight: resolves right away, well inside the cap
+        let start = Instant::now();
+        let result = drain(std::future::ready("done"), Some(Duration::from_secs(30))).await;
+        assert_eq!(result, Some("done"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // A stuck connection is abandoned once the cap is reached
+        let start = Instant::now();
+        let result = drain(
+            std::future::pending::<()>(),
+            Some(Duration::from_millis(50)),
+        )
+        .await;
+        assert_eq!(result, None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(drain(std::future::ready(1), None).await, Some(1));
+    }
+
+    #[test]
+    fn test_startup_summary() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/a".to_string(),
+            vec![Rule::new("https://example.com/a", 301)],
+        );
+        rules.insert(
+            "/b".to_string(),
+            vec![Rule::new("https://example.com/b", 302)],
+        );
+        let rules = Matcher::from(rules);
+
+        let summary = startup_summary(&rules, &AppOptions::default());
+        assert!(summary.contains("2 redirect rules"));
+        assert!(summary.contains("options: none"));
+
+        let options = AppOptions {
+            modern: true,
+            logging: true,
+            static_dir: Some(PathBuf::from("static")),
+            exact_only: true,
+            ..Default::default()
+        };
+        let summary = startup_summary(&rules, &options);
+        assert!(summary.contains("2 redirect rules"));
+        assert!(summary.contains("modern"));
+        assert!(summary.contains("logging"));
+        assert!(summary.contains("static-dir=static"));
+        assert!(summary.contains("exact-only"));
+    }
+
+    #[test]
+    fn test_load_redirect_rules_with_whitespace() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, " /test , https://example.com ,301").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        // CSV library should handle whitespace in string fields
+        assert_eq!(rules.len(), 1);
+        assert!(rules.exact.contains_key(" /test "));
+    }
+
+    #[test]
+    fn test_load_redirect_rules_with_empty_lines() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/first,https://example.com/first,301").unwrap();
+        writeln!(temp_file).unwrap(); // Empty line
+        writeln!(temp_file, "   ").unwrap(); // Line with only whitespace
+        writeln!(temp_file, "/second,https://example.com/second,302").unwrap();
+        writeln!(temp_file).unwrap(); // Another empty line
+        writeln!(temp_file, "	").unwrap(); // Line with only tab
+        writeln!(temp_file, "/third,https://example.com/third,301").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        // Should have exactly 3 rules, empty/whitespace lines should be ignored
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules.exact.get("/first"),
+            Some(&vec![Rule::new("https://example.com/first", 301)])
+        );
+        assert_eq!(
+            rules.exact.get("/second"),
+            Some(&vec![Rule::new("https://example.com/second", 302)])
+        );
+        assert_eq!(
+            rules.exact.get("/third"),
+            Some(&vec![Rule::new("https://example.com/third", 301)])
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_with_comments_and_empty_lines() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "# GitHub and development links").unwrap();
+        writeln!(temp_file, "/gh,https://github.com/user,301").unwrap();
+        writeln!(temp_file).unwrap(); // Empty line
+        writeln!(temp_file, "# Marketing and promotional links").unwrap();
+        writeln!(temp_file, "   ").unwrap(); // Whitespace line
+        writeln!(temp_file, "/promo,https://site.com/promo,302").unwrap();
+        writeln!(temp_file).unwrap(); // Another empty line
+        writeln!(temp_file, "# Documentation").unwrap();
+        writeln!(temp_file, "/docs,https://docs.site.com,301").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        // Should have exactly 3 rules, comments and empty lines should be ignored
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules.exact.get("/gh"),
+            Some(&vec![Rule::new("https://github.com/user", 301)])
+        );
+        assert_eq!(
+            rules.exact.get("/promo"),
+            Some(&vec![Rule::new("https://site.com/promo", 302)])
+        );
+        assert_eq!(
+            rules.exact.get("/docs"),
+            Some(&vec![Rule::new("https://docs.site.com", 301)])
+        );
+    }
+
+    #[test]
+    fn test_large_ruleset() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        for i in 0..1000 {
+            writeln!(temp_file, "/test{i},https://example.com/target{i},301").unwrap();
+        }
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(rules.len(), 1000);
+    }
+
+    #[test]
+    fn test_special_characters_in_urls() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(
+            temp_file,
+            "/test-path_with.special?chars,https://example.com/target,301"
+        )
+        .unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(rules.len(), 1);
+        assert!(rules.exact.contains_key("/test-path_with.special?chars"));
+    }
+
+    #[test]
+    fn test_load_redirect_rules_error_message() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/test,https://example.com,999").unwrap();
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("Invalid status code: 999"));
+        assert!(error.to_string().contains("Must be 301 or 302"));
+    }
+
+    #[test]
+    fn test_load_redirect_rules_simple_comments() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "# This is a comment").unwrap();
+        writeln!(temp_file, "/test,https://example.com,301").unwrap();
+        writeln!(temp_file, "# Another comment").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(
+            rules.exact.get("/test"),
+            Some(&vec![Rule::new("https://example.com", 301)])
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_comments_only() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/test1,https://example.com,301").unwrap();
+        writeln!(temp_file, "/test2,https://example.com,302").unwrap();
+        writeln!(temp_file, "/test3,https://example.com,301").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules.exact.get("/test1"),
+            Some(&vec![Rule::new("https://example.com", 301)])
+        );
+        assert_eq!(
+            rules.exact.get("/test2"),
+            Some(&vec![Rule::new("https://example.com", 302)])
+        );
+        assert_eq!(
+            rules.exact.get("/test3"),
+            Some(&vec![Rule::new("https://example.com", 301)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_redirect_with_query_params() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/api/v1/users".to_string(),
+            vec![Rule::new("https://api.example.com/users", 301)],
+        );
+
+        let result = handle_redirect(
+            axum::extract::Path("api/v1/users".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
 
-fn load_redirect_rules(
-    file_path: &str,
-) -> Result<HashMap<String, (String, u16)>, Box<dyn std::error::Error>> {
-    let file_content = std::fs::read_to_string(file_path)?;
+    #[tokio::test]
+    async fn test_validate_destinations_function_signature() {
+        // Test that the validation function handles different input scenarios
+        // without making actual HTTP calls
 
-    // Filter out empty lines and lines with only whitespace
-    let filtered_content: String = file_content
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .map(|line| format!("{line}\n"))
-        .collect();
+        // Test with malformed URLs
+        let mut rules = HashMap::new();
+        rules.insert("/test".to_string(), vec![Rule::new("not-a-valid-url", 301)]);
 
-    let mut reader = csv::ReaderBuilder::new()
-        .comment(Some(b'#'))
-        .from_reader(filtered_content.as_bytes());
-    let mut rules = HashMap::new();
+        let result = validate_destinations(
+            &rules.into(),
+            &reqwest::Client::new(),
+            ValidateOptions::default(),
+            true,
+        )
+        .await;
+        assert!(result.is_err());
 
-    for result in reader.deserialize() {
-        let rule: RedirectRule = result?;
+        // Test with invalid protocols
+        let mut rules2 = HashMap::new();
+        rules2.insert(
+            "/test".to_string(),
+            vec![Rule::new("ftp://example.com", 301)],
+        );
 
-        // Validate status code
-        if rule.status != 301 && rule.status != 302 {
-            return Err(format!(
-                "Invalid status code: {status}. Must be 301 or 302",
-                status = rule.status
+        let result2 = validate_destinations(
+            &rules2.into(),
+            &reqwest::Client::new(),
+            ValidateOptions::default(),
+            true,
+        )
+        .await;
+        assert!(result2.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_destinations_error_formatting() {
+        // Test that validation errors are properly formatted
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/test1".to_string(),
+            vec![Rule::new("http://invalid-domain-12345.local", 301)],
+        );
+        rules.insert(
+            "/test2".to_string(),
+            vec![Rule::new("http://another-invalid-domain-67890.local", 302)],
+        );
+
+        let result = validate_destinations(
+            &rules.into(),
+            &reqwest::Client::new(),
+            ValidateOptions::default(),
+            true,
+        )
+        .await;
+        assert!(result.is_err());
+
+        let error_msg = result.unwrap_err().to_string();
+        assert!(error_msg.contains("Validation failed"));
+        assert!(error_msg.contains("destinations"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_destinations_json_error() {
+        let mut rules = HashMap::new();
+        rules.insert("/test".to_string(), vec![Rule::new("not-a-valid-url", 301)]);
+
+        let error = validate_destinations(
+            &rules.into(),
+            &reqwest::Client::new(),
+            ValidateOptions::default(),
+            false,
+        )
+        .await
+        .unwrap_err();
+        let mut output = Vec::new();
+        write_cli_error(&mut output, "Validation failed", &error, true).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["error"], "Validation failed for 1 destinations");
+        let details = json["details"].as_array().unwrap();
+        assert_eq!(details.len(), 1);
+        assert!(
+            details[0]
+                .as_str()
+                .unwrap()
+                .starts_with("not-a-valid-url: ")
+        );
+    }
+
+    #[test]
+    fn test_write_cli_error_text() {
+        let mut output = Vec::new();
+        let error = CliError::new("Validation failed for 2 destinations");
+        write_cli_error(&mut output, "Validation failed", &error, false).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Validation failed: Validation failed for 2 destinations\n"
+        );
+    }
+
+    #[test]
+    fn test_read_config_from_stdin() {
+        let stdin = io::Cursor::new("url,target,status\n/gh,https://github.com,301\n");
+        let content = read_config("-", stdin).unwrap();
+
+        let rules = build_matcher(parse_redirect_rules(&content, &LoadOptions::default()).unwrap())
+            .unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules.exact["/gh"][0].target, "https://github.com");
+    }
+
+    #[test]
+    fn test_read_config_ignores_stdin_for_files() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+
+        let stdin = io::Cursor::new("not read");
+        let content = read_config(temp_file.path().to_str().unwrap(), stdin).unwrap();
+        assert_eq!(content, "url,target,status\n");
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let app = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "done"
+            }),
+        );
+        let options = AppOptions {
+            request_timeout: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let app = apply_layers(app, &AppState::new(Matcher::default(), options));
+
+        let request = Request::builder().uri("/slow").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_requests() {
+        let started = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Notify::new());
+        let app = Router::new()
+            .route(
+                "/slow",
+                get({
+                    let (started, release) = (started.clone(), release.clone());
+                    || async move {
+                        started.notify_one();
+                        release.notified().await;
+                        "done"
+                    }
+                }),
             )
-            .into());
+            .route("/fast", get(|| async { "done" }));
+        let options = AppOptions {
+            max_concurrent_requests: Some(1),
+            ..Default::default()
+        };
+        let app = apply_layers(app, &AppState::new(Matcher::default(), options));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let slow = tokio::spawn(app.clone().oneshot(get("/slow")));
+        started.notified().await;
+
+        // The slow request holds the only permit, so this one is shed
+        let response = app.clone().oneshot(get("/fast")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        release.notify_one();
+        assert_eq!(slow.await.unwrap().unwrap().status(), StatusCode::OK);
+        let response = app.oneshot(get("/fast")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let app =
+            Router::new().route(
+                "/",
+                get(|headers: HeaderMap| async move {
+                    headers[X_REQUEST_ID].to_str().unwrap().to_string()
+                }),
+            );
+        let options = AppOptions {
+            request_id: true,
+            ..Default::default()
+        };
+        let app = apply_layers(app, &AppState::new(Matcher::default(), options));
+        let request = |id: Option<&str>| {
+            let mut builder = Request::builder().uri("/");
+            if let Some(id) = id {
+                builder = builder.header(X_REQUEST_ID, id);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        // An incoming ID is passed on and echoed back
+        let response = app.clone().oneshot(request(Some("abc-123"))).await.unwrap();
+        assert_eq!(response.headers()[X_REQUEST_ID], "abc-123");
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(&body[..], b"abc-123");
+
+        // Missing or unusable IDs are replaced with a UUID
+        let too_long = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        for id in [None, Some(""), Some("has space"), Some(too_long.as_str())] {
+            let response = app.clone().oneshot(request(id)).await.unwrap();
+            let assigned = response.headers()[X_REQUEST_ID].to_str().unwrap();
+            assert_eq!(assigned.len(), 36, "{id:?}");
+            assert_eq!(&assigned[14..15], "4");
+            assert_eq!(assigned.matches('-').count(), 4);
         }
+        assert_ne!(random_uuid(), random_uuid());
 
-        rules.insert(rule.url, (rule.target, rule.status));
+        // Off by default
+        let app = apply_layers(
+            Router::new().route("/", get(|| async { "ok" })),
+            &AppState::new(Matcher::default(), AppOptions::default()),
+        );
+        let response = app.oneshot(request(None)).await.unwrap();
+        assert!(response.headers().get(X_REQUEST_ID).is_none());
     }
 
-    Ok(rules)
-}
+    #[tokio::test]
+    async fn test_early_answers_are_logged() {
+        let started = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Notify::new());
+        let app = Router::new().route(
+            "/slow",
+            get({
+                let (started, release) = (started.clone(), release.clone());
+                || async move {
+                    started.notify_one();
+                    release.notified().await;
+                    "done"
+                }
+            }),
+        );
+        let (lines, mut logged) = tokio::sync::mpsc::unbounded_channel();
+        let state = AppState {
+            access_log: LogSink::Channel(lines),
+            ..AppState::new(
+                Matcher::default(),
+                AppOptions {
+                    logging: true,
+                    max_concurrent_requests: Some(1),
+                    ..Default::default()
+                },
+            )
+        };
+        let app = apply_layers(app, &state);
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let slow = tokio::spawn(app.clone().oneshot(get("/slow")));
+        started.notified().await;
+
+        // Shed before reaching any route, but still logged and counted
+        let response = app.oneshot(get("/shed")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let line = logged.recv().await.unwrap();
+        assert!(line.contains(" GET /shed 503 "), "{line}");
+        assert_eq!(state.stats.snapshot().requests, 1);
+
+        release.notify_one();
+        slow.await.unwrap().unwrap();
+        assert!(logged.recv().await.unwrap().contains(" GET /slow 200 "));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::http::StatusCode;
-    use std::collections::HashMap;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+    #[tokio::test]
+    async fn test_log_syslog() {
+        /// Keeps each record, as a datagram socket would
+        struct Records(Arc<std::sync::Mutex<Vec<String>>>);
+        impl Write for Records {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.write_fmt(format_args!("{}", String::from_utf8_lossy(buf)))?;
+                Ok(buf.len())
+            }
+            fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> io::Result<()> {
+                self.0.lock().unwrap().push(args.to_string());
+                Ok(())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let records = Arc::default();
+        let syslog = Syslog::new(
+            Box::new(Records(Arc::clone(&records))),
+            syslog::Facility::LOG_LOCAL0,
+        );
+        let state = AppState {
+            access_log: LogSink::Syslog(syslog),
+            ..AppState::new(
+                rules.into(),
+                AppOptions {
+                    logging: true,
+                    ..Default::default()
+                },
+            )
+        };
+        let app = create_app(state);
+        for uri in ["/gh", "/missing"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2, "{records:?}");
+        // local0.info is priority 16 * 8 + 6
+        let prefix = format!("dslf[{}]: ", std::process::id());
+        for (record, request) in records.iter().zip(["GET /gh 301 ", "GET /missing 404 "]) {
+            assert!(record.starts_with("<134>"), "{record}");
+            let line = &record[record.find(&prefix).unwrap() + prefix.len()..];
+            assert!(line.contains(request), "{record}");
+        }
+
+        assert!(parse_syslog_facility("daemon").is_ok());
+        assert!(parse_syslog_facility("nope").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_not_exceeded() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let options = AppOptions {
+            request_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let app = create_app(AppState::new(rules.into(), options));
+
+        let request = Request::builder().uri("/gh").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    }
 
     #[test]
-    fn test_load_redirect_rules() {
+    fn test_load_redirect_rules_jsonl_round_trip() {
+        let redirects = vec![
+            import::DslfRedirect {
+                url: "/gh".to_string(),
+                target: "https://github.com".to_string(),
+                status: 301,
+                metadata: None,
+            },
+            import::DslfRedirect {
+                url: "/promo".to_string(),
+                target: "https://example.com/offer?a=1,2".to_string(),
+                status: 302,
+                metadata: None,
+            },
+        ];
+
+        let mut temp_file = tempfile::Builder::new()
+            .suffix(".jsonl")
+            .tempfile()
+            .unwrap();
+        import::write_redirects(&mut temp_file, &redirects, import::ExportFormat::Jsonl).unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules.exact["/gh"][0], Rule::new("https://github.com", 301));
+        assert_eq!(
+            rules.exact["/promo"][0],
+            Rule::new("https://example.com/offer?a=1,2", 302)
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_jsonl_invalid_line() {
+        let mut temp_file = tempfile::Builder::new()
+            .suffix(".jsonl")
+            .tempfile()
+            .unwrap();
+        writeln!(
+            temp_file,
+            r#"{{"url":"/gh","target":"https://github.com"}}"#
+        )
+        .unwrap();
+        writeln!(temp_file).unwrap();
+        writeln!(temp_file, "not json").unwrap();
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid JSON on line 3")
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_rejects_control_characters() {
         let mut temp_file = NamedTempFile::new().unwrap();
         writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/old,https://example.com/new,301").unwrap();
-        writeln!(temp_file, "/temp,https://example.com/temp,302").unwrap();
+        writeln!(
+            temp_file,
+            "/bad,\"https://example.com/\r\nSet-Cookie: x=1\",301"
+        )
+        .unwrap();
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Target for /bad contains a line break at byte 20; targets must fit on one line"
+        );
+
+        // A quoted field spanning lines, with the rows around it intact
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/ok,https://example.com,301").unwrap();
+        writeln!(temp_file, "/split,\"https://example.com/a\nb\",301").unwrap();
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Target for /split contains a line break at byte 21; targets must fit on one line"
+        );
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/bell,https://example.com/\u{7},301").unwrap();
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Target for /bell contains control character U+0007 at byte 20"
+        );
+
+        // Schedule targets reach the Location header too
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,schedule").unwrap();
+        writeln!(
+            temp_file,
+            "/help,https://example.com,302,\"daily 09:00-17:00 => https://chat.example.com/\u{7}; else => https://example.com/form\""
+        )
+        .unwrap();
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Target for /help contains control character U+0007 at byte 25"
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_max_target_len() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(
+            temp_file,
+            "/long,https://example.com/{},301",
+            "a".repeat(20)
+        )
+        .unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let options = LoadOptions {
+            max_target_len: 32,
+            ..Default::default()
+        };
+        let result = load_redirect_rules(path, &options);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Target for /long is 40 bytes, longer than the 32 byte limit"
+        );
+
+        // Within the default limit
+        assert!(load_redirect_rules(path, &LoadOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_load_redirect_rules_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("10-base.csv"),
+            "url,target,status\n/gh,https://github.com/old,301\n/docs,https://docs.example.com,301\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("20-overrides.jsonl"),
+            "{\"url\":\"/gh\",\"target\":\"https://github.com/new\",\"status\":302}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README.md"), "not a rule file").unwrap();
+
+        let rules =
+            load_redirect_rules(dir.path().to_str().unwrap(), &LoadOptions::default()).unwrap();
+        assert_eq!(rules.len(), 2);
+        // The later file wins
+        assert_eq!(
+            rules.exact["/gh"][0],
+            Rule::new("https://github.com/new", 302)
+        );
+        assert_eq!(rules.exact["/docs"][0].target, "https://docs.example.com");
+
+        // Errors name the file they came from
+        std::fs::write(
+            dir.path().join("30-bad.csv"),
+            "url,target,status\n/x,https://x.com,999\n",
+        )
+        .unwrap();
+        let error = load_redirect_rules(dir.path().to_str().unwrap(), &LoadOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("30-bad.csv: Invalid status code: 999"),
+            "{error}"
+        );
+
+        // A directory holding just `redirects.csv` loads that file
+        let single = tempfile::tempdir().unwrap();
+        std::fs::write(
+            single.path().join("redirects.csv"),
+            "url,target,status\n/gh,https://github.com,301\n",
+        )
+        .unwrap();
+        let rules =
+            load_redirect_rules(single.path().to_str().unwrap(), &LoadOptions::default()).unwrap();
+        assert_eq!(rules.exact["/gh"][0].target, "https://github.com");
+
+        // Without any rule file the directory is an error, not an empty config
+        let empty = tempfile::tempdir().unwrap();
+        std::fs::write(empty.path().join("redirects.yaml"), "- url: /gh\n").unwrap();
+        let path = empty.path().to_str().unwrap();
+        let error = load_redirect_rules(path, &LoadOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!("{path} is a directory with no .csv or .jsonl rule files")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slug_prefix_map() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(
+            temp_file,
+            "/old-docs/intro,https://docs.example.com/intro,301"
+        )
+        .unwrap();
+        writeln!(temp_file, "/old-docs/*,https://docs.example.com/*,301").unwrap();
+        writeln!(temp_file, "/old-docsite,https://example.com/site,301").unwrap();
+        let options = LoadOptions {
+            slug_prefix_map: vec![("/old-docs".to_string(), "/docs".to_string())],
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(temp_file.path().to_str().unwrap(), &options).unwrap();
+
+        assert!(rules.exact.contains_key("/docs/intro"));
+        assert!(!rules.exact.contains_key("/old-docs/intro"));
+        assert_eq!(rules.wildcard[0].prefix, "/docs/");
+        // Only whole segments are mapped
+        assert!(rules.exact.contains_key("/old-docsite"));
+
+        let response = handle_redirect(
+            Path("docs/intro".to_string()),
+            axum::extract::State(AppState::new(rules, AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://docs.example.com/intro"
+        );
+    }
+
+    #[test]
+    fn test_slug_prefix_map_collisions() {
+        let rule = |url: &str| ParsedRule {
+            url: url.to_string(),
+            rule: Rule::new("https://example.com", 301),
+        };
+        let mut parsed = vec![rule("/old/a"), rule("/new/a"), rule("/old/b")];
+        let map = [("/old".to_string(), "/new".to_string())];
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
+        let warnings = apply_slug_prefix_map(&mut parsed, &map);
+        assert_eq!(
+            warnings,
+            ["/old/a is mapped to /new/a, which is defined more than once"]
+        );
+        assert_eq!(parsed[2].url, "/new/b");
 
-        assert_eq!(rules.len(), 2);
+        assert!(Cli::try_parse_from(["dslf", "--slug-prefix-map", "old=/new"]).is_err());
+        let cli = Cli::parse_from(["dslf", "--slug-prefix-map", "/old=/new"]);
+        assert_eq!(LoadOptions::from(&cli).slug_prefix_map, map);
+    }
+
+    #[test]
+    fn test_lowercase_host() {
+        assert_eq!(
+            lowercase_host("HTTPS://Example.COM/Path"),
+            "https://example.com/Path"
+        );
         assert_eq!(
-            rules.get("/old"),
-            Some(&("https://example.com/new".to_string(), 301))
+            lowercase_host("https://User:Pw@Example.com:8443/A?Q=B#Frag"),
+            "https://User:Pw@example.com:8443/A?Q=B#Frag"
         );
+        assert_eq!(lowercase_host("https://Example.com"), "https://example.com");
+        assert_eq!(lowercase_host("@GH"), "@GH");
+        assert_eq!(lowercase_host("Not A URL"), "Not A URL");
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/x,HTTPS://Example.COM/Path,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        let options = LoadOptions {
+            lowercase_host: true,
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(path, &options).unwrap();
+        assert_eq!(rules.exact["/x"][0].target, "https://example.com/Path");
+        // Schedule targets are lowercased too
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,schedule").unwrap();
+        writeln!(
+            temp_file,
+            "/help,https://example.com,302,daily 00:00-00:00 => HTTPS://Chat.Example.COM/A"
+        )
+        .unwrap();
+        let scheduled = load_redirect_rules(temp_file.path().to_str().unwrap(), &options).unwrap();
+        let schedule = scheduled.exact["/help"][0].schedule.as_ref().unwrap();
         assert_eq!(
-            rules.get("/temp"),
-            Some(&("https://example.com/temp".to_string(), 302))
+            schedule.targets().collect::<Vec<_>>(),
+            ["https://chat.example.com/A"]
         );
+        // Off by default
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
+        assert_eq!(rules.exact["/x"][0].target, "HTTPS://Example.COM/Path");
     }
 
     #[test]
-    fn test_invalid_status_code() {
+    fn test_load_redirect_rules_max_rules() {
         let mut temp_file = NamedTempFile::new().unwrap();
         writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/invalid,https://example.com,200").unwrap();
+        for i in 0..3 {
+            writeln!(temp_file, "/r{i},https://example.com/{i},301").unwrap();
+        }
+        let path = temp_file.path().to_str().unwrap();
 
-        let result = load_redirect_rules(temp_file.path().to_str().unwrap());
-        assert!(result.is_err());
+        let options = |max_rules| LoadOptions {
+            max_rules: Some(max_rules),
+            ..Default::default()
+        };
+        assert_eq!(
+            load_redirect_rules(path, &options(2))
+                .unwrap_err()
+                .to_string(),
+            "Config has 3 rules, more than the --max-rules limit of 2"
+        );
+        assert_eq!(load_redirect_rules(path, &options(3)).unwrap().len(), 3);
+        assert!(load_redirect_rules(path, &LoadOptions::default()).is_ok());
     }
 
     #[test]
-    fn test_load_redirect_rules_file_not_found() {
-        let result = load_redirect_rules("nonexistent.csv");
-        assert!(result.is_err());
-    }
+    fn test_load_redirect_rules_strict_slugs() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "github,https://github.com,301").unwrap();
+        writeln!(temp_file, "~^/blog/(.+)$,https://blog.example.com/$1,301").unwrap();
+        let path = temp_file.path().to_str().unwrap();
 
-    #[test]
-    fn test_load_redirect_rules_empty_file() {
-        let temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file.as_file(), "url,target,status").unwrap();
+        // Loaded as-is by default
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
+        assert!(rules.exact.contains_key("github"));
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
-        assert_eq!(rules.len(), 0);
+        let strict = LoadOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            load_redirect_rules(path, &strict).unwrap_err().to_string(),
+            "Slug github must start with '/'"
+        );
+
+        let fix = LoadOptions {
+            strict: true,
+            fix: true,
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(path, &fix).unwrap();
+        assert!(rules.exact.contains_key("/github"));
+        assert_eq!(rules.regex.len(), 1);
     }
 
-    #[test]
-    fn test_load_redirect_rules_invalid_csv() {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/invalid,https://example.com").unwrap(); // Missing status column
+    /// Answers every HEAD request with the same status
+    struct StatusProbe(StatusCode);
 
-        let result = load_redirect_rules(temp_file.path().to_str().unwrap());
-        assert!(result.is_err());
+    impl Probe for StatusProbe {
+        async fn head(&self, _url: &str) -> Result<StatusCode, String> {
+            Ok(self.0)
+        }
     }
 
     #[tokio::test]
-    async fn test_handle_redirect_301() {
+    async fn test_preload_validate() {
         let mut rules = HashMap::new();
         rules.insert(
-            "/old".to_string(),
-            ("https://example.com/new".to_string(), 301),
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
         );
+        let rules = Matcher::from(rules);
+        let down = StatusProbe(StatusCode::NOT_FOUND);
 
-        let result = handle_redirect(
-            axum::extract::Path("old".to_string()),
-            axum::extract::State((rules, false)),
+        // Fail fast: a failing destination stops startup
+        let error = preload_validate(&rules, &down, ValidateOptions::default(), false, false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.error, "Validation failed for 1 destinations");
+        assert_eq!(error.details, ["https://github.com: HTTP 404 Not Found"]);
+
+        // Warn: failures are reported but serving goes ahead
+        assert!(
+            preload_validate(&rules, &down, ValidateOptions::default(), true, false)
+                .await
+                .is_ok()
+        );
+
+        let up = StatusProbe(StatusCode::OK);
+        assert!(
+            preload_validate(&rules, &up, ValidateOptions::default(), false, false)
+                .await
+                .is_ok()
+        );
+        let accept_404 = ValidateOptions {
+            accept: &[404],
+            ..Default::default()
+        };
+        assert!(
+            preload_validate(&rules, &down, accept_404, false, false)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_cli_preload_validate_flags_conflict() {
+        assert!(
+            Cli::try_parse_from(["dslf", "--preload-validate", "--preload-validate-warn"]).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_destinations_empty() {
+        let rules = HashMap::new();
+
+        let result = validate_destinations(
+            &rules.into(),
+            &reqwest::Client::new(),
+            ValidateOptions::default(),
+            true,
         )
         .await;
-
         assert!(result.is_ok());
-        // The redirect is created successfully - we can't easily test the exact type
-        // without more complex setup, but we know it worked based on the Ok result
     }
 
     #[tokio::test]
-    async fn test_handle_redirect_302() {
+    async fn test_handle_redirect_trailing_slash() {
         let mut rules = HashMap::new();
         rules.insert(
-            "/temp".to_string(),
-            ("https://example.com/temp".to_string(), 302),
+            "/github".to_string(),
+            vec![Rule::new("https://github.com/vpetersson", 301)],
         );
 
+        // Test exact match (without trailing slash)
         let result = handle_redirect(
-            axum::extract::Path("temp".to_string()),
-            axum::extract::State((rules, false)),
+            axum::extract::Path("github".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
         )
         .await;
-
         assert!(result.is_ok());
-        // The redirect is created successfully - we can't easily test the exact type
-        // without more complex setup, but we know it worked based on the Ok result
-    }
-
-    #[tokio::test]
-    async fn test_handle_redirect_not_found() {
-        let rules = HashMap::new();
 
+        // Test with trailing slash - should also work
         let result = handle_redirect(
-            axum::extract::Path("nonexistent".to_string()),
-            axum::extract::State((rules, false)),
+            axum::extract::Path("github/".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
         )
         .await;
+        assert!(result.is_ok());
 
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+        // Test with multiple trailing slashes
+        let result = handle_redirect(
+            axum::extract::Path("github///".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
-    async fn test_handle_redirect_invalid_status() {
+    async fn test_handle_redirect_trailing_slash_priority() {
         let mut rules = HashMap::new();
+        // Add both versions to test priority
         rules.insert(
-            "/invalid".to_string(),
-            ("https://example.com".to_string(), 200),
+            "/api".to_string(),
+            vec![Rule::new("https://api.example.com/v1", 301)],
+        );
+        rules.insert(
+            "/api/".to_string(),
+            vec![Rule::new("https://api.example.com/v2", 302)],
         );
 
+        // Test that exact match takes priority
         let result = handle_redirect(
-            axum::extract::Path("invalid".to_string()),
-            axum::extract::State((rules, false)),
+            axum::extract::Path("api/".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
         )
         .await;
-
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), StatusCode::INTERNAL_SERVER_ERROR);
-    }
-
-    #[test]
-    fn test_redirect_rule_deserialize() {
-        let csv_data = "url,target,status\n/test,https://example.com,301";
-        let mut reader = csv::Reader::from_reader(csv_data.as_bytes());
-        let mut rules = Vec::new();
-
-        for result in reader.deserialize() {
-            let rule: RedirectRule = result.unwrap();
-            rules.push(rule);
-        }
-
-        assert_eq!(rules.len(), 1);
-        assert_eq!(rules[0].url, "/test");
-        assert_eq!(rules[0].target, "https://example.com");
-        assert_eq!(rules[0].status, 301);
+        assert!(result.is_ok());
+        // This should match the exact /api/ rule (302), not the /api rule (301)
     }
 
-    #[test]
-    fn test_multiple_rules_same_url() {
+    #[tokio::test]
+    async fn test_handle_redirect_hierarchical() {
         let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/same,https://example.com/first,301").unwrap();
-        writeln!(temp_file, "/same,https://example.com/second,302").unwrap();
+        writeln!(
+            temp_file,
+            "url,target,status\n\
+             /,https://example.com,301\n\
+             /docs,https://docs.example.com,301\n\
+             /docs/v2,https://docs.example.com/v2,301\n\
+             /blog/*,https://blog.example.com/*,301"
+        )
+        .unwrap();
+        let rules = || {
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap()
+        };
+        let redirect = |state: &AppState, path: &str| {
+            let (state, path) = (state.clone(), path.to_string());
+            async move {
+                handle_redirect(
+                    Path(path),
+                    axum::extract::State(state),
+                    HeaderMap::new(),
+                    axum::extract::RawQuery(None),
+                )
+                .await
+                .map(|response| response.headers()[header::LOCATION].clone())
+            }
+        };
+        let options = AppOptions {
+            hierarchical: true,
+            ..Default::default()
+        };
+        let state = AppState::new(rules(), options);
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
+        // The most specific defined ancestor wins
+        assert_eq!(
+            redirect(&state, "docs/v2/intro/setup").await.unwrap(),
+            "https://docs.example.com/v2"
+        );
+        assert_eq!(
+            redirect(&state, "docs/v3/intro/").await.unwrap(),
+            "https://docs.example.com"
+        );
+        assert_eq!(
+            redirect(&state, "docs/v2").await.unwrap(),
+            "https://docs.example.com/v2"
+        );
+        // Wildcards still match before any ancestor
+        assert_eq!(
+            redirect(&state, "blog/post").await.unwrap(),
+            "https://blog.example.com/post"
+        );
+        // The root isn't an ancestor
+        assert_eq!(
+            redirect(&state, "unknown/page").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
 
-        // Should have only one entry (the last one overwrites the first)
-        assert_eq!(rules.len(), 1);
+        // Off by default
+        let state = AppState::new(rules(), AppOptions::default());
         assert_eq!(
-            rules.get("/same"),
-            Some(&("https://example.com/second".to_string(), 302))
+            redirect(&state, "docs/v2/intro").await.unwrap_err(),
+            StatusCode::NOT_FOUND
         );
     }
 
     #[tokio::test]
-    async fn test_integration_server_redirect() {
-        // Create a test CSV file
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/test,https://example.com/redirect,301").unwrap();
-        writeln!(temp_file, "/temp,https://example.com/temp,302").unwrap();
-
-        // Load the rules
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
-
-        // Create the app using the new function
-        let app = create_app(rules, false, false, None);
-
-        // Test redirect for /test
-        let request = axum::http::Request::builder()
-            .uri("/test")
-            .body(axum::body::Body::empty())
-            .unwrap();
-
-        let response = tower::ServiceExt::oneshot(app.clone(), request)
-            .await
-            .unwrap();
-        assert_eq!(response.status(), axum::http::StatusCode::MOVED_PERMANENTLY);
-
-        // Test redirect for /temp
-        let request = axum::http::Request::builder()
-            .uri("/temp")
-            .body(axum::body::Body::empty())
-            .unwrap();
-
-        let response = tower::ServiceExt::oneshot(app.clone(), request)
-            .await
-            .unwrap();
-        assert_eq!(response.status(), axum::http::StatusCode::FOUND);
-
-        // Test 404 for unknown path
-        let request = axum::http::Request::builder()
-            .uri("/unknown")
-            .body(axum::body::Body::empty())
-            .unwrap();
-
-        let response = tower::ServiceExt::oneshot(app.clone(), request)
-            .await
-            .unwrap();
-        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
-    }
+    async fn test_handle_redirect_exact_only() {
+        let options = AppOptions {
+            exact_only: true,
+            ..Default::default()
+        };
 
-    #[test]
-    fn test_create_app() {
+        // Only /x is defined, so /x/ must not fall back to it
         let mut rules = HashMap::new();
         rules.insert(
-            "/test".to_string(),
-            ("https://example.com".to_string(), 301),
+            "/x".to_string(),
+            vec![Rule::new("https://example.com/x", 301)],
         );
 
-        let app = create_app(rules, false, false, None);
-
-        // We can't test much about the router without running it,
-        // but we can verify it was created successfully
-        assert!(format!("{app:?}").contains("Router"));
-    }
-
-    #[test]
-    fn test_redirect_rule_debug() {
-        let rule = RedirectRule {
-            url: "/test".to_string(),
-            target: "https://example.com".to_string(),
-            status: 301,
-        };
-
-        let debug_str = format!("{rule:?}");
-        assert!(debug_str.contains("/test"));
-        assert!(debug_str.contains("https://example.com"));
-        assert!(debug_str.contains("301"));
-    }
-
-    #[test]
-    fn test_load_redirect_rules_malformed_status() {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/test,https://example.com,not_a_number").unwrap();
-
-        let result = load_redirect_rules(temp_file.path().to_str().unwrap());
-        assert!(result.is_err());
-    }
+        let result = handle_redirect(
+            axum::extract::Path("x".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), options.clone())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+        assert!(result.is_ok());
 
-    #[test]
-    fn test_load_redirect_rules_extra_columns() {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status,extra").unwrap();
-        writeln!(temp_file, "/test,https://example.com,301,ignored").unwrap();
+        let result = handle_redirect(
+            axum::extract::Path("x/".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), options.clone())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
-        assert_eq!(rules.len(), 1);
+        // A literal /x/ rule still matches
+        rules.insert(
+            "/x/".to_string(),
+            vec![Rule::new("https://example.com/x-slash", 302)],
+        );
+        let result = handle_redirect(
+            axum::extract::Path("x/".to_string()),
+            axum::extract::State(AppState::new(rules.into(), options)),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await;
+        let response = result.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
         assert_eq!(
-            rules.get("/test"),
-            Some(&("https://example.com".to_string(), 301))
+            response.headers().get("location").unwrap(),
+            "https://example.com/x-slash"
         );
     }
 
     #[tokio::test]
-    async fn test_handle_redirect_path_formatting() {
+    async fn test_compress() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("index.html"),
+            format!("<html><body>{}</body></html>", "<p>links</p>".repeat(50)),
+        )
+        .unwrap();
         let mut rules = HashMap::new();
         rules.insert(
-            "/test/path".to_string(),
-            ("https://example.com".to_string(), 301),
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
         );
+        let app = |compress| {
+            create_app(AppState::new(
+                rules.clone().into(),
+                AppOptions {
+                    static_dir: Some(temp_dir.path().to_path_buf()),
+                    compress,
+                    ..Default::default()
+                },
+            ))
+        };
+        let get = |app: Router, path: &str| {
+            let request = Request::builder()
+                .uri(path)
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+            async move { app.oneshot(request).await.unwrap() }
+        };
 
-        let result = handle_redirect(
-            axum::extract::Path("test/path".to_string()),
-            axum::extract::State((rules, false)),
-        )
-        .await;
-
-        assert!(result.is_ok());
-    }
+        let response = get(app(true), "/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
 
-    #[test]
-    fn test_load_redirect_rules_status_validation() {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/test1,https://example.com,301").unwrap();
-        writeln!(temp_file, "/test2,https://example.com,302").unwrap();
-        writeln!(temp_file, "/test3,https://example.com,303").unwrap();
+        // Redirects have no body to compress
+        let response = get(app(true), "/gh").await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
 
-        let result = load_redirect_rules(temp_file.path().to_str().unwrap());
-        assert!(result.is_err());
-        assert!(
-            result
-                .unwrap_err()
-                .to_string()
-                .contains("Invalid status code: 303")
-        );
+        let response = get(app(false), "/").await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
     }
 
-    #[test]
-    fn test_empty_hashmap() {
-        let rules = HashMap::new();
-        let app = create_app(rules, false, false, None);
-        assert!(format!("{app:?}").contains("Router"));
-    }
+    #[tokio::test]
+    async fn test_integration_static_dir_exact_only() {
+        use tempfile::TempDir;
 
-    #[test]
-    fn test_create_app_with_logging() {
+        let temp_dir = TempDir::new().unwrap();
         let mut rules = HashMap::new();
         rules.insert(
-            "/test".to_string(),
-            ("https://example.com".to_string(), 301),
+            "/x".to_string(),
+            vec![Rule::new("https://example.com/x", 301)],
         );
 
-        // Test app with logging enabled
-        let app_with_logging = create_app(rules.clone(), false, true, None);
-        assert!(format!("{app_with_logging:?}").contains("Router"));
-
-        // Test app without logging
-        let app_without_logging = create_app(rules, false, false, None);
-        assert!(format!("{app_without_logging:?}").contains("Router"));
-    }
+        let app = create_app(AppState::new(
+            rules.into(),
+            AppOptions {
+                static_dir: Some(temp_dir.path().to_path_buf()),
+                exact_only: true,
+                ..Default::default()
+            },
+        ));
 
-    #[test]
-    fn test_load_redirect_rules_with_whitespace() {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, " /test , https://example.com ,301").unwrap();
+        let request = axum::http::Request::builder()
+            .uri("/x/")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
-        // CSV library should handle whitespace in string fields
-        assert_eq!(rules.len(), 1);
-        assert!(rules.contains_key(" /test "));
+        let request = axum::http::Request::builder()
+            .uri("/x")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
     }
 
     #[test]
-    fn test_load_redirect_rules_with_empty_lines() {
+    fn test_load_redirect_rules_default_status() {
         let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/first,https://example.com/first,301").unwrap();
-        writeln!(temp_file).unwrap(); // Empty line
-        writeln!(temp_file, "   ").unwrap(); // Line with only whitespace
-        writeln!(temp_file, "/second,https://example.com/second,302").unwrap();
-        writeln!(temp_file).unwrap(); // Another empty line
-        writeln!(temp_file, "	").unwrap(); // Line with only tab
-        writeln!(temp_file, "/third,https://example.com/third,301").unwrap();
-
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
+        writeln!(temp_file, "url,target").unwrap();
+        writeln!(temp_file, "/gh,https://github.com/user").unwrap();
+        writeln!(temp_file, "/docs,https://docs.example.com").unwrap();
+        let path = temp_file.path().to_str().unwrap();
 
-        // Should have exactly 3 rules, empty/whitespace lines should be ignored
-        assert_eq!(rules.len(), 3);
+        let rules = load_redirect_rules(path, &LoadOptions::default()).unwrap();
         assert_eq!(
-            rules.get("/first"),
-            Some(&("https://example.com/first".to_string(), 301))
+            rules.exact.get("/gh"),
+            Some(&vec![Rule::new("https://github.com/user", 301)])
         );
         assert_eq!(
-            rules.get("/second"),
-            Some(&("https://example.com/second".to_string(), 302))
+            rules.exact.get("/docs"),
+            Some(&vec![Rule::new("https://docs.example.com", 301)])
         );
-        assert_eq!(
-            rules.get("/third"),
-            Some(&("https://example.com/third".to_string(), 301))
-        );
-    }
-
-    #[test]
-    fn test_load_redirect_rules_with_comments_and_empty_lines() {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "# GitHub and development links").unwrap();
-        writeln!(temp_file, "/gh,https://github.com/user,301").unwrap();
-        writeln!(temp_file).unwrap(); // Empty line
-        writeln!(temp_file, "# Marketing and promotional links").unwrap();
-        writeln!(temp_file, "   ").unwrap(); // Whitespace line
-        writeln!(temp_file, "/promo,https://site.com/promo,302").unwrap();
-        writeln!(temp_file).unwrap(); // Another empty line
-        writeln!(temp_file, "# Documentation").unwrap();
-        writeln!(temp_file, "/docs,https://docs.site.com,301").unwrap();
-
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
 
-        // Should have exactly 3 rules, comments and empty lines should be ignored
-        assert_eq!(rules.len(), 3);
-        assert_eq!(
-            rules.get("/gh"),
-            Some(&("https://github.com/user".to_string(), 301))
-        );
+        let load_options = LoadOptions {
+            default_status: 302,
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(path, &load_options).unwrap();
         assert_eq!(
-            rules.get("/promo"),
-            Some(&("https://site.com/promo".to_string(), 302))
+            rules.exact.get("/gh"),
+            Some(&vec![Rule::new("https://github.com/user", 302)])
         );
-        assert_eq!(
-            rules.get("/docs"),
-            Some(&("https://docs.site.com".to_string(), 301))
+
+        // The default is still restricted to the allowed set
+        let load_options = LoadOptions {
+            default_status: 307,
+            ..Default::default()
+        };
+        let result = load_redirect_rules(path, &load_options);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid status code: 307")
         );
     }
 
     #[test]
-    fn test_large_ruleset() {
+    fn test_load_redirect_rules_empty_status_uses_default() {
         let mut temp_file = NamedTempFile::new().unwrap();
         writeln!(temp_file, "url,target,status").unwrap();
-        for i in 0..1000 {
-            writeln!(temp_file, "/test{i},https://example.com/target{i},301").unwrap();
-        }
+        writeln!(temp_file, "/gh,https://github.com/user,").unwrap();
+        writeln!(temp_file, "/promo,https://example.com/promo,302").unwrap();
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
-        assert_eq!(rules.len(), 1000);
+        let load_options = LoadOptions {
+            default_status: 302,
+            ..Default::default()
+        };
+        let rules = load_redirect_rules(temp_file.path().to_str().unwrap(), &load_options).unwrap();
+        assert_eq!(rules.exact.get("/gh").unwrap()[0].status, 302);
+        assert_eq!(rules.exact.get("/promo").unwrap()[0].status, 302);
     }
 
     #[test]
-    fn test_special_characters_in_urls() {
+    fn test_load_redirect_rules_when_header() {
         let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "url,target,status,when_header").unwrap();
+        writeln!(temp_file, "/app,https://example.com/prod,301,").unwrap();
         writeln!(
             temp_file,
-            "/test-path_with.special?chars,https://example.com/target,301"
+            "/app,https://staging.example.com,302,X-Internal=1"
         )
         .unwrap();
+        writeln!(temp_file, "/app,https://beta.example.com,302,X-Beta=yes").unwrap();
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
-        assert_eq!(rules.len(), 1);
-        assert!(rules.contains_key("/test-path_with.special?chars"));
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        let candidates = rules.exact.get("/app").unwrap();
+
+        // Conditional rules keep file order, the unconditional one comes last
+        assert_eq!(candidates.len(), 3);
+        assert_eq!(candidates[0].target, "https://staging.example.com");
+        assert_eq!(
+            candidates[0].when_header,
+            Some((HeaderName::from_static("x-internal"), "1".to_string()))
+        );
+        assert_eq!(candidates[1].target, "https://beta.example.com");
+        assert_eq!(candidates[2].target, "https://example.com/prod");
+        assert_eq!(candidates[2].when_header, None);
     }
 
-    #[test]
-    fn test_load_redirect_rules_error_message() {
+    #[tokio::test]
+    async fn test_load_redirect_rules_host_slugs() {
         let mut temp_file = NamedTempFile::new().unwrap();
         writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/test,https://example.com,999").unwrap();
+        writeln!(temp_file, "/x,https://example.com/any-host,301").unwrap();
+        writeln!(temp_file, "go.example.com/x,https://example.com/go,302").unwrap();
+        writeln!(
+            temp_file,
+            "Links.Example.com/docs/*,https://docs.example.com/*,301"
+        )
+        .unwrap();
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
 
-        let result = load_redirect_rules(temp_file.path().to_str().unwrap());
-        assert!(result.is_err());
-        let error = result.unwrap_err();
-        assert!(error.to_string().contains("Invalid status code: 999"));
-        assert!(error.to_string().contains("Must be 301 or 302"));
-    }
+        assert_eq!(rules.exact["/x"].len(), 2);
+        assert_eq!(rules.exact["/x"][0].host.as_deref(), Some("go.example.com"));
+        assert_eq!(
+            rules.wildcard[0].rule.host.as_deref(),
+            Some("links.example.com")
+        );
 
-    #[test]
-    fn test_load_redirect_rules_simple_comments() {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "# This is a comment").unwrap();
-        writeln!(temp_file, "/test,https://example.com,301").unwrap();
-        writeln!(temp_file, "# Another comment").unwrap();
+        let state = AppState::new(rules, AppOptions::default());
+        let location = |path: &str, host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, host.parse().unwrap());
+            handle_redirect(
+                Path(path.to_string()),
+                axum::extract::State(state.clone()),
+                headers,
+                axum::extract::RawQuery(None),
+            )
+        };
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
+        let response = location("x", "go.example.com").await.unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/go"
+        );
+        let response = location("x", "other.example.com").await.unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/any-host"
+        );
+        let response = location("docs/intro", "links.example.com:3000")
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://docs.example.com/intro"
+        );
+        assert_eq!(
+            location("docs/intro", "go.example.com").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
 
-        assert_eq!(rules.len(), 1);
+    #[test]
+    fn test_split_host_slug() {
         assert_eq!(
-            rules.get("/test"),
-            Some(&("https://example.com".to_string(), 301))
+            split_host_slug("go.example.com/x"),
+            Some(("go.example.com".to_string(), "/x".to_string()))
         );
+        assert_eq!(
+            split_host_slug("localhost:3000/x"),
+            Some(("localhost:3000".to_string(), "/x".to_string()))
+        );
+        assert_eq!(split_host_slug("/x"), None);
+        assert_eq!(split_host_slug("github"), None);
+        assert_eq!(split_host_slug("docs/intro"), None);
+        assert_eq!(split_host_slug("~^/a.b/(.*)$"), None);
     }
 
     #[test]
-    fn test_load_redirect_rules_comments_only() {
+    fn test_load_redirect_rules_invalid_when_header() {
         let mut temp_file = NamedTempFile::new().unwrap();
-        writeln!(temp_file, "url,target,status").unwrap();
-        writeln!(temp_file, "/test1,https://example.com,301").unwrap();
-        writeln!(temp_file, "/test2,https://example.com,302").unwrap();
-        writeln!(temp_file, "/test3,https://example.com,301").unwrap();
+        writeln!(temp_file, "url,target,status,when_header").unwrap();
+        writeln!(temp_file, "/app,https://example.com,301,X-Internal").unwrap();
 
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid when_header")
+        );
+    }
 
-        assert_eq!(rules.len(), 3);
-        assert_eq!(
-            rules.get("/test1"),
-            Some(&("https://example.com".to_string(), 301))
+    #[tokio::test]
+    async fn test_handle_redirect_when_header() {
+        let mut rules = matcher::Rules::default();
+        add_rule(
+            &mut rules,
+            "/app".to_string(),
+            Rule::new("https://example.com/prod", 301),
         );
-        assert_eq!(
-            rules.get("/test2"),
-            Some(&("https://example.com".to_string(), 302))
+        add_rule(
+            &mut rules,
+            "/app".to_string(),
+            Rule {
+                when_header: Some((HeaderName::from_static("x-internal"), "1".to_string())),
+                ..Rule::new("https://staging.example.com", 302)
+            },
+        );
+        add_rule(
+            &mut rules,
+            "/internal".to_string(),
+            Rule {
+                when_header: Some((HeaderName::from_static("x-internal"), "1".to_string())),
+                ..Rule::new("https://internal.example.com", 302)
+            },
         );
+
+        // Header present: the conditional rule fires
+        let mut headers = HeaderMap::new();
+        headers.insert("x-internal", "1".parse().unwrap());
+        let response = handle_redirect(
+            axum::extract::Path("app".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), AppOptions::default())),
+            headers,
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
         assert_eq!(
-            rules.get("/test3"),
-            Some(&("https://example.com".to_string(), 301))
+            response.headers().get("location").unwrap(),
+            "https://staging.example.com"
         );
-    }
 
-    #[tokio::test]
-    async fn test_handle_redirect_with_query_params() {
-        let mut rules = HashMap::new();
-        rules.insert(
-            "/api/v1/users".to_string(),
-            ("https://api.example.com/users".to_string(), 301),
+        // Header absent: falls through to the unconditional rule
+        let response = handle_redirect(
+            axum::extract::Path("app".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers().get("location").unwrap(),
+            "https://example.com/prod"
         );
 
+        // Header with a different value and no fallback rule: 404
+        let mut headers = HeaderMap::new();
+        headers.insert("x-internal", "0".parse().unwrap());
         let result = handle_redirect(
-            axum::extract::Path("api/v1/users".to_string()),
-            axum::extract::State((rules, false)),
+            axum::extract::Path("internal".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            headers,
+            axum::extract::RawQuery(None),
         )
         .await;
-
-        assert!(result.is_ok());
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_validate_destinations_function_signature() {
-        // Test that the validation function handles different input scenarios
-        // without making actual HTTP calls
+    async fn test_gone_note() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,message,note").unwrap();
+        writeln!(temp_file, "/spring,,410,,expired campaign").unwrap();
+        writeln!(temp_file, "/summer,,410,Summer is over, ").unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(rules.exact["/summer"][0].note, None);
+        let state = AppState::new(rules, AppOptions::default());
+        let body = |response: Response| async {
+            let bytes = http_body_util::BodyExt::collect(response.into_body())
+                .await
+                .unwrap()
+                .to_bytes();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
 
-        // Test with malformed URLs
-        let mut rules = HashMap::new();
-        rules.insert("/test".to_string(), ("not-a-valid-url".to_string(), 301));
+        let response = handle_redirect(
+            axum::extract::Path("spring".to_string()),
+            axum::extract::State(state.clone()),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(
+            response.extensions().get::<RuleNote>().unwrap().0,
+            "expired campaign"
+        );
+        assert_eq!(
+            body(response).await,
+            format!("{DEFAULT_GONE_MESSAGE}\nReason: expired campaign")
+        );
 
-        let result = validate_destinations(&rules).await;
-        assert!(result.is_err());
+        // Without a note the body is just the message
+        let response = handle_redirect(
+            axum::extract::Path("summer".to_string()),
+            axum::extract::State(state),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert!(response.extensions().get::<RuleNote>().is_none());
+        assert_eq!(body(response).await, "Summer is over");
+    }
 
-        // Test with invalid protocols
-        let mut rules2 = HashMap::new();
-        rules2.insert("/test".to_string(), ("ftp://example.com".to_string(), 301));
+    #[test]
+    fn test_load_redirect_rules_gone_message() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status,when_header,message").unwrap();
+        writeln!(temp_file, "/old,,410,,Campaign ended in 2023").unwrap();
+        writeln!(temp_file, "/older,,410,,").unwrap();
 
-        let result2 = validate_destinations(&rules2).await;
-        assert!(result2.is_err());
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(
+            rules.exact.get("/old").unwrap()[0].message.as_deref(),
+            Some("Campaign ended in 2023")
+        );
+        assert_eq!(rules.exact.get("/older").unwrap()[0].message, None);
     }
 
     #[tokio::test]
-    async fn test_validate_destinations_error_formatting() {
-        // Test that validation errors are properly formatted
+    async fn test_webhook_events() {
         let mut rules = HashMap::new();
         rules.insert(
-            "/test1".to_string(),
-            ("http://invalid-domain-12345.local".to_string(), 301),
-        );
-        rules.insert(
-            "/test2".to_string(),
-            ("http://another-invalid-domain-67890.local".to_string(), 302),
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 302)],
         );
+        rules.insert("/old".to_string(), vec![Rule::new("", 410)]);
+        let (webhook, mut events) = Webhook::channel(1);
+        let state = AppState::new(rules.into(), AppOptions::default()).with_webhook(webhook);
+        let get = |slug: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::USER_AGENT, HeaderValue::from_static("curl/8.0"));
+            headers.insert("x-real-ip", HeaderValue::from_static("203.0.113.9"));
+            handle_redirect(
+                axum::extract::Path(slug.to_string()),
+                axum::extract::State(state.clone()),
+                headers,
+                axum::extract::RawQuery(None),
+            )
+        };
 
-        let result = validate_destinations(&rules).await;
-        assert!(result.is_err());
-
-        let error_msg = result.unwrap_err().to_string();
-        assert!(error_msg.contains("Validation failed"));
-        assert!(error_msg.contains("destinations"));
+        assert_eq!(get("gh").await.unwrap().status(), StatusCode::FOUND);
+        let event = events.try_recv().unwrap();
+        assert_eq!(
+            event,
+            RedirectEvent {
+                slug: "/gh".to_string(),
+                target: "https://github.com".to_string(),
+                status: 302,
+                ts: event.ts,
+                ua: Some("curl/8.0".to_string()),
+                ip: Some("203.0.113.9".to_string()),
+            }
+        );
+        assert!(event.ts > 0);
+
+        // Retired links aren't redirects
+        get("old").await.unwrap();
+        assert!(events.try_recv().is_err());
+
+        // Nothing drains the queue of one, so the second redirect is dropped
+        // without holding up the response
+        get("gh").await.unwrap();
+        assert_eq!(get("gh").await.unwrap().status(), StatusCode::FOUND);
+        assert_eq!(state.stats.snapshot().webhook_dropped, 1);
+        assert!(events.try_recv().is_ok());
+        assert!(events.try_recv().is_err());
     }
 
     #[tokio::test]
-    async fn test_validate_destinations_empty() {
-        let rules = HashMap::new();
+    async fn test_interstitial() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com/a?b=<c>", 302)],
+        );
+        rules.insert(
+            "/home".to_string(),
+            vec![Rule::new("https://go.example.com/", 301)],
+        );
+        rules.insert("/local".to_string(), vec![Rule::new("/docs", 301)]);
+        let state = AppState::new(
+            rules.into(),
+            AppOptions {
+                interstitial: true,
+                forward_query: true,
+                ..Default::default()
+            },
+        );
+        let get = |slug: &str, query: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, HeaderValue::from_static("go.example.com"));
+            handle_redirect(
+                Path(slug.to_string()),
+                axum::extract::State(state.clone()),
+                headers,
+                axum::extract::RawQuery(query.map(str::to_string)),
+            )
+        };
 
-        let result = validate_destinations(&rules).await;
-        assert!(result.is_ok());
+        // External targets get the page first
+        let response = get("gh", Some("utm=x")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::LOCATION).is_none());
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("going to <strong>https://github.com/a?b=&lt;c&gt;</strong>"));
+        assert!(body.contains(r#"<a href="?continue=1&amp;utm=x">Continue</a>"#));
+
+        // Following the link redirects, without passing `continue` on
+        let response = get("gh", Some("continue=1&utm=x")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://github.com/a?b=<c>&utm=x"
+        );
+
+        // Same-host and relative targets redirect straight away
+        for slug in ["home", "local"] {
+            let response = get(slug, None).await.unwrap();
+            assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY, "{slug}");
+        }
+        assert!(!is_external(
+            "https://go.example.com/x",
+            &HeaderMap::new(),
+            Some("https://GO.example.com")
+        ));
+        assert!(is_external("https://github.com", &HeaderMap::new(), None));
     }
 
     #[tokio::test]
-    async fn test_handle_redirect_trailing_slash() {
+    async fn test_handle_redirect_gone() {
         let mut rules = HashMap::new();
         rules.insert(
-            "/github".to_string(),
-            ("https://github.com/vpetersson".to_string(), 301),
+            "/old".to_string(),
+            vec![Rule {
+                message: Some("Campaign ended in 2023".to_string()),
+                ..Rule::new("", 410)
+            }],
         );
+        rules.insert("/older".to_string(), vec![Rule::new("", 410)]);
 
-        // Test exact match (without trailing slash)
-        let result = handle_redirect(
-            axum::extract::Path("github".to_string()),
-            axum::extract::State((rules.clone(), false)),
+        let response = handle_redirect(
+            axum::extract::Path("old".to_string()),
+            axum::extract::State(AppState::new(rules.clone().into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
         )
-        .await;
-        assert!(result.is_ok());
-
-        // Test with trailing slash - should also work
-        let result = handle_redirect(
-            axum::extract::Path("github/".to_string()),
-            axum::extract::State((rules.clone(), false)),
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert!(response.headers().get("location").is_none());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, "Campaign ended in 2023");
+
+        // No message falls back to the default text
+        let response = handle_redirect(
+            axum::extract::Path("older".to_string()),
+            axum::extract::State(AppState::new(rules.into(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
         )
-        .await;
-        assert!(result.is_ok());
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, DEFAULT_GONE_MESSAGE);
+    }
 
-        // Test with multiple trailing slashes
-        let result = handle_redirect(
-            axum::extract::Path("github///".to_string()),
-            axum::extract::State((rules.clone(), false)),
+    #[test]
+    fn test_load_redirect_rules_regex() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "/blog,https://new.example.com,301").unwrap();
+        writeln!(
+            temp_file,
+            r"~^/blog/(\d+)/(.+)$,https://new.example.com/posts/$2,301"
         )
-        .await;
-        assert!(result.is_ok());
+        .unwrap();
+
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules.regex.len(), 1);
+        assert_eq!(rules.regex[0].pattern.as_str(), r"^/blog/(\d+)/(.+)$");
+        assert_eq!(
+            rules.regex[0].rule.target,
+            "https://new.example.com/posts/$2"
+        );
+    }
+
+    #[test]
+    fn test_load_redirect_rules_invalid_regex() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "url,target,status").unwrap();
+        writeln!(temp_file, "~^/blog/(\\d+,https://example.com,301").unwrap();
+
+        let result =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default());
+        assert!(result.unwrap_err().to_string().contains("Invalid regex"));
     }
 
     #[tokio::test]
-    async fn test_handle_redirect_trailing_slash_priority() {
-        let mut rules = HashMap::new();
-        // Add both versions to test priority
-        rules.insert(
-            "/api".to_string(),
-            ("https://api.example.com/v1".to_string(), 301),
+    async fn test_handle_redirect_regex() {
+        let mut rules = Matcher::default();
+        add_rule(
+            &mut rules.exact,
+            "/blog/1/exact".to_string(),
+            Rule::new("https://example.com/exact", 301),
         );
-        rules.insert(
-            "/api/".to_string(),
-            ("https://api.example.com/v2".to_string(), 302),
+        rules.regex.push(RegexRule {
+            pattern: Regex::new(r"^/blog/(\d+)/(.+)$").unwrap(),
+            rule: Rule::new("https://new.example.com/posts/$2?id=${1}", 302),
+        });
+
+        // Captures are substituted into the target
+        let response = handle_redirect(
+            axum::extract::Path("blog/42/hello-world".to_string()),
+            axum::extract::State(AppState::new(rules.clone(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers().get("location").unwrap(),
+            "https://new.example.com/posts/hello-world?id=42"
         );
 
-        // Test that exact match takes priority
+        // Exact matches take precedence over regex rules
+        let response = handle_redirect(
+            axum::extract::Path("blog/1/exact".to_string()),
+            axum::extract::State(AppState::new(rules.clone(), AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers().get("location").unwrap(),
+            "https://example.com/exact"
+        );
+
+        // Non-matching paths fall through to 404
         let result = handle_redirect(
-            axum::extract::Path("api/".to_string()),
-            axum::extract::State((rules.clone(), false)),
+            axum::extract::Path("blog/not-a-number/post".to_string()),
+            axum::extract::State(AppState::new(rules, AppOptions::default())),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
         )
         .await;
-        assert!(result.is_ok());
-        // This should match the exact /api/ rule (302), not the /api rule (301)
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
@@ -1011,10 +7111,12 @@ mod tests {
         writeln!(temp_file, "/test302,https://example.com/302,302").unwrap();
 
         // Load the rules
-        let rules = load_redirect_rules(temp_file.path().to_str().unwrap()).unwrap();
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
 
         // Test classic redirect codes (default behavior)
-        let app_classic = create_app(rules.clone(), false, false, None);
+        let app_classic = create_app(AppState::new(rules.clone(), AppOptions::default()));
 
         // Test 301 -> MOVED_PERMANENTLY (301)
         let request = axum::http::Request::builder()
@@ -1045,7 +7147,13 @@ mod tests {
         );
 
         // Test modern redirect codes (with --modern flag)
-        let app_modern = create_app(rules.clone(), true, false, None);
+        let app_modern = create_app(AppState::new(
+            rules.clone(),
+            AppOptions {
+                modern: true,
+                ..Default::default()
+            },
+        ));
 
         // Test 301 -> PERMANENT_REDIRECT (308)
         let request = axum::http::Request::builder()
@@ -1076,35 +7184,444 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_noindex() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let rules = Matcher::from(rules);
+        let redirect = |noindex| {
+            let state = AppState::new(
+                rules.clone(),
+                AppOptions {
+                    noindex,
+                    ..Default::default()
+                },
+            );
+            handle_redirect(
+                Path("gh".to_string()),
+                axum::extract::State(state),
+                HeaderMap::new(),
+                axum::extract::RawQuery(None),
+            )
+        };
+
+        let response = redirect(true).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "https://github.com");
+        assert_eq!(response.headers()["x-robots-tag"], "noindex");
+
+        let response = redirect(false).await.unwrap();
+        assert!(!response.headers().contains_key("x-robots-tag"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_response_delay() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let state = AppState::new(
+            rules.into(),
+            AppOptions {
+                response_delay: Some(Duration::from_millis(250)),
+                ..Default::default()
+            },
+        );
+
+        let started = tokio::time::Instant::now();
+        let response = handle_redirect(
+            Path("gh".to_string()),
+            axum::extract::State(state),
+            HeaderMap::new(),
+            axum::extract::RawQuery(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert!(started.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn test_preconnect() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/docs".to_string(),
+            vec![Rule::new("https://docs.example.com:8443/guide?x=1", 302)],
+        );
+        rules.insert("/local".to_string(), vec![Rule::new("/docs", 302)]);
+        let rules = Matcher::from(rules);
+        let redirect = |slug: &str, preconnect| {
+            let state = AppState::new(
+                rules.clone(),
+                AppOptions {
+                    preconnect,
+                    ..Default::default()
+                },
+            );
+            handle_redirect(
+                Path(slug.to_string()),
+                axum::extract::State(state),
+                HeaderMap::new(),
+                axum::extract::RawQuery(None),
+            )
+        };
+
+        let response = redirect("docs", true).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://docs.example.com:8443/guide?x=1"
+        );
+        assert_eq!(
+            response.headers()[header::LINK],
+            "<https://docs.example.com:8443>; rel=preconnect"
+        );
+
+        let response = redirect("local", true).await.unwrap();
+        assert!(!response.headers().contains_key(header::LINK));
+
+        let response = redirect("docs", false).await.unwrap();
+        assert!(!response.headers().contains_key(header::LINK));
+    }
+
     #[test]
     fn test_create_redirect_response() {
         // Test classic codes
-        let response = create_redirect_response("https://example.com", 301, false);
+        let response = create_redirect_response("https://example.com", 301, false, None);
         assert!(response.is_ok());
         assert_eq!(response.unwrap().status(), StatusCode::MOVED_PERMANENTLY); // 301
 
-        let response = create_redirect_response("https://example.com", 302, false);
+        let response = create_redirect_response("https://example.com", 302, false, None);
         assert!(response.is_ok());
         assert_eq!(response.unwrap().status(), StatusCode::FOUND); // 302
 
         // Test modern codes
-        let response = create_redirect_response("https://example.com", 301, true);
+        let response = create_redirect_response("https://example.com", 301, true, None);
         assert!(response.is_ok());
         assert_eq!(response.unwrap().status(), StatusCode::PERMANENT_REDIRECT); // 308
 
-        let response = create_redirect_response("https://example.com", 302, true);
+        let response = create_redirect_response("https://example.com", 302, true, None);
         assert!(response.is_ok());
         assert_eq!(response.unwrap().status(), StatusCode::TEMPORARY_REDIRECT); // 307
 
         // Test invalid status code
-        let response = create_redirect_response("https://example.com", 200, false);
+        let response = create_redirect_response("https://example.com", 200, false, None);
         assert!(response.is_err());
     }
 
+    #[tokio::test]
+    async fn test_percent_encoded_paths() {
+        assert_eq!(decode_path("/caf%C3%A9").as_deref(), Some("/café"));
+        assert_eq!(decode_path("/a%2fb").as_deref(), Some("/a/b"));
+        assert_eq!(decode_path("/plain").as_deref(), Some("/plain"));
+        assert_eq!(decode_path("/%"), None);
+        assert_eq!(decode_path("/%Z"), None);
+        assert_eq!(decode_path("/%ZZ"), None);
+        assert_eq!(decode_path("/%FF"), None);
+
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/café".to_string(),
+            vec![Rule::new("https://example.com/coffee", 301)],
+        );
+        let static_dir = tempfile::tempdir().unwrap();
+        for static_dir in [None, Some(static_dir.path().to_path_buf())] {
+            let app = create_app(AppState::new(
+                Matcher::from(rules.clone()),
+                AppOptions {
+                    static_dir,
+                    ..Default::default()
+                },
+            ));
+            let status = |uri: &'static str| {
+                let app = app.clone();
+                async move {
+                    let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                    app.oneshot(request).await.unwrap().status()
+                }
+            };
+
+            assert_eq!(status("/caf%C3%A9").await, StatusCode::MOVED_PERMANENTLY);
+            assert_eq!(status("/caf%c3%a9").await, StatusCode::MOVED_PERMANENTLY);
+            assert_eq!(status("/caf%ZZ").await, StatusCode::BAD_REQUEST);
+            assert_eq!(status("/%").await, StatusCode::BAD_REQUEST);
+            assert_eq!(status("/%41bc").await, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_substituted_text_is_encoded() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(
+            temp_file,
+            "url,target,status\n/docs/*,https://docs.example.com/*,301"
+        )
+        .unwrap();
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        let app = create_app(AppState::new(rules, AppOptions::default()));
+        let location = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+                response.headers()[header::LOCATION]
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+
+        // A decoded CR/LF can't inject a header
+        assert_eq!(
+            location("/docs/a%0d%0aSet-Cookie:x").await,
+            "https://docs.example.com/a%0D%0ASet-Cookie:x"
+        );
+        // Nor turn into a query, fragment or literal percent
+        assert_eq!(
+            location("/docs/a%3Fb=1%23c%2520").await,
+            "https://docs.example.com/a%3Fb%3D1%23c%2520"
+        );
+        assert_eq!(
+            location("/docs/guide/intro").await,
+            "https://docs.example.com/guide/intro"
+        );
+
+        // Regex captures are encoded the same way
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(
+            temp_file,
+            "url,target,status\n~^/p/(.*)$,https://posts.example.com/$1,301"
+        )
+        .unwrap();
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        let app = create_app(AppState::new(rules, AppOptions::default()));
+        let request = Request::builder()
+            .uri("/p/a%23b%0dSet-Cookie:x")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://posts.example.com/a%23b%0DSet-Cookie:x"
+        );
+
+        // And so are template segments, which are substituted only once
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(
+            temp_file,
+            "url,target,status\n/u/{{name}}/{{id}},https://example.com/users/{{name}}/{{id}},301"
+        )
+        .unwrap();
+        let rules =
+            load_redirect_rules(temp_file.path().to_str().unwrap(), &LoadOptions::default())
+                .unwrap();
+        let app = create_app(AppState::new(rules, AppOptions::default()));
+        let location = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+                response.headers()[header::LOCATION]
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+        assert_eq!(
+            location("/u/a%3Fx=1/1").await,
+            "https://example.com/users/a%3Fx%3D1/1"
+        );
+        assert_eq!(
+            location("/u/a%23top/1").await,
+            "https://example.com/users/a%23top/1"
+        );
+        assert_eq!(
+            location("/u/a%0ASet-Cookie:x/1").await,
+            "https://example.com/users/a%0ASet-Cookie:x/1"
+        );
+        assert_eq!(
+            location("/u/%7Bid%7D/7").await,
+            "https://example.com/users/%7Bid%7D/7"
+        );
+
+        assert_eq!(
+            create_redirect_response("https://example.com/\r\nx", 301, false, None).unwrap_err(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_canonical_host() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 302)],
+        );
+        let app = create_app(AppState::new(
+            Matcher::from(rules),
+            AppOptions {
+                canonical_host: Some("go.example.com".to_string()),
+                ..Default::default()
+            },
+        ));
+        let request = |host: &str, uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header(header::HOST, host)
+                .header("x-forwarded-proto", "https")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for host in ["www.go.example.com", "203.0.113.7:3000"] {
+            let response = app
+                .clone()
+                .oneshot(request(host, "/gh?utm=x"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+            assert_eq!(
+                response.headers()[header::LOCATION],
+                "https://go.example.com/gh?utm=x"
+            );
+        }
+
+        // The canonical host (any port, any case) goes straight to the rules
+        let response = app
+            .clone()
+            .oneshot(request("Go.Example.com:443", "/gh"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::LOCATION], "https://github.com");
+
+        // Probes of the version endpoint by IP aren't redirected
+        let response = app
+            .clone()
+            .oneshot(request("203.0.113.7", DEFAULT_VERSION_PATH))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Only http and https are taken from X-Forwarded-Proto
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/gh")
+                    .header(header::HOST, "www.go.example.com")
+                    .header("x-forwarded-proto", "https://evil.example/?")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "http://go.example.com/gh"
+        );
+
+        // Nor are readiness probes, so a draining instance's 503 gets through
+        let app = create_app(AppState::new(
+            Matcher::default(),
+            AppOptions {
+                canonical_host: Some("go.example.com".to_string()),
+                admin_token: Some("secret".to_string()),
+                ..Default::default()
+            },
+        ));
+        let response = app
+            .clone()
+            .oneshot(request("203.0.113.7", "/readyz"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/_ready/false")
+                    .header(header::HOST, "203.0.113.7")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_body_is_empty() {
+        let mut rules = HashMap::new();
+        rules.insert(
+            "/gh".to_string(),
+            vec![Rule::new("https://github.com", 301)],
+        );
+        let app = create_app(AppState::new(Matcher::from(rules), AppOptions::default()));
+
+        let request = Request::builder().uri("/gh").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "0");
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert!(body.is_empty());
+
+        // --strict-empty-body also covers redirects built elsewhere
+        let app = Router::new()
+            .route(
+                "/moved",
+                get(|| async {
+                    (
+                        StatusCode::FOUND,
+                        [(header::LOCATION, "/new")],
+                        "<a href=\"/new\">moved</a>",
+                    )
+                }),
+            )
+            .route("/ok", get(|| async { "hello" }))
+            .layer(middleware::from_fn(empty_redirect_body_middleware));
+
+        let request = Request::builder()
+            .uri("/moved")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "0");
+        assert!(response.headers().get(header::CONTENT_TYPE).is_none());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert!(body.is_empty());
+
+        let request = Request::builder().uri("/ok").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(&body[..], b"hello");
+    }
+
     #[test]
     fn test_create_redirect_response_headers() {
         // Test that Location header is set correctly
-        let response = create_redirect_response("https://example.com/target", 301, false);
+        let response = create_redirect_response("https://example.com/target", 301, false, None);
         assert!(response.is_ok());
         let response = response.unwrap();
 
@@ -1113,13 +7630,80 @@ mod tests {
         assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
 
         // Test modern redirect with Location header
-        let response = create_redirect_response("https://github.com/vpetersson", 302, true);
+        let response = create_redirect_response("https://github.com/vpetersson", 302, true, None);
         assert!(response.is_ok());
         let response = response.unwrap();
 
         let location = response.headers().get("location").unwrap();
         assert_eq!(location, "https://github.com/vpetersson");
         assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT); // 307
+        assert!(response.headers().get(header::DATE).is_none());
+    }
+
+    #[test]
+    fn test_create_redirect_response_cacheable() {
+        for modern in [false, true] {
+            let response =
+                create_redirect_response("https://example.com", 301, modern, Some(3600)).unwrap();
+            let headers = response.headers();
+            assert_eq!(headers[header::CACHE_CONTROL], "public, max-age=3600");
+            assert_eq!(headers[header::AGE], "0");
+
+            let date = headers[header::DATE].to_str().unwrap();
+            let parsed = chrono::NaiveDateTime::parse_from_str(date, "%a, %d %b %Y %H:%M:%S GMT")
+                .unwrap()
+                .and_utc();
+            assert_eq!(http_date(parsed), date);
+            assert!((chrono::Utc::now() - parsed).num_seconds().abs() < 5);
+        }
+
+        // Temporary redirects stay uncached
+        let response =
+            create_redirect_response("https://example.com", 302, false, Some(3600)).unwrap();
+        assert!(response.headers().get(header::CACHE_CONTROL).is_none());
+        assert!(response.headers().get(header::DATE).is_none());
+
+        let time = chrono::DateTime::from_timestamp(784_111_777, 0).unwrap();
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn test_server_config() {
+        let mut config = NamedTempFile::new().unwrap();
+        writeln!(
+            config,
+            "[server]\nbind = \"127.0.0.1\"\nport = 8080\nmodern = true\nsilent = true"
+        )
+        .unwrap();
+        let path = config.path().to_str().unwrap();
+
+        // File values fill in everything not given on the command line
+        let matches = Cli::command().get_matches_from(["dslf", "--server-config", path]);
+        let cli = parse_cli(matches).unwrap();
+        assert_eq!(cli.bind, "127.0.0.1");
+        assert_eq!(cli.port, 8080);
+        assert!(cli.modern);
+        assert!(cli.silent);
+
+        // Flags take precedence
+        let matches = Cli::command().get_matches_from([
+            "dslf",
+            "--server-config",
+            path,
+            "--bind",
+            "0.0.0.0",
+            "--port",
+            "9000",
+        ]);
+        let cli = parse_cli(matches).unwrap();
+        assert_eq!(cli.bind, "0.0.0.0");
+        assert_eq!(cli.port, 9000);
+        assert!(cli.modern);
+
+        // Unknown keys are rejected rather than silently ignored
+        assert!(ServerSettings::parse("[server]\nprot = 8080").is_err());
+        // A file without a [server] section changes nothing
+        assert!(ServerSettings::parse("").unwrap().port.is_none());
     }
 
     #[test]
@@ -1207,6 +7791,90 @@ mod tests {
         assert!(cli.silent);
     }
 
+    #[test]
+    fn test_bind_address() {
+        assert_eq!(bind_address("0.0.0.0", 3000), "0.0.0.0:3000");
+        assert_eq!(bind_address("localhost", 3000), "localhost:3000");
+
+        // IPv6 literals are bracketed and parse as a socket address
+        let addr = bind_address("::1", 3000);
+        assert_eq!(addr, "[::1]:3000");
+        let parsed: SocketAddr = addr.parse().unwrap();
+        assert!(parsed.is_ipv6());
+        assert_eq!(parsed.port(), 3000);
+
+        assert_eq!(bind_address("::", 8080), "[::]:8080");
+        assert_eq!(bind_address("[::1]", 3000), "[::1]:3000");
+    }
+
+    #[tokio::test]
+    async fn test_static_dir_before_catch_all() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join(".well-known")).unwrap();
+        std::fs::write(
+            temp_dir.path().join(".well-known/security.txt"),
+            "Contact: mailto:security@example.com\n",
+        )
+        .unwrap();
+        std::fs::write(temp_dir.path().join("style.css"), "body { margin: 0 }").unwrap();
+
+        let rules = build_matcher(vec![
+            ParsedRule {
+                url: "/style.css".to_string(),
+                rule: Rule::new("https://cdn.example.com/style.css", 302),
+            },
+            ParsedRule {
+                url: "/gh".to_string(),
+                rule: Rule::new("https://github.com", 301),
+            },
+            ParsedRule {
+                url: "/*".to_string(),
+                rule: Rule::new("https://example.com/*", 301),
+            },
+        ])
+        .unwrap();
+        let app = create_app(AppState::new(
+            rules,
+            AppOptions {
+                static_dir: Some(temp_dir.path().to_path_buf()),
+                ..Default::default()
+            },
+        ));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        // A file beats the catch-all wildcard
+        let response = app
+            .clone()
+            .oneshot(get("/.well-known/security.txt"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(&body[..], b"Contact: mailto:security@example.com\n");
+
+        // Exact slugs still beat files, and the wildcard covers the rest
+        let response = app.clone().oneshot(get("/style.css")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let response = app.clone().oneshot(get("/gh")).await.unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "https://github.com");
+        let response = app.clone().oneshot(get("/missing.txt")).await.unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/missing.txt"
+        );
+        let response = app.oneshot(get("/../etc/passwd")).await.unwrap();
+        assert_ne!(response.status(), StatusCode::OK);
+    }
+
     #[tokio::test]
     async fn test_integration_static_dir_with_redirects() {
         use tempfile::TempDir;
@@ -1224,15 +7892,21 @@ mod tests {
         let mut rules = HashMap::new();
         rules.insert(
             "/gh".to_string(),
-            ("https://github.com/test".to_string(), 301),
+            vec![Rule::new("https://github.com/test", 301)],
         );
         rules.insert(
             "/blog".to_string(),
-            ("https://blog.example.com".to_string(), 302),
+            vec![Rule::new("https://blog.example.com", 302)],
         );
 
         // Create the app with both static_dir and redirect rules
-        let app = create_app(rules, false, false, Some(temp_dir.path().to_path_buf()));
+        let app = create_app(AppState::new(
+            rules.into(),
+            AppOptions {
+                static_dir: Some(temp_dir.path().to_path_buf()),
+                ..Default::default()
+            },
+        ));
 
         // Test 1: Root path should serve index.html (200 OK)
         let request = axum::http::Request::builder()
@@ -0,0 +1,1324 @@
+use crate::schedule::Schedule;
+use axum::http::{HeaderMap, HeaderName, header};
+use regex::Regex;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Redirect rules keyed by slug, see [`add_rule`] for the ordering of each entry
+pub type Rules = HashMap<String, Vec<Rule>, SlugHasher>;
+
+/// Slugs come from the operator's config, so with the `fxhash` feature the
+/// exact lookup trades SipHash's DoS resistance for speed
+#[cfg(feature = "fxhash")]
+pub type SlugHasher = rustc_hash::FxBuildHasher;
+#[cfg(not(feature = "fxhash"))]
+pub type SlugHasher = std::hash::RandomState;
+
+/// A single destination for a slug
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub target: String,
+    pub status: u16,
+    /// Only match when the request carries this header with this exact value
+    pub when_header: Option<(HeaderName, String)>,
+    /// Body returned for retired (410) links
+    pub message: Option<String>,
+    /// Why a retired link is gone, added to its body and access log line
+    pub note: Option<String>,
+    /// Sent instead of `target` while the health checker reports it down
+    pub fallback: Option<String>,
+    /// Serve `status` as written, even with `--modern`
+    pub literal: bool,
+    /// Referer domains (and their subdomains) allowed to use this rule; empty
+    /// means unrestricted
+    pub allow_referer: Vec<String>,
+    /// Only match requests for this `Host` (lowercase), from a `host/path` slug
+    pub host: Option<String>,
+    /// Targets that replace `target` at certain times of the week
+    pub schedule: Option<Schedule>,
+    /// Free-form label for finding the rule with `GET /_lookup`
+    pub keyword: Option<String>,
+    /// Targets served in turn instead of `target` alone
+    pub rotation: Option<Rotation>,
+}
+
+/// Round-robin over equal targets, from a `rotate` column. Clones share the
+/// position, so every copy of a loaded rule cycles together.
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    targets: Vec<String>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Rotation {
+    /// Starts at the first of `targets`, which must not be empty
+    pub fn new(targets: Vec<String>) -> Self {
+        assert!(!targets.is_empty(), "a rotation needs at least one target");
+        Self {
+            targets,
+            next: Arc::default(),
+        }
+    }
+
+    /// The target for this hit, wrapping around after the last
+    pub fn next(&self) -> &str {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.targets.len();
+        &self.targets[index]
+    }
+
+    pub fn targets(&self) -> &[String] {
+        &self.targets
+    }
+}
+
+/// Rules are equal when they rotate over the same targets, wherever they are
+impl PartialEq for Rotation {
+    fn eq(&self, other: &Self) -> bool {
+        self.targets == other.targets
+    }
+}
+
+impl Rule {
+    pub fn new(target: impl Into<String>, status: u16) -> Self {
+        Self {
+            target: target.into(),
+            status,
+            when_header: None,
+            message: None,
+            note: None,
+            fallback: None,
+            literal: false,
+            allow_referer: Vec::new(),
+            host: None,
+            schedule: None,
+            keyword: None,
+            rotation: None,
+        }
+    }
+
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        let header_matches = match &self.when_header {
+            Some((name, value)) => {
+                headers.get(name).and_then(|v| v.to_str().ok()) == Some(value.as_str())
+            }
+            None => true,
+        };
+        header_matches
+            && self
+                .host
+                .as_deref()
+                .is_none_or(|host| host_matches(host, headers))
+    }
+
+    /// Whether the rule only applies to some requests for its slug
+    pub fn is_conditional(&self) -> bool {
+        self.when_header.is_some() || self.host.is_some()
+    }
+
+    /// Whether the request's `Referer` host is one of [`Rule::allow_referer`].
+    /// A protected rule rejects requests without a parseable referer.
+    pub fn referer_allowed(&self, headers: &HeaderMap) -> bool {
+        if self.allow_referer.is_empty() {
+            return true;
+        }
+        let Some(host) = headers
+            .get(header::REFERER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| reqwest::Url::parse(v).ok())
+            .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        else {
+            return false;
+        };
+
+        self.allow_referer.iter().any(|domain| {
+            host == *domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
+
+/// Compares the request's `Host` with `host`, ignoring the request's port
+/// unless `host` names one
+pub fn host_matches(host: &str, headers: &HeaderMap) -> bool {
+    let Some(request_host) = headers.get(header::HOST).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let request_host = if host.contains(':') {
+        request_host
+    } else {
+        request_host
+            .rsplit_once(':')
+            .map_or(request_host, |(name, _)| name)
+    };
+    request_host.eq_ignore_ascii_case(host)
+}
+
+/// Adds a rule for `url`. Conditional rules are kept in file order ahead of
+/// the unconditional fallback, and a later unconditional rule replaces an
+/// earlier one.
+pub fn add_rule(rules: &mut Rules, url: String, rule: Rule) {
+    let candidates = rules.entry(url).or_default();
+    let fallback = candidates.iter().position(|r| !r.is_conditional());
+
+    match (rule.is_conditional(), fallback) {
+        (true, Some(index)) => candidates.insert(index, rule),
+        (false, Some(index)) => candidates[index] = rule,
+        _ => candidates.push(rule),
+    }
+}
+
+pub fn parse_header_condition(spec: &str) -> Result<(HeaderName, String), Box<dyn Error>> {
+    let (name, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid when_header: {spec}. Expected Header-Name=value"))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("Invalid header name in when_header: {spec}"))?;
+
+    Ok((name, value.trim().to_string()))
+}
+
+/// A row from the config, before it is compiled into a [`Matcher`]
+#[derive(Debug, Clone)]
+pub struct ParsedRule {
+    pub url: String,
+    pub rule: Rule,
+}
+
+/// A rule whose slug ends with `*` and matches every path under that prefix
+#[derive(Debug, Clone)]
+pub struct WildcardRule {
+    pub prefix: String,
+    pub rule: Rule,
+}
+
+/// A rule whose slug is a regular expression (url column prefixed with `~`)
+#[derive(Debug, Clone)]
+pub struct RegexRule {
+    pub pattern: Regex,
+    pub rule: Rule,
+}
+
+/// A rule whose slug has `{name}` path segments, e.g. `/u/{user}`. Each
+/// variable matches one non-empty segment and is substituted into the target.
+#[derive(Debug, Clone)]
+pub struct TemplateRule {
+    /// The slug as written in the config
+    pub slug: String,
+    segments: Vec<Segment>,
+    pub rule: Rule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Var(String),
+}
+
+impl TemplateRule {
+    /// Splits `slug` into segments, or returns `None` if it has no variables
+    fn segments(slug: &str) -> Option<Vec<Segment>> {
+        let segments: Vec<Segment> = slug
+            .split('/')
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|s| s.strip_suffix('}'))
+                    .filter(|name| {
+                        !name.is_empty()
+                            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    }) {
+                    Some(name) => Segment::Var(name.to_string()),
+                    None => Segment::Literal(segment.to_string()),
+                }
+            })
+            .collect();
+
+        segments
+            .iter()
+            .any(|s| matches!(s, Segment::Var(_)))
+            .then_some(segments)
+    }
+
+    /// The target with each `{name}` replaced by its segment of `path`,
+    /// passed through [`encode_substituted`]
+    fn expand(&self, path: &str) -> Option<String> {
+        let parts: Vec<&str> = path.split('/').collect();
+        if parts.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut bound: Vec<(&str, &str)> = Vec::new();
+        for (segment, part) in self.segments.iter().zip(parts) {
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Var(name) if !part.is_empty() => bound.push((name, part)),
+                _ => return None,
+            }
+        }
+
+        // One pass over the target, so a segment that itself looks like
+        // `{name}` is never expanded again
+        let target = &self.rule.target;
+        let mut expanded = String::with_capacity(target.len());
+        let mut rest = target.as_str();
+        while let Some(open) = rest.find('{') {
+            expanded.push_str(&rest[..open]);
+            rest = &rest[open..];
+            let part = rest.find('}').and_then(|close| {
+                let name = &rest[1..close];
+                bound
+                    .iter()
+                    .find(|(bound_name, _)| *bound_name == name)
+                    .map(|(_, part)| (close, *part))
+            });
+            match part {
+                Some((close, part)) => {
+                    expanded.push_str(&encode_substituted(part));
+                    rest = &rest[close + 1..];
+                }
+                None => {
+                    expanded.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        expanded.push_str(rest);
+        Some(expanded)
+    }
+}
+
+/// Which part of the [`Matcher`] a rule lives in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleKind {
+    Exact,
+    Template,
+    Wildcard,
+    Regex,
+}
+
+/// Immutable lookup structure built once per load.
+///
+/// Precedence: exact slugs (as-is, then without trailing slashes), then
+/// `{var}` templates in file order, then wildcard prefixes (longest first),
+/// then regex rules in file order.
+#[derive(Debug, Clone, Default)]
+pub struct Matcher {
+    pub exact: Rules,
+    pub template: Vec<TemplateRule>,
+    /// Sorted by descending prefix length, ties keep file order
+    pub wildcard: Vec<WildcardRule>,
+    pub regex: Vec<RegexRule>,
+    /// Memoizes non-exact matches, see [`Matcher::with_resolve_cache`]
+    pub resolve_cache: Option<ResolveCache>,
+}
+
+impl<S> From<HashMap<String, Vec<Rule>, S>> for Matcher {
+    fn from(exact: HashMap<String, Vec<Rule>, S>) -> Self {
+        Self {
+            exact: exact.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Matcher {
+    /// Caches up to `capacity` template, wildcard and regex matches by path.
+    /// Exact slugs are still looked up first on every request. The cache is
+    /// skipped when any non-exact rule has a condition, since its result
+    /// would then depend on more than the path.
+    pub fn with_resolve_cache(mut self, capacity: usize) -> Self {
+        let conditional = self.template.iter().any(|t| t.rule.is_conditional())
+            || self.wildcard.iter().any(|w| w.rule.is_conditional())
+            || self.regex.iter().any(|r| r.rule.is_conditional());
+        if capacity > 0 && !conditional {
+            self.resolve_cache = Some(ResolveCache::new(capacity));
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.template.len() + self.wildcard.len() + self.regex.len()
+    }
+
+    /// Every rule with its slug as written in the config, in lookup order
+    /// (exact slugs sorted alphabetically)
+    pub fn entries(&self) -> Vec<(Cow<'_, str>, RuleKind, &Rule)> {
+        let mut exact: Vec<_> = self.exact.iter().collect();
+        exact.sort_by(|a, b| a.0.cmp(b.0));
+
+        let exact = exact.into_iter().flat_map(|(url, candidates)| {
+            candidates
+                .iter()
+                .map(move |rule| (Cow::Borrowed(url.as_str()), RuleKind::Exact, rule))
+        });
+        let template = self
+            .template
+            .iter()
+            .map(|t| (Cow::Borrowed(t.slug.as_str()), RuleKind::Template, &t.rule));
+        let wildcard = self.wildcard.iter().map(|w| {
+            (
+                Cow::Owned(format!("{}*", w.prefix)),
+                RuleKind::Wildcard,
+                &w.rule,
+            )
+        });
+        let regex = self.regex.iter().map(|r| {
+            (
+                Cow::Owned(format!("~{}", r.pattern)),
+                RuleKind::Regex,
+                &r.rule,
+            )
+        });
+
+        exact.chain(template).chain(wildcard).chain(regex).collect()
+    }
+
+    /// Slugs added, removed or changed in `new` compared to `self`
+    pub fn diff(&self, new: &Matcher) -> RuleDiff {
+        fn by_slug(matcher: &Matcher) -> BTreeMap<String, Vec<&Rule>> {
+            let mut slugs: BTreeMap<String, Vec<&Rule>> = BTreeMap::new();
+            for (slug, _, rule) in matcher.entries() {
+                slugs.entry(slug.into_owned()).or_default().push(rule);
+            }
+            slugs
+        }
+
+        let old = by_slug(self);
+        let new = by_slug(new);
+        let mut diff = RuleDiff::default();
+
+        for (slug, rules) in &new {
+            match old.get(slug) {
+                None => diff.added.push(slug.clone()),
+                Some(old_rules) if old_rules != rules => diff.modified.push(slug.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .into_keys()
+            .filter(|slug| !new.contains_key(slug))
+            .collect();
+
+        diff
+    }
+}
+
+/// Changes between two rulesets, by slug and in sorted order
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RuleDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+/// Compiles parsed rows into a [`Matcher`]. A `url` ending with `*` becomes a
+/// wildcard rule, one starting with `~` a regex rule and one with a `{name}`
+/// segment a [`TemplateRule`]. Alias targets (`@slug`) are replaced by the
+/// referenced slug's target.
+pub fn build_matcher(mut parsed: Vec<ParsedRule>) -> Result<Matcher, Box<dyn Error>> {
+    resolve_aliases(&mut parsed)?;
+    let mut matcher = Matcher::default();
+
+    for ParsedRule { url, rule } in parsed {
+        if let Some(pattern) = url.strip_prefix('~') {
+            let pattern =
+                Regex::new(pattern).map_err(|e| format!("Invalid regex in url {url}: {e}"))?;
+            matcher.regex.push(RegexRule { pattern, rule });
+        } else if let Some(prefix) = url.strip_suffix('*') {
+            matcher.wildcard.push(WildcardRule {
+                prefix: prefix.to_string(),
+                rule,
+            });
+        } else if let Some(segments) = TemplateRule::segments(&url) {
+            matcher.template.push(TemplateRule {
+                slug: url,
+                segments,
+                rule,
+            });
+        } else {
+            add_rule(&mut matcher.exact, url, rule);
+        }
+    }
+
+    // Stable sort, so equal prefixes keep their file order
+    matcher
+        .wildcard
+        .sort_by_key(|w| std::cmp::Reverse(w.prefix.len()));
+
+    Ok(matcher)
+}
+
+/// `@y` and `@/y` both refer to the exact slug `/y`
+fn alias_slug(target: &str) -> Option<String> {
+    let name = target.strip_prefix('@')?;
+    Some(if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("/{name}")
+    })
+}
+
+/// Replaces `@slug` targets with the target of that slug's unconditional rule,
+/// following chains of aliases and rejecting cycles and dangling references
+fn resolve_aliases(parsed: &mut [ParsedRule]) -> Result<(), Box<dyn Error>> {
+    // Later rows replace earlier ones, matching `add_rule`
+    let targets: HashMap<&str, &str> = parsed
+        .iter()
+        .filter(|p| !p.rule.is_conditional())
+        .map(|p| (p.url.as_str(), p.rule.target.as_str()))
+        .collect();
+
+    let mut resolved = Vec::new();
+    for (index, p) in parsed.iter().enumerate() {
+        let Some(mut slug) = alias_slug(&p.rule.target) else {
+            continue;
+        };
+
+        let mut chain = vec![p.url.clone()];
+        let target = loop {
+            if chain.contains(&slug) {
+                chain.push(slug);
+                return Err(format!("Alias cycle: {}", chain.join(" -> ")).into());
+            }
+            let target = targets
+                .get(slug.as_str())
+                .ok_or_else(|| format!("Alias {} refers to unknown slug {slug}", p.url))?;
+            chain.push(slug);
+            match alias_slug(target) {
+                Some(next) => slug = next,
+                None => break target.to_string(),
+            }
+        };
+        resolved.push((index, target));
+    }
+
+    for (index, target) in resolved {
+        parsed[index].rule.target = target;
+    }
+
+    Ok(())
+}
+
+/// A matched rule and the target to send, after any substitution
+#[derive(Debug)]
+pub struct Matched<'a> {
+    /// The rule's slug as written in the config, e.g. `/docs/*`
+    pub slug: Cow<'a, str>,
+    pub rule: &'a Rule,
+    pub target: Cow<'a, str>,
+    /// Which kind of rule matched, for `--static-dir`'s precedence
+    pub kind: RuleKind,
+}
+
+/// The matching flags of [`match_rule`], from `--exact-only` and `--hierarchical`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchOptions {
+    pub exact_only: bool,
+    pub hierarchical: bool,
+}
+
+/// The rule a request for `path` is answered with, as the router picks it:
+/// [`find_rule`], then the nearest ancestor with `hierarchical`. Host rules
+/// read the `Host` header from `headers`. Does no I/O, so it can be
+/// benchmarked on its own (`cargo bench`).
+pub fn match_rule<'a>(
+    matcher: &'a Matcher,
+    path: &str,
+    headers: &HeaderMap,
+    options: MatchOptions,
+) -> Option<Matched<'a>> {
+    find_rule(matcher, path, headers, options.exact_only).or_else(|| {
+        options
+            .hierarchical
+            .then(|| find_ancestor(matcher, path, headers))
+            .flatten()
+    })
+}
+
+/// Finds the first rule matching the request, following the precedence
+/// documented on [`Matcher`]. The trailing-slash fallback is skipped when
+/// `exact_only` is set.
+pub fn find_rule<'a>(
+    matcher: &'a Matcher,
+    path: &str,
+    headers: &HeaderMap,
+    exact_only: bool,
+) -> Option<Matched<'a>> {
+    let exact = find_exact(matcher, path, headers).or_else(|| {
+        let trimmed = path.trim_end_matches('/');
+        if exact_only || trimmed == path {
+            None
+        } else {
+            find_exact(matcher, trimmed, headers)
+        }
+    });
+    if exact.is_some() {
+        return exact;
+    }
+
+    match &matcher.resolve_cache {
+        Some(cache) => {
+            if let Some(matched) = cache.get(matcher, path) {
+                return Some(matched);
+            }
+            let (kind, index, matched) = find_dynamic(matcher, path, headers, exact_only)?;
+            cache.insert(path, kind, index, &matched.target);
+            Some(matched)
+        }
+        None => find_dynamic(matcher, path, headers, exact_only).map(|(_, _, matched)| matched),
+    }
+}
+
+/// The nearest ancestor of `path` with an exact rule, for `--hierarchical`:
+/// `/docs/v2/intro` tries `/docs/v2`, then `/docs`. The root is not an
+/// ancestor, or every unknown path would land on it.
+pub fn find_ancestor<'a>(
+    matcher: &'a Matcher,
+    path: &str,
+    headers: &HeaderMap,
+) -> Option<Matched<'a>> {
+    let mut path = path.trim_end_matches('/');
+    while let Some((parent, _)) = path.rsplit_once('/') {
+        if parent.is_empty() {
+            return None;
+        }
+        if let Some(matched) = find_exact(matcher, parent, headers) {
+            return Some(matched);
+        }
+        path = parent;
+    }
+    None
+}
+
+fn find_exact<'a>(matcher: &'a Matcher, key: &str, headers: &HeaderMap) -> Option<Matched<'a>> {
+    let (slug, candidates) = matcher.exact.get_key_value(key)?;
+    let rule = candidates.iter().find(|rule| rule.matches(headers))?;
+    Some(Matched {
+        slug: Cow::Borrowed(slug),
+        rule,
+        target: Cow::Borrowed(&rule.target),
+        kind: RuleKind::Exact,
+    })
+}
+
+/// The template, wildcard and regex stages of [`find_rule`], with the kind
+/// and index of the matching rule
+fn find_dynamic<'a>(
+    matcher: &'a Matcher,
+    path: &str,
+    headers: &HeaderMap,
+    exact_only: bool,
+) -> Option<(RuleKind, usize, Matched<'a>)> {
+    let template = |path: &str| {
+        matcher
+            .template
+            .iter()
+            .enumerate()
+            .find_map(|(index, template)| {
+                if !template.rule.matches(headers) {
+                    return None;
+                }
+                let target = template.expand(path)?;
+                Some((
+                    RuleKind::Template,
+                    index,
+                    matched(matcher, RuleKind::Template, index, Cow::Owned(target)),
+                ))
+            })
+    };
+    let template = template(path).or_else(|| {
+        let trimmed = path.trim_end_matches('/');
+        if exact_only || trimmed == path {
+            None
+        } else {
+            template(trimmed)
+        }
+    });
+    if template.is_some() {
+        return template;
+    }
+
+    let wildcard = matcher
+        .wildcard
+        .iter()
+        .enumerate()
+        .find_map(|(index, wildcard_rule)| {
+            let rest = path.strip_prefix(&wildcard_rule.prefix)?;
+            let rule = &wildcard_rule.rule;
+            if !rule.matches(headers) {
+                return None;
+            }
+            // A target ending with `*` receives the rest of the path
+            let target = match rule.target.strip_suffix('*') {
+                Some(base) => Cow::Owned(format!("{base}{}", encode_substituted(rest))),
+                None => Cow::Borrowed(rule.target.as_str()),
+            };
+            Some((
+                RuleKind::Wildcard,
+                index,
+                matched(matcher, RuleKind::Wildcard, index, target),
+            ))
+        });
+    if wildcard.is_some() {
+        return wildcard;
+    }
+
+    matcher
+        .regex
+        .iter()
+        .enumerate()
+        .find_map(|(index, regex_rule)| {
+            if !regex_rule.rule.matches(headers) {
+                return None;
+            }
+            let captures = regex_rule.pattern.captures(path)?;
+            let target = expand_encoded(&captures, &regex_rule.rule.target);
+            Some((
+                RuleKind::Regex,
+                index,
+                matched(matcher, RuleKind::Regex, index, Cow::Owned(target)),
+            ))
+        })
+}
+
+/// Percent-encodes decoded request text before it is put into a target, so
+/// it can't break out of the URL (`?`, `#`, `&`) or the `Location` header
+/// (CR/LF). Unreserved characters, `/` and the path-safe `:@!$'()*,` are kept.
+fn encode_substituted(text: &str) -> Cow<'_, str> {
+    let safe = |b: u8| b.is_ascii_alphanumeric() || b"-._~/:@!$'()*,".contains(&b);
+    if text.bytes().all(safe) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut encoded = String::with_capacity(text.len() + 8);
+    for b in text.bytes() {
+        if safe(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// [`regex::Captures::expand`] with each capture passed through
+/// [`encode_substituted`]: `$1`, `${1}`, `$name` and `${name}` are replaced by
+/// the group (empty when it didn't take part), `$$` by `$`.
+fn expand_encoded(captures: &regex::Captures, template: &str) -> String {
+    let group = |name: &str| {
+        let found = match name.parse::<usize>() {
+            Ok(index) => captures.get(index),
+            Err(_) => captures.name(name),
+        };
+        found.map_or(Cow::Borrowed(""), |m| encode_substituted(m.as_str()))
+    };
+
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                rest.split_at(end)
+            }
+        };
+        if name.is_empty() {
+            // Not a group reference, keep the `$`
+            expanded.push('$');
+            continue;
+        }
+        expanded.push_str(&group(name));
+        rest = after;
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// A match for the non-exact rule at `index` in the `kind` list
+fn matched<'a>(
+    matcher: &'a Matcher,
+    kind: RuleKind,
+    index: usize,
+    target: Cow<'a, str>,
+) -> Matched<'a> {
+    let (slug, rule) = match kind {
+        RuleKind::Template => {
+            let template = &matcher.template[index];
+            (Cow::Borrowed(template.slug.as_str()), &template.rule)
+        }
+        RuleKind::Wildcard => {
+            let wildcard = &matcher.wildcard[index];
+            (Cow::Owned(format!("{}*", wildcard.prefix)), &wildcard.rule)
+        }
+        RuleKind::Regex => {
+            let regex = &matcher.regex[index];
+            (Cow::Owned(format!("~{}", regex.pattern)), &regex.rule)
+        }
+        RuleKind::Exact => unreachable!("exact rules aren't indexed"),
+    };
+    Matched {
+        slug,
+        rule,
+        target,
+        kind,
+    }
+}
+
+/// Bounded memo of non-exact matches keyed by request path, evicting the least
+/// recently used entry when full. It lives in the [`Matcher`], so a reload
+/// starts from an empty cache.
+#[derive(Debug)]
+pub struct ResolveCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+}
+
+/// Entries live in `slots`, threaded into a most- to least-recently used list
+/// by index, so lookups, promotions and evictions are all O(1)
+#[derive(Debug, Default)]
+struct CacheState {
+    by_path: HashMap<String, usize>,
+    slots: Vec<CacheSlot>,
+    /// Most recently used slot
+    head: Option<usize>,
+    /// Least recently used slot, the next to be evicted
+    tail: Option<usize>,
+}
+
+#[derive(Debug)]
+struct CacheSlot {
+    path: String,
+    entry: CachedMatch,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+#[derive(Debug)]
+struct CachedMatch {
+    kind: RuleKind,
+    index: usize,
+    target: String,
+}
+
+impl CacheState {
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = (self.slots[slot].prev, self.slots[slot].next);
+        match prev {
+            Some(prev) => self.slots[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.slots[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        self.slots[slot].prev = None;
+        self.slots[slot].next = self.head;
+        match self.head {
+            Some(head) => self.slots[head].prev = Some(slot),
+            None => self.tail = Some(slot),
+        }
+        self.head = Some(slot);
+    }
+
+    fn promote(&mut self, slot: usize) {
+        if self.head != Some(slot) {
+            self.unlink(slot);
+            self.push_front(slot);
+        }
+    }
+}
+
+impl ResolveCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::default(),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Lookups answered from the cache since the rules were loaded
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn get<'a>(&self, matcher: &'a Matcher, path: &str) -> Option<Matched<'a>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = *state.by_path.get(path)?;
+        state.promote(slot);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        let entry = &state.slots[slot].entry;
+        Some(matched(
+            matcher,
+            entry.kind,
+            entry.index,
+            Cow::Owned(entry.target.clone()),
+        ))
+    }
+
+    fn insert(&self, path: &str, kind: RuleKind, index: usize, target: &str) {
+        let entry = CachedMatch {
+            kind,
+            index,
+            target: target.to_string(),
+        };
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Another request may have cached the same path since our lookup
+        if let Some(&slot) = state.by_path.get(path) {
+            state.slots[slot].entry = entry;
+            state.promote(slot);
+            return;
+        }
+
+        let slot = match state.tail {
+            // Full, so the least recently used slot is reused
+            Some(tail) if state.slots.len() >= self.capacity => {
+                let old = std::mem::replace(&mut state.slots[tail].path, path.to_string());
+                state.by_path.remove(&old);
+                state.slots[tail].entry = entry;
+                state.unlink(tail);
+                tail
+            }
+            _ => {
+                state.slots.push(CacheSlot {
+                    path: path.to_string(),
+                    entry,
+                    prev: None,
+                    next: None,
+                });
+                state.slots.len() - 1
+            }
+        };
+        state.push_front(slot);
+        state.by_path.insert(path.to_string(), slot);
+    }
+}
+
+/// A copy starts empty, since its entries index into another [`Matcher`]
+impl Clone for ResolveCache {
+    fn clone(&self) -> Self {
+        Self::new(self.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(url: &str, target: &str) -> ParsedRule {
+        ParsedRule {
+            url: url.to_string(),
+            rule: Rule::new(target, 301),
+        }
+    }
+
+    fn resolve(matcher: &Matcher, path: &str) -> Option<String> {
+        find_rule(matcher, path, &HeaderMap::new(), false).map(|m| m.target.into_owned())
+    }
+
+    #[test]
+    fn test_host_condition() {
+        let rule = Rule {
+            host: Some("go.example.com".to_string()),
+            ..Rule::new("https://target.example.org", 302)
+        };
+        let host = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, value.parse().unwrap());
+            headers
+        };
+
+        assert!(rule.matches(&host("go.example.com")));
+        assert!(rule.matches(&host("GO.example.com:3000")));
+        assert!(!rule.matches(&host("example.com")));
+        assert!(!rule.matches(&HeaderMap::new()));
+        assert!(rule.is_conditional());
+
+        let with_port = Rule {
+            host: Some("localhost:3000".to_string()),
+            ..Rule::new("https://target.example.org", 302)
+        };
+        assert!(with_port.matches(&host("localhost:3000")));
+        assert!(!with_port.matches(&host("localhost:4000")));
+    }
+
+    #[test]
+    fn test_referer_allowed() {
+        let rule = Rule {
+            allow_referer: vec!["example.com".to_string()],
+            ..Rule::new("https://target.example.org", 302)
+        };
+        let referer = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::REFERER, value.parse().unwrap());
+            headers
+        };
+
+        assert!(rule.referer_allowed(&referer("https://example.com/page")));
+        assert!(rule.referer_allowed(&referer("https://blog.EXAMPLE.com/")));
+        assert!(!rule.referer_allowed(&referer("https://notexample.com/")));
+        assert!(!rule.referer_allowed(&referer("not a url")));
+        assert!(!rule.referer_allowed(&HeaderMap::new()));
+
+        // Unrestricted rules ignore the referer
+        assert!(Rule::new("https://target.example.org", 302).referer_allowed(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_build_matcher_sorts_rules() {
+        let matcher = build_matcher(vec![
+            parsed("/docs/*", "https://docs.example.com"),
+            parsed("~^/blog/(\\d+)$", "https://blog.example.com/$1"),
+            parsed("/docs/api/*", "https://api.example.com"),
+            parsed("/gh", "https://github.com"),
+        ])
+        .unwrap();
+
+        assert_eq!(matcher.len(), 4);
+        assert_eq!(matcher.exact.len(), 1);
+        assert_eq!(matcher.regex.len(), 1);
+        let prefixes: Vec<&str> = matcher.wildcard.iter().map(|w| w.prefix.as_str()).collect();
+        assert_eq!(prefixes, vec!["/docs/api/", "/docs/"]);
+    }
+
+    #[test]
+    fn test_template_rules() {
+        let matcher = build_matcher(vec![
+            parsed("/u/{user}", "https://profiles.example.com/{user}"),
+            parsed("/u/admin", "https://admin.example.com"),
+            parsed("/repo/{owner}/{name}", "https://github.com/{owner}/{name}"),
+            parsed("/u/*", "https://fallback.example.com"),
+            parsed("/{x}y", "https://literal.example.com"),
+        ])
+        .unwrap();
+        assert_eq!(matcher.template.len(), 2);
+        assert_eq!(matcher.exact.len(), 2);
+
+        assert_eq!(
+            resolve(&matcher, "/u/alice").as_deref(),
+            Some("https://profiles.example.com/alice")
+        );
+        assert_eq!(
+            resolve(&matcher, "/u/alice/").as_deref(),
+            Some("https://profiles.example.com/alice")
+        );
+        assert_eq!(
+            resolve(&matcher, "/repo/rust-lang/rust").as_deref(),
+            Some("https://github.com/rust-lang/rust")
+        );
+        // Exact slugs win over templates, templates over wildcards
+        assert_eq!(
+            resolve(&matcher, "/u/admin").as_deref(),
+            Some("https://admin.example.com")
+        );
+        assert_eq!(
+            resolve(&matcher, "/u/alice/extra").as_deref(),
+            Some("https://fallback.example.com")
+        );
+        // A variable needs a non-empty segment
+        assert_eq!(resolve(&matcher, "/u"), None);
+        assert_eq!(resolve(&matcher, "/repo//rust"), None);
+        assert_eq!(resolve(&matcher, "/repo/rust-lang"), None);
+    }
+
+    #[test]
+    fn test_expand_encoded() {
+        let pattern = Regex::new(r"^/(?P<section>[^/]+)/(\d+)/(.*)$").unwrap();
+        let captures = pattern.captures("/blog/42/a b?c").unwrap();
+
+        // Same syntax as `Captures::expand` for safe text
+        for template in [
+            "https://x.com/$1/$2",
+            "https://x.com/${section}-${2}",
+            "https://x.com/$section/$$2",
+            "https://x.com/$9/${missing}/$",
+            "https://x.com/${2",
+        ] {
+            let mut expected = String::new();
+            captures.expand(template, &mut expected);
+            assert_eq!(expand_encoded(&captures, template), expected, "{template}");
+        }
+        assert_eq!(
+            expand_encoded(&captures, "https://x.com/$3"),
+            "https://x.com/a%20b%3Fc"
+        );
+    }
+
+    #[test]
+    fn test_large_ruleset_lookup() {
+        // Runs under whichever `SlugHasher` the build picked (see `fxhash`)
+        let count = 5_000;
+        let matcher = build_matcher(
+            (0..count)
+                .map(|i| parsed(&format!("/s{i}"), &format!("https://example.com/{i}")))
+                .chain([parsed("/docs/*", "https://docs.example.com")])
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(matcher.exact.len(), count);
+
+        for i in 0..count {
+            let target = format!("https://example.com/{i}");
+            assert_eq!(resolve(&matcher, &format!("/s{i}")), Some(target.clone()));
+            assert_eq!(resolve(&matcher, &format!("/s{i}/")), Some(target));
+        }
+        assert_eq!(resolve(&matcher, &format!("/s{count}")), None);
+        assert_eq!(
+            resolve(&matcher, "/docs/s1"),
+            Some("https://docs.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_cache() {
+        let matcher = build_matcher(vec![
+            parsed("~^/blog/(\\d+)$", "https://blog.example.com/$1"),
+            parsed("/u/{user}", "https://profiles.example.com/{user}"),
+            parsed("/gh", "https://github.com"),
+        ])
+        .unwrap()
+        .with_resolve_cache(2);
+        let cache = matcher.resolve_cache.as_ref().unwrap();
+
+        for _ in 0..3 {
+            let matched = find_rule(&matcher, "/blog/42", &HeaderMap::new(), false).unwrap();
+            assert_eq!(matched.target, "https://blog.example.com/42");
+            assert_eq!(matched.slug, "~^/blog/(\\d+)$");
+        }
+        assert_eq!(cache.hits(), 2);
+
+        // Exact slugs and misses aren't cached
+        assert_eq!(
+            resolve(&matcher, "/gh").as_deref(),
+            Some("https://github.com")
+        );
+        assert_eq!(resolve(&matcher, "/nope"), None);
+        assert_eq!(cache.hits(), 2);
+
+        // The least recently used path is evicted
+        resolve(&matcher, "/u/alice");
+        resolve(&matcher, "/blog/42");
+        resolve(&matcher, "/blog/7");
+        assert_eq!(cache.hits(), 3);
+        resolve(&matcher, "/blog/42");
+        assert_eq!(cache.hits(), 4);
+        resolve(&matcher, "/u/alice");
+        assert_eq!(cache.hits(), 4);
+
+        // A run of unique paths keeps reusing the same slots
+        for i in 0..10 {
+            resolve(&matcher, &format!("/u/user{i}"));
+        }
+        let state = cache.state.lock().unwrap();
+        assert_eq!((state.slots.len(), state.by_path.len()), (2, 2));
+        assert!(state.by_path.contains_key("/u/user9"));
+        assert!(state.by_path.contains_key("/u/user8"));
+        drop(state);
+
+        let copy = matcher.clone();
+        resolve(&copy, "/blog/42");
+        assert_eq!(copy.resolve_cache.unwrap().hits(), 0);
+
+        // Conditions make the result depend on headers, so no cache
+        let conditional = build_matcher(vec![ParsedRule {
+            url: "/docs/*".to_string(),
+            rule: Rule {
+                host: Some("docs.example.com".to_string()),
+                ..Rule::new("https://docs.example.org", 302)
+            },
+        }])
+        .unwrap()
+        .with_resolve_cache(2);
+        assert!(conditional.resolve_cache.is_none());
+    }
+
+    #[test]
+    fn test_build_matcher_invalid_regex() {
+        let result = build_matcher(vec![parsed("~^/blog/(\\d+", "https://example.com")]);
+        assert!(result.unwrap_err().to_string().contains("Invalid regex"));
+    }
+
+    #[test]
+    fn test_build_matcher_resolves_aliases() {
+        let matcher = build_matcher(vec![
+            parsed("/x", "@y"),
+            parsed("/y", "@/z"),
+            parsed("/z", "https://example.com"),
+            parsed("/docs/*", "@z"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            resolve(&matcher, "/x").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            resolve(&matcher, "/y").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            resolve(&matcher, "/docs/intro").as_deref(),
+            Some("https://example.com")
+        );
+    }
+
+    #[test]
+    fn test_build_matcher_dangling_alias() {
+        let result = build_matcher(vec![parsed("/x", "@missing")]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Alias /x refers to unknown slug /missing"
+        );
+    }
+
+    #[test]
+    fn test_build_matcher_alias_cycle() {
+        let result = build_matcher(vec![
+            parsed("/a", "@b"),
+            parsed("/b", "@c"),
+            parsed("/c", "@a"),
+        ]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Alias cycle: /a -> /b -> /c -> /a"
+        );
+    }
+
+    #[test]
+    fn test_find_rule_precedence() {
+        let matcher = build_matcher(vec![
+            parsed("~^/docs/(.+)$", "https://regex.example.com/$1"),
+            parsed("/docs/*", "https://docs.example.com/*"),
+            parsed("/docs/api/*", "https://api.example.com"),
+            parsed("/docs/intro", "https://exact.example.com"),
+            parsed("~^/blog/(\\d+)$", "https://blog.example.com/$1"),
+        ])
+        .unwrap();
+
+        // Exact beats wildcard and regex, including the trailing-slash fallback
+        assert_eq!(
+            resolve(&matcher, "/docs/intro").as_deref(),
+            Some("https://exact.example.com")
+        );
+        assert_eq!(
+            resolve(&matcher, "/docs/intro/").as_deref(),
+            Some("https://exact.example.com")
+        );
+
+        // Longest wildcard prefix wins, `*` in the target receives the rest
+        assert_eq!(
+            resolve(&matcher, "/docs/api/users").as_deref(),
+            Some("https://api.example.com")
+        );
+        assert_eq!(
+            resolve(&matcher, "/docs/guide/setup").as_deref(),
+            Some("https://docs.example.com/guide/setup")
+        );
+
+        // Wildcards beat regex rules, regex still covers the rest
+        assert_eq!(
+            resolve(&matcher, "/blog/42").as_deref(),
+            Some("https://blog.example.com/42")
+        );
+        assert_eq!(resolve(&matcher, "/blog/latest"), None);
+        assert_eq!(resolve(&matcher, "/docs"), None);
+    }
+
+    #[test]
+    fn test_find_rule_reports_slug() {
+        let matcher = build_matcher(vec![
+            parsed("/gh", "https://github.com"),
+            parsed("/docs/*", "https://docs.example.com/*"),
+            parsed("~^/blog/(\\d+)$", "https://blog.example.com/$1"),
+        ])
+        .unwrap();
+
+        let slug = |path| {
+            find_rule(&matcher, path, &HeaderMap::new(), false)
+                .map(|m| m.slug.into_owned())
+                .unwrap()
+        };
+        assert_eq!(slug("/gh/"), "/gh");
+        assert_eq!(slug("/docs/intro"), "/docs/*");
+        assert_eq!(slug("/blog/7"), "~^/blog/(\\d+)$");
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = build_matcher(vec![
+            parsed("/same", "https://same.example.com"),
+            parsed("/changed", "https://before.example.com"),
+            parsed("/gone", "https://gone.example.com"),
+        ])
+        .unwrap();
+        let new = build_matcher(vec![
+            parsed("/same", "https://same.example.com"),
+            parsed("/changed", "https://after.example.com"),
+            parsed("/docs/*", "https://docs.example.com"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            old.diff(&new),
+            RuleDiff {
+                added: vec!["/docs/*".to_string()],
+                removed: vec!["/gone".to_string()],
+                modified: vec!["/changed".to_string()],
+            }
+        );
+        assert_eq!(new.diff(&new), RuleDiff::default());
+    }
+
+    #[test]
+    fn test_entries_follow_lookup_order() {
+        let matcher = build_matcher(vec![
+            parsed("~^/x/(.+)$", "https://regex.example.com/$1"),
+            parsed("/x/*", "https://wildcard.example.com"),
+            parsed("/b", "https://b.example.com"),
+            parsed("/a", "https://a.example.com"),
+            parsed("/t/{id}", "https://t.example.com/{id}"),
+        ])
+        .unwrap();
+
+        let entries: Vec<(String, RuleKind)> = matcher
+            .entries()
+            .into_iter()
+            .map(|(url, kind, _)| (url.into_owned(), kind))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("/a".to_string(), RuleKind::Exact),
+                ("/b".to_string(), RuleKind::Exact),
+                ("/t/{id}".to_string(), RuleKind::Template),
+                ("/x/*".to_string(), RuleKind::Wildcard),
+                ("~^/x/(.+)$".to_string(), RuleKind::Regex),
+            ]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use chrono::{Datelike, Local, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+
+/// Targets picked by day of week and time of day, from a `schedule` column
+/// such as `mon-fri 09:00-17:00 => https://chat.example.com; else => https://example.com/form`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    windows: Vec<Window>,
+    /// Target outside every window; the rule's own target when unset
+    otherwise: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    /// Indexed from Monday
+    days: [bool; 7],
+    /// Minutes since midnight. A window with `end <= start` wraps past
+    /// midnight, the early hours counting towards the day it started on.
+    start: u32,
+    end: u32,
+    target: String,
+}
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Schedule {
+    /// Parses `;`-separated `DAYS HH:MM-HH:MM => URL` clauses and an optional
+    /// `else => URL`. DAYS is `daily`, a day (`mon`), a range (`mon-fri`) or a
+    /// comma-separated list of those.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut schedule = Self {
+            windows: Vec::new(),
+            otherwise: None,
+        };
+
+        for clause in spec.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+            let (when, target) = clause
+                .split_once("=>")
+                .ok_or_else(|| format!("expected WHEN => URL, got {clause}"))?;
+            let (when, target) = (when.trim(), target.trim().to_string());
+            if target.is_empty() {
+                return Err(format!("missing target in {clause}"));
+            }
+
+            if when == "else" {
+                if schedule.otherwise.replace(target).is_some() {
+                    return Err("more than one else clause".to_string());
+                }
+                continue;
+            }
+
+            let (days, hours) = when
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("expected DAYS HH:MM-HH:MM, got {when}"))?;
+            let (start, end) = hours
+                .trim()
+                .split_once('-')
+                .ok_or_else(|| format!("expected HH:MM-HH:MM, got {hours}"))?;
+            schedule.windows.push(Window {
+                days: parse_days(days)?,
+                start: parse_time(start)?,
+                end: parse_time(end)?,
+                target,
+            });
+        }
+
+        if schedule.windows.is_empty() {
+            return Err("schedule has no time windows".to_string());
+        }
+        Ok(schedule)
+    }
+
+    /// Every window target, then the `else` target
+    pub fn targets(&self) -> impl Iterator<Item = &String> {
+        self.windows
+            .iter()
+            .map(|w| &w.target)
+            .chain(&self.otherwise)
+    }
+
+    /// Mutable [`Schedule::targets`], for rewriting them at load time
+    pub fn targets_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.windows
+            .iter_mut()
+            .map(|w| &mut w.target)
+            .chain(&mut self.otherwise)
+    }
+
+    /// The target for local time `now`: the first window containing it, else
+    /// the `else` target, else `None` (use the rule's target)
+    pub fn target_at(&self, now: NaiveDateTime) -> Option<&str> {
+        let day = now.weekday().num_days_from_monday() as usize;
+        let yesterday = (day + 6) % 7;
+        let minute = now.hour() * 60 + now.minute();
+
+        self.windows
+            .iter()
+            .find(|w| {
+                if w.start < w.end {
+                    w.days[day] && (w.start..w.end).contains(&minute)
+                } else {
+                    (w.days[day] && minute >= w.start) || (w.days[yesterday] && minute < w.end)
+                }
+            })
+            .map(|w| w.target.as_str())
+            .or(self.otherwise.as_deref())
+    }
+}
+
+/// The current wall-clock time in `tz`, or in the server's local zone
+pub fn now(tz: Option<Tz>) -> NaiveDateTime {
+    match tz {
+        Some(tz) => Utc::now().with_timezone(&tz).naive_local(),
+        None => Local::now().naive_local(),
+    }
+}
+
+fn parse_days(spec: &str) -> Result<[bool; 7], String> {
+    let day = |name: &str| {
+        DAYS.iter()
+            .position(|d| name.eq_ignore_ascii_case(d))
+            .ok_or_else(|| format!("unknown day {name}, expected mon..sun"))
+    };
+
+    let mut days = [false; 7];
+    for part in spec.split(',') {
+        if part.eq_ignore_ascii_case("daily") {
+            days = [true; 7];
+            continue;
+        }
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (day(from)?, day(to)?);
+                // `fri-mon` wraps over the weekend
+                let mut d = from;
+                loop {
+                    days[d] = true;
+                    if d == to {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => days[day(part)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+/// `HH:MM` as minutes since midnight; `24:00` is allowed as an end time
+fn parse_time(spec: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time {spec}, expected HH:MM");
+    let (hours, minutes) = spec.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if minutes >= 60 || hours * 60 + minutes > 24 * 60 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// 2024-06-03 is a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 6, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_schedule_target_at() {
+        let schedule = Schedule::parse(
+            "mon-fri 09:00-17:00 => https://chat.example.com; \
+             sat 22:00-02:00 => https://night.example.com; \
+             else => https://example.com/contact",
+        )
+        .unwrap();
+
+        // In hours on a Wednesday
+        assert_eq!(
+            schedule.target_at(at(5, 9, 0)),
+            Some("https://chat.example.com")
+        );
+        assert_eq!(
+            schedule.target_at(at(5, 16, 59)),
+            Some("https://chat.example.com")
+        );
+        // Out of hours and at the weekend
+        assert_eq!(
+            schedule.target_at(at(5, 17, 0)),
+            Some("https://example.com/contact")
+        );
+        assert_eq!(
+            schedule.target_at(at(8, 12, 0)),
+            Some("https://example.com/contact")
+        );
+        // Windows can wrap past midnight into the next day
+        assert_eq!(
+            schedule.target_at(at(8, 23, 30)),
+            Some("https://night.example.com")
+        );
+        assert_eq!(
+            schedule.target_at(at(9, 1, 30)),
+            Some("https://night.example.com")
+        );
+        assert_eq!(
+            schedule.target_at(at(8, 1, 30)),
+            Some("https://example.com/contact")
+        );
+
+        let without_else =
+            Schedule::parse("sat,sun 00:00-24:00 => https://weekend.example.com").unwrap();
+        assert_eq!(
+            without_else.target_at(at(9, 10, 0)),
+            Some("https://weekend.example.com")
+        );
+        assert_eq!(without_else.target_at(at(3, 10, 0)), None);
+    }
+
+    #[test]
+    fn test_schedule_parse_errors() {
+        for spec in [
+            "",
+            "else => https://example.com",
+            "mon-fri => https://example.com",
+            "mon-fri 09:00-17:00",
+            "someday 09:00-17:00 => https://example.com",
+            "mon 9-17 => https://example.com",
+            "mon 09:00-25:00 => https://example.com",
+            "mon 09:60-17:00 => https://example.com",
+            "mon 09:00-17:00 =>",
+            "daily 09:00-17:00 => a; else => b; else => c",
+        ] {
+            assert!(Schedule::parse(spec).is_err(), "{spec}");
+        }
+        assert!(Schedule::parse("Fri-Mon 18:00-09:00 => https://example.com").is_ok());
+    }
+}
@@ -0,0 +1,430 @@
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Per-slug hit counts since boot, keyed by the slug as written in the config
+#[derive(Debug, Default)]
+pub struct HitCounter {
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl HitCounter {
+    /// Counts a hit for `slug` and returns its new total
+    pub fn record(&self, slug: &str) -> u64 {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        match counts.get_mut(slug) {
+            Some(count) => {
+                *count += 1;
+                *count
+            }
+            None => {
+                counts.insert(slug.to_string(), 1);
+                1
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        let counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        counts
+            .iter()
+            .map(|(slug, &count)| (slug.clone(), count))
+            .collect()
+    }
+}
+
+/// Microsecond values below this get a bucket each
+const LINEAR_BUCKETS: usize = 16;
+/// Each doubling above that is split into this many buckets, so a reported
+/// percentile is at most 25% above the true value
+const SUB_BUCKETS: usize = 4;
+/// Doublings covered above the linear range, up to 2^26µs (~67s); anything
+/// slower lands in the last bucket
+const DOUBLINGS: usize = 22;
+
+/// Log-linear histogram of handling times in microseconds, cheap enough to
+/// update on every redirect without a lock
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Vec<AtomicU64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: (0..LINEAR_BUCKETS + DOUBLINGS * SUB_BUCKETS)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+}
+
+/// Redirect handling time percentiles in milliseconds, `null` before the
+/// first redirect
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LatencySnapshot {
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket(micros).min(self.buckets.len() - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The upper bound, in milliseconds, of the bucket holding the `quantile`
+    /// (0..=1) sample
+    pub fn percentile(&self, quantile: f64) -> Option<f64> {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+
+        let rank = ((quantile * total as f64).ceil() as u64).clamp(1, total);
+        let mut seen = 0;
+        let index = counts.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(bucket_upper_bound(index) as f64 / 1000.0)
+    }
+
+    pub fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            p50_ms: self.percentile(0.50),
+            p95_ms: self.percentile(0.95),
+            p99_ms: self.percentile(0.99),
+        }
+    }
+}
+
+fn bucket(micros: u64) -> usize {
+    if micros < LINEAR_BUCKETS as u64 {
+        return micros as usize;
+    }
+    let exponent = micros.ilog2() as usize;
+    let sub = (micros >> (exponent - 2)) as usize & (SUB_BUCKETS - 1);
+    LINEAR_BUCKETS + (exponent - 4) * SUB_BUCKETS + sub
+}
+
+/// The smallest value, in microseconds, past the end of bucket `index`
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < LINEAR_BUCKETS {
+        return index as u64 + 1;
+    }
+    let (exponent, sub) = (
+        (index - LINEAR_BUCKETS) / SUB_BUCKETS + 4,
+        (index - LINEAR_BUCKETS) % SUB_BUCKETS,
+    );
+    ((SUB_BUCKETS + sub + 1) as u64) << (exponent - 2)
+}
+
+/// Process-wide counters since boot
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    requests: AtomicU64,
+    redirects: AtomicU64,
+    not_found: AtomicU64,
+    reload_failures: AtomicU64,
+    /// Redirect events dropped because the `--webhook` queue was full
+    webhook_dropped: AtomicU64,
+    /// Unix time of the last successful (re)load, starting with the boot load
+    last_reload: AtomicU64,
+    pub hits: HitCounter,
+    /// Time spent producing redirect responses
+    pub latency: LatencyHistogram,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            redirects: AtomicU64::new(0),
+            not_found: AtomicU64::new(0),
+            reload_failures: AtomicU64::new(0),
+            webhook_dropped: AtomicU64::new(0),
+            last_reload: AtomicU64::new(unix_now()),
+            hits: HitCounter::default(),
+            latency: LatencyHistogram::default(),
+        }
+    }
+}
+
+/// The JSON body of `/_stats`
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub uptime_seconds: u64,
+    pub requests: u64,
+    pub redirects: u64,
+    pub not_found: u64,
+    pub reload_failures: u64,
+    pub webhook_dropped: u64,
+    pub last_reload_timestamp: u64,
+    pub hits: BTreeMap<String, u64>,
+    pub latency: LatencySnapshot,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl Stats {
+    /// Counts a finished request by its response status
+    pub fn record_response(&self, status: StatusCode) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if status.is_redirection() {
+            self.redirects.fetch_add(1, Ordering::Relaxed);
+        } else if status == StatusCode::NOT_FOUND {
+            self.not_found.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a reload attempt; only successes move the last-reload time
+    pub fn record_reload(&self, success: bool) {
+        if success {
+            self.last_reload.store(unix_now(), Ordering::Relaxed);
+        } else {
+            self.reload_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_webhook_drop(&self) {
+        self.webhook_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters in the Prometheus text format, for `/metrics`
+    pub fn render_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let metrics = [
+            (
+                "dslf_requests_total",
+                "counter",
+                "Requests answered",
+                snapshot.requests,
+            ),
+            (
+                "dslf_redirects_total",
+                "counter",
+                "Redirects sent",
+                snapshot.redirects,
+            ),
+            (
+                "dslf_not_found_total",
+                "counter",
+                "404 responses",
+                snapshot.not_found,
+            ),
+            (
+                "dslf_reload_failures_total",
+                "counter",
+                "Config reloads that failed and kept the previous rules",
+                snapshot.reload_failures,
+            ),
+            (
+                "dslf_webhook_dropped_total",
+                "counter",
+                "Redirect events dropped because the webhook queue was full",
+                snapshot.webhook_dropped,
+            ),
+            (
+                "dslf_last_reload_success_timestamp_seconds",
+                "gauge",
+                "Unix time of the last successful config load",
+                snapshot.last_reload_timestamp,
+            ),
+            (
+                "dslf_uptime_seconds",
+                "gauge",
+                "Seconds since start",
+                snapshot.uptime_seconds,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+            );
+        }
+        out
+    }
+
+    /// Totals followed by per-slug hits (busiest first), as dumped to stderr
+    /// on SIGUSR1
+    pub fn render_text(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = format!(
+            "dslf stats: uptime={}s requests={} redirects={} not_found={} reload_failures={}\n",
+            snapshot.uptime_seconds,
+            snapshot.requests,
+            snapshot.redirects,
+            snapshot.not_found,
+            snapshot.reload_failures,
+        );
+
+        let mut hits: Vec<_> = snapshot.hits.into_iter().collect();
+        hits.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        for (slug, count) in hits {
+            let _ = writeln!(out, "  {count:>8} {slug}");
+        }
+        out
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            uptime_seconds: self.started.elapsed().as_secs(),
+            requests: self.requests.load(Ordering::Relaxed),
+            redirects: self.redirects.load(Ordering::Relaxed),
+            not_found: self.not_found.load(Ordering::Relaxed),
+            reload_failures: self.reload_failures.load(Ordering::Relaxed),
+            webhook_dropped: self.webhook_dropped.load(Ordering::Relaxed),
+            last_reload_timestamp: self.last_reload.load(Ordering::Relaxed),
+            hits: self.hits.snapshot(),
+            latency: self.latency.snapshot(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_counter() {
+        let hits = HitCounter::default();
+
+        assert_eq!(hits.record("/gh"), 1);
+        assert_eq!(hits.record("/gh"), 2);
+        assert_eq!(hits.record("/docs/*"), 1);
+        assert_eq!(hits.record("/gh"), 3);
+
+        let snapshot = hits.snapshot();
+        assert_eq!(snapshot["/gh"], 3);
+        assert_eq!(snapshot["/docs/*"], 1);
+    }
+
+    #[test]
+    fn test_record_response() {
+        let stats = Stats::default();
+        stats.record_response(StatusCode::MOVED_PERMANENTLY);
+        stats.record_response(StatusCode::TEMPORARY_REDIRECT);
+        stats.record_response(StatusCode::NOT_FOUND);
+        stats.record_response(StatusCode::OK);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.requests, 4);
+        assert_eq!(snapshot.redirects, 2);
+        assert_eq!(snapshot.not_found, 1);
+        assert_eq!(snapshot.uptime_seconds, 0);
+    }
+
+    #[test]
+    fn test_render_text() {
+        let stats = Stats::default();
+        stats.record_response(StatusCode::MOVED_PERMANENTLY);
+        stats.record_response(StatusCode::NOT_FOUND);
+        stats.hits.record("/docs/*");
+        stats.hits.record("/gh");
+        stats.hits.record("/gh");
+
+        assert_eq!(
+            stats.render_text(),
+            "dslf stats: uptime=0s requests=2 redirects=1 not_found=1 reload_failures=0\n\
+             \x20\x20       2 /gh\n\
+             \x20\x20       1 /docs/*\n"
+        );
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let latency = LatencyHistogram::default();
+        assert_eq!(
+            latency.snapshot(),
+            LatencySnapshot {
+                p50_ms: None,
+                p95_ms: None,
+                p99_ms: None
+            }
+        );
+
+        // 90 fast, 9 slower and 1 very slow redirect
+        for _ in 0..90 {
+            latency.record(Duration::from_micros(200));
+        }
+        for _ in 0..9 {
+            latency.record(Duration::from_millis(5));
+        }
+        latency.record(Duration::from_secs(2));
+
+        let in_range = |value: Option<f64>, expected: f64| {
+            let value = value.unwrap();
+            assert!(
+                (expected..=expected * 1.25).contains(&value),
+                "{value} not within 25% above {expected}"
+            );
+        };
+        let snapshot = latency.snapshot();
+        in_range(snapshot.p50_ms, 0.2);
+        in_range(snapshot.p95_ms, 5.0);
+        in_range(snapshot.p99_ms, 5.0);
+        in_range(latency.percentile(1.0), 2000.0);
+
+        // Sub-microsecond and huge values stay in the first and last bucket
+        latency.record(Duration::ZERO);
+        latency.record(Duration::from_secs(3600));
+        assert_eq!(latency.percentile(0.0), Some(0.001));
+        assert_eq!(latency.percentile(1.0), Some(67_108.864));
+    }
+
+    #[test]
+    fn test_latency_buckets_are_contiguous() {
+        for micros in 0..100_000 {
+            let index = bucket(micros);
+            assert!(micros < bucket_upper_bound(index), "{micros}");
+            if index > 0 {
+                assert!(micros >= bucket_upper_bound(index - 1), "{micros}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_reload() {
+        let stats = Stats::default();
+        stats.last_reload.store(0, Ordering::Relaxed);
+
+        stats.record_reload(false);
+        stats.record_reload(false);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.reload_failures, 2);
+        assert_eq!(snapshot.last_reload_timestamp, 0);
+
+        stats.record_reload(true);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.reload_failures, 2);
+        assert!(snapshot.last_reload_timestamp > 0);
+
+        let metrics = stats.render_prometheus();
+        assert!(
+            metrics.contains(
+                "# TYPE dslf_reload_failures_total counter\ndslf_reload_failures_total 2\n"
+            )
+        );
+        assert!(metrics.contains("# TYPE dslf_last_reload_success_timestamp_seconds gauge\n"));
+    }
+}
@@ -0,0 +1,876 @@
+use crate::matcher::{Matcher, RuleKind};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Sends the HEAD request used to check a destination
+pub trait Probe: Sync {
+    fn head(&self, url: &str) -> impl Future<Output = Result<StatusCode, String>> + Send;
+
+    /// Like [`Probe::head`], plus the `Location` of a redirect so
+    /// [`validate_targets`] can follow it. Probes that follow redirects
+    /// themselves never report one.
+    fn head_location(
+        &self,
+        url: &str,
+    ) -> impl Future<Output = Result<(StatusCode, Option<String>), String>> + Send {
+        async move { Ok((self.head(url).await?, None)) }
+    }
+}
+
+impl Probe for reqwest::Client {
+    async fn head(&self, url: &str) -> Result<StatusCode, String> {
+        Ok(self.head_location(url).await?.0)
+    }
+
+    async fn head_location(&self, url: &str) -> Result<(StatusCode, Option<String>), String> {
+        let response = reqwest::Client::head(self, url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok((response.status(), location))
+    }
+}
+
+/// The client for validating destinations. It doesn't follow redirects, so
+/// [`validate_targets`] can count the hops itself.
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap_or_default()
+}
+
+/// Sends the GET used to warm up a destination. Clients are cloned into one
+/// task per request.
+pub trait Fetch: Clone + Send + Sync + 'static {
+    fn get(&self, url: &str) -> impl Future<Output = Result<StatusCode, String>> + Send;
+}
+
+impl Fetch for reqwest::Client {
+    async fn get(&self, url: &str) -> Result<StatusCode, String> {
+        let response = reqwest::Client::get(self, url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(response.status())
+    }
+}
+
+/// Outcome of checking one destination
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationResult {
+    pub slug: String,
+    pub target: String,
+    /// Response status, if the request completed
+    pub status: Option<u16>,
+    /// Why the destination counts as unreachable
+    pub error: Option<String>,
+    /// Reused from a [`ValidationCache`] instead of checked again
+    pub cached: bool,
+    /// Redirects followed before the final answer
+    pub redirects: usize,
+    /// Where the redirects ended up, when there were any
+    pub final_url: Option<String>,
+}
+
+impl ValidationResult {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Slugs and targets worth checking: retired links have no destination, and
+/// templated targets only exist once a request matches
+pub fn validation_targets(rules: &Matcher) -> Vec<(String, String)> {
+    rules
+        .entries()
+        .into_iter()
+        .filter(|(_, _, rule)| rule.status != 410)
+        .filter(|(_, kind, rule)| match kind {
+            RuleKind::Exact => true,
+            RuleKind::Template => !rule.target.contains('{'),
+            RuleKind::Wildcard => !rule.target.ends_with('*'),
+            RuleKind::Regex => !rule.target.contains('$'),
+        })
+        .flat_map(|(slug, _, rule)| {
+            // Rotating rules list their own target first
+            let targets = match &rule.rotation {
+                Some(rotation) => rotation.targets().to_vec(),
+                None => vec![rule.target.clone()],
+            };
+            targets
+                .into_iter()
+                .map(move |target| (slug.to_string(), target))
+        })
+        .collect()
+}
+
+/// Sends a HEAD request to `target`, returning the status if the request
+/// completed and an error unless it was a success, a redirection or one of the
+/// `accept` statuses
+pub async fn check_target(
+    client: &impl Probe,
+    target: &str,
+    accept: &[u16],
+) -> (Option<u16>, Option<String>) {
+    match client.head(target).await {
+        Ok(status) => (Some(status.as_u16()), status_error(status, accept)),
+        Err(e) => (None, Some(e)),
+    }
+}
+
+fn status_error(status: StatusCode, accept: &[u16]) -> Option<String> {
+    let ok = status.is_success() || status.is_redirection() || accept.contains(&status.as_u16());
+    (!ok).then(|| format!("HTTP {status}"))
+}
+
+/// [`check_target`], following up to `max_redirects` redirects. A redirect
+/// left over once the cap is reached counts as reachable, like any 3xx.
+async fn follow_target(
+    client: &impl Probe,
+    target: &str,
+    options: ValidateOptions<'_>,
+) -> (Option<u16>, Option<String>, usize, Option<String>) {
+    let mut url = target.to_string();
+    let mut redirects = 0;
+    loop {
+        let (status, location) = match client.head_location(&url).await {
+            Ok(answer) => answer,
+            Err(e) => return (None, Some(e), redirects, None),
+        };
+        let next = location
+            .filter(|_| status.is_redirection() && redirects < options.max_redirects)
+            .and_then(|location| reqwest::Url::parse(&url).ok()?.join(&location).ok());
+        match next {
+            Some(next) => {
+                url = next.to_string();
+                redirects += 1;
+            }
+            None => {
+                let error = status_error(status, options.accept);
+                let final_url = (redirects > 0).then_some(url);
+                return (Some(status.as_u16()), error, redirects, final_url);
+            }
+        }
+    }
+}
+
+/// Tweaks for [`validate_rules`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidateOptions<'a> {
+    /// Statuses counted as reachable besides 2xx and 3xx
+    pub accept: &'a [u16],
+    /// Redirects to follow per destination, reporting the hops and final URL
+    pub max_redirects: usize,
+    /// Report targets with a fresh passing entry from this cache instead of
+    /// checking them again
+    pub cache: Option<(&'a ValidationCache, CacheFreshness)>,
+    /// Check distinct hosts concurrently, with at most this many requests in
+    /// flight to each. Unset checks one target at a time.
+    pub max_per_host: Option<usize>,
+}
+
+/// Checks every destination, following redirects as configured. Results follow
+/// [`validation_targets`] order.
+pub async fn validate_rules(
+    rules: &Matcher,
+    client: &impl Probe,
+    options: ValidateOptions<'_>,
+) -> Vec<ValidationResult> {
+    validate_targets(validation_targets(rules), client, options).await
+}
+
+/// Checks `(slug, target)` pairs in order, see [`validate_rules`]
+pub async fn validate_targets(
+    targets: Vec<(String, String)>,
+    client: &impl Probe,
+    options: ValidateOptions<'_>,
+) -> Vec<ValidationResult> {
+    let Some(max_per_host) = options.max_per_host else {
+        let mut results = Vec::new();
+        for (slug, target) in targets {
+            results.push(validate_target(slug, target, client, options).await);
+        }
+        return results;
+    };
+
+    // One limit per host the target names; redirects to other hosts count
+    // against the host they started from
+    let mut limits: HashMap<String, Arc<Semaphore>> = HashMap::new();
+    let checks = targets.into_iter().map(|(slug, target)| {
+        let host = reqwest::Url::parse(&target)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+            .unwrap_or_default();
+        let limit = limits
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(max_per_host.max(1))))
+            .clone();
+        async move {
+            let _permit = limit.acquire().await;
+            validate_target(slug, target, client, options).await
+        }
+    });
+    futures_util::future::join_all(checks.collect::<Vec<_>>()).await
+}
+
+/// Checks one destination, or reports its cached result
+async fn validate_target(
+    slug: String,
+    target: String,
+    client: &impl Probe,
+    options: ValidateOptions<'_>,
+) -> ValidationResult {
+    let cached = options
+        .cache
+        .and_then(|(cache, freshness)| cache.fresh(&target, freshness));
+    let (status, error, cached, redirects, final_url) = match cached {
+        Some(entry) => (entry.status, None, true, 0, None),
+        None => {
+            let (status, error, redirects, final_url) =
+                follow_target(client, &target, options).await;
+            (status, error, false, redirects, final_url)
+        }
+    };
+    ValidationResult {
+        slug,
+        target,
+        status,
+        error,
+        cached,
+        redirects,
+        final_url,
+    }
+}
+
+/// Reads targets for `dslf validate --stdin`: one URL per line, or
+/// `url,target,status` rows (header optional). Blank lines and `#` comments
+/// are skipped. Bare URLs are labelled with their line number.
+pub fn read_target_list(
+    mut reader: impl io::Read,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+
+    let mut targets = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !line.contains(',') {
+            targets.push((format!("stdin:{}", index + 1), line.to_string()));
+            continue;
+        }
+
+        let record = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .trim(csv::Trim::All)
+            .from_reader(line.as_bytes())
+            .records()
+            .next()
+            .transpose()?
+            .unwrap_or_default();
+        match (record.get(0), record.get(1)) {
+            (Some("url"), Some("target")) => {}
+            (Some(slug), Some(target)) => targets.push((slug.to_string(), target.to_string())),
+            _ => return Err(format!("Line {}: expected url,target,status", index + 1).into()),
+        }
+    }
+    Ok(targets)
+}
+
+/// GETs each distinct destination once, at most `concurrency` at a time, so
+/// slow-starting targets are primed before traffic arrives. Returns how many
+/// targets answered without a transport error, out of how many were tried.
+pub async fn warmup(rules: &Matcher, client: &impl Fetch, concurrency: usize) -> (usize, usize) {
+    let mut targets: Vec<String> = validation_targets(rules)
+        .into_iter()
+        .map(|(_, target)| target)
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    let total = targets.len();
+    for target in targets {
+        let client = client.clone();
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            client.get(&target).await.is_ok()
+        });
+    }
+
+    let answered = tasks.join_all().await.into_iter().filter(|ok| *ok).count();
+    (answered, total)
+}
+
+/// Looks up a destination host, for `--prewarm-dns`. Resolvers are cloned
+/// into one task per host.
+pub trait Resolve: Clone + Send + Sync + 'static {
+    fn resolve(&self, host: &str, port: u16) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// Resolves through the system resolver, priming its cache
+#[derive(Debug, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> Result<(), String> {
+        let addrs = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| e.to_string())?;
+        match addrs.count() {
+            0 => Err("no addresses".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Distinct `(host, port)` pairs of absolute destinations, sorted
+pub fn target_hosts(rules: &Matcher) -> Vec<(String, u16)> {
+    let mut hosts: Vec<(String, u16)> = validation_targets(rules)
+        .into_iter()
+        .filter_map(|(_, target)| {
+            let url = reqwest::Url::parse(&target).ok()?;
+            Some((url.host_str()?.to_string(), url.port_or_known_default()?))
+        })
+        .collect();
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// Resolves every [`target_hosts`] entry, at most `concurrency` at a time.
+/// Returns the hosts that failed with why, and how many were tried.
+pub async fn prewarm_dns(
+    rules: &Matcher,
+    resolver: &impl Resolve,
+    concurrency: usize,
+) -> (Vec<(String, String)>, usize) {
+    let hosts = target_hosts(rules);
+    let total = hosts.len();
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for (host, port) in hosts {
+        let resolver = resolver.clone();
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            resolver.resolve(&host, port).await.err().map(|e| (host, e))
+        });
+    }
+
+    let mut failed: Vec<_> = tasks.join_all().await.into_iter().flatten().collect();
+    failed.sort();
+    (failed, total)
+}
+
+/// When a cached result may be reused
+#[derive(Debug, Clone, Copy)]
+pub struct CacheFreshness {
+    /// Unix time of this run
+    pub now: u64,
+    /// Seconds a passing result stays valid
+    pub ttl: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    /// Unix time the target was last actually checked
+    pub checked_at: u64,
+}
+
+/// Previous validation results keyed by target URL, for `--validate-only-changed`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ValidationCache {
+    pub entries: BTreeMap<String, CacheEntry>,
+}
+
+impl ValidationCache {
+    /// Reads the cache, treating a missing file as empty
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).map_err(io::Error::other),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// The entry for `target`, if it passed within the TTL. Failures are never
+    /// fresh, so they're always checked again.
+    pub fn fresh(&self, target: &str, freshness: CacheFreshness) -> Option<&CacheEntry> {
+        self.entries.get(target).filter(|entry| {
+            entry.error.is_none() && freshness.now.saturating_sub(entry.checked_at) < freshness.ttl
+        })
+    }
+
+    /// Replaces the cache with this run's results. Reused results keep their
+    /// original check time, so they still expire.
+    pub fn update(&mut self, results: &[ValidationResult], now: u64) {
+        let mut entries = BTreeMap::new();
+        for result in results {
+            let previous = self.entries.remove(&result.target);
+            let entry = match previous {
+                Some(entry) if result.cached => entry,
+                _ => CacheEntry {
+                    status: result.status,
+                    error: result.error.clone(),
+                    checked_at: now,
+                },
+            };
+            entries.insert(result.target.clone(), entry);
+        }
+        self.entries = entries;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{ParsedRule, Rule, build_matcher};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Answers from a fixed table, erroring for unknown URLs
+    struct MockClient(HashMap<&'static str, StatusCode>);
+
+    impl Probe for MockClient {
+        async fn head(&self, url: &str) -> Result<StatusCode, String> {
+            self.0
+                .get(url)
+                .copied()
+                .ok_or_else(|| format!("dns error: {url}"))
+        }
+    }
+
+    fn parsed(url: &str, target: &str, status: u16) -> ParsedRule {
+        ParsedRule {
+            url: url.to_string(),
+            rule: Rule::new(target, status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_rules() {
+        let rules = build_matcher(vec![
+            parsed("/ok", "https://ok.example.com", 301),
+            parsed("/moved", "https://moved.example.com", 302),
+            parsed("/missing", "https://missing.example.com", 301),
+            parsed("/down", "https://down.example.com", 301),
+            parsed("/retired", "", 410),
+            parsed("~^/blog/(.+)$", "https://blog.example.com/$1", 301),
+        ])
+        .unwrap();
+        let client = MockClient(HashMap::from([
+            ("https://ok.example.com", StatusCode::OK),
+            ("https://moved.example.com", StatusCode::MOVED_PERMANENTLY),
+            ("https://missing.example.com", StatusCode::NOT_FOUND),
+        ]));
+
+        let results = validate_rules(&rules, &client, ValidateOptions::default()).await;
+        let result =
+            |slug: &str, target: &str, status: Option<u16>, error: Option<&str>| ValidationResult {
+                slug: slug.to_string(),
+                target: target.to_string(),
+                status,
+                error: error.map(str::to_string),
+                cached: false,
+                redirects: 0,
+                final_url: None,
+            };
+        assert_eq!(
+            results,
+            vec![
+                result(
+                    "/down",
+                    "https://down.example.com",
+                    None,
+                    Some("dns error: https://down.example.com")
+                ),
+                result(
+                    "/missing",
+                    "https://missing.example.com",
+                    Some(404),
+                    Some("HTTP 404 Not Found")
+                ),
+                result("/moved", "https://moved.example.com", Some(301), None),
+                result("/ok", "https://ok.example.com", Some(200), None),
+            ]
+        );
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_validate_rules_accept() {
+        let rules =
+            build_matcher(vec![parsed("/cdn", "https://cdn.example.com/a.png", 301)]).unwrap();
+        let client = MockClient(HashMap::from([(
+            "https://cdn.example.com/a.png",
+            StatusCode::FORBIDDEN,
+        )]));
+
+        let results = validate_rules(&rules, &client, ValidateOptions::default()).await;
+        assert_eq!(results[0].error.as_deref(), Some("HTTP 403 Forbidden"));
+
+        let options = ValidateOptions {
+            accept: &[403, 405],
+            ..Default::default()
+        };
+        let results = validate_rules(&rules, &client, options).await;
+        assert!(results[0].is_ok());
+        assert_eq!(results[0].status, Some(403));
+
+        let options = ValidateOptions {
+            accept: &[405],
+            ..Default::default()
+        };
+        let results = validate_rules(&rules, &client, options).await;
+        assert!(!results[0].is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_validate_target_list() {
+        let input = "# ad-hoc checks\n\
+                     https://ok.example.com\n\
+                     \n\
+                     url,target,status\n\
+                     /new,https://missing.example.com,301\n";
+        let targets = read_target_list(input.as_bytes()).unwrap();
+        assert_eq!(
+            targets,
+            vec![
+                ("stdin:2".to_string(), "https://ok.example.com".to_string()),
+                (
+                    "/new".to_string(),
+                    "https://missing.example.com".to_string()
+                ),
+            ]
+        );
+
+        let client = MockClient(HashMap::from([
+            ("https://ok.example.com", StatusCode::OK),
+            ("https://missing.example.com", StatusCode::NOT_FOUND),
+        ]));
+        let results = validate_targets(targets, &client, ValidateOptions::default()).await;
+        let summary: Vec<_> = results
+            .iter()
+            .map(|r| (r.slug.as_str(), r.status, r.is_ok()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("stdin:2", Some(200), true), ("/new", Some(404), false)]
+        );
+    }
+
+    /// Redirects `/a` to `/b` to `/c`, relative to the request
+    struct RedirectingClient;
+
+    impl Probe for RedirectingClient {
+        async fn head(&self, _url: &str) -> Result<StatusCode, String> {
+            unreachable!("validation asks for the location")
+        }
+
+        async fn head_location(&self, url: &str) -> Result<(StatusCode, Option<String>), String> {
+            let next = match url {
+                "https://a.example.com/a" => Some("/b"),
+                "https://a.example.com/b" => Some("https://c.example.com/c"),
+                _ => None,
+            };
+            Ok(match next {
+                Some(next) => (StatusCode::MOVED_PERMANENTLY, Some(next.to_string())),
+                None => (StatusCode::OK, None),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_follows_redirects() {
+        let targets = || vec![("/a".to_string(), "https://a.example.com/a".to_string())];
+        let options = ValidateOptions {
+            max_redirects: 10,
+            ..Default::default()
+        };
+
+        let result = &validate_targets(targets(), &RedirectingClient, options).await[0];
+        assert!(result.is_ok());
+        assert_eq!(result.status, Some(200));
+        assert_eq!(result.redirects, 2);
+        assert_eq!(result.final_url.as_deref(), Some("https://c.example.com/c"));
+
+        // The cap stops at the last redirect, which still counts as reachable
+        let options = ValidateOptions {
+            max_redirects: 1,
+            ..Default::default()
+        };
+        let result = &validate_targets(targets(), &RedirectingClient, options).await[0];
+        assert!(result.is_ok());
+        assert_eq!(result.status, Some(301));
+        assert_eq!(result.redirects, 1);
+        assert_eq!(result.final_url.as_deref(), Some("https://a.example.com/b"));
+    }
+
+    /// Records which URLs were requested
+    #[derive(Default)]
+    struct RecordingClient(Mutex<Vec<String>>);
+
+    impl Probe for RecordingClient {
+        async fn head(&self, url: &str) -> Result<StatusCode, String> {
+            self.0.lock().unwrap().push(url.to_string());
+            Ok(StatusCode::OK)
+        }
+    }
+
+    /// Tracks how many requests are in flight per host, and the most seen
+    #[derive(Default)]
+    struct InFlightClient {
+        in_flight: Mutex<HashMap<String, usize>>,
+        peak: Mutex<HashMap<String, usize>>,
+        peak_total: Mutex<usize>,
+    }
+
+    impl Probe for InFlightClient {
+        async fn head(&self, url: &str) -> Result<StatusCode, String> {
+            let host = reqwest::Url::parse(url)
+                .unwrap()
+                .host_str()
+                .unwrap()
+                .to_string();
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                let count = in_flight.entry(host.clone()).or_default();
+                *count += 1;
+                let peak = &mut *self.peak.lock().unwrap();
+                let peak = peak.entry(host.clone()).or_default();
+                *peak = (*peak).max(*count);
+                let total = in_flight.values().sum();
+                let peak_total = &mut *self.peak_total.lock().unwrap();
+                *peak_total = (*peak_total).max(total);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            *self.in_flight.lock().unwrap().get_mut(&host).unwrap() -= 1;
+            Ok(StatusCode::OK)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_validate_max_per_host() {
+        let targets: Vec<(String, String)> = (0..6)
+            .map(|i| format!("https://busy.example.com/{i}"))
+            .chain(["https://a.example.com", "https://b.example.com"].map(String::from))
+            .map(|target| (target.clone(), target))
+            .collect();
+        let client = InFlightClient::default();
+        let options = ValidateOptions {
+            max_per_host: Some(2),
+            ..Default::default()
+        };
+
+        let results = validate_targets(targets.clone(), &client, options).await;
+        assert!(results.iter().all(ValidationResult::is_ok));
+        let order: Vec<_> = results.into_iter().map(|r| (r.slug, r.target)).collect();
+        assert_eq!(order, targets);
+
+        let peak = client.peak.lock().unwrap().clone();
+        assert_eq!(peak["busy.example.com"], 2);
+        assert_eq!(peak["a.example.com"], 1);
+        // The other hosts were checked alongside the busy one
+        assert_eq!(*client.peak_total.lock().unwrap(), 4);
+
+        // Without a limit, targets are checked one at a time
+        let client = InFlightClient::default();
+        validate_targets(targets, &client, ValidateOptions::default()).await;
+        assert_eq!(*client.peak_total.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_validate_rules_cached() {
+        let rules = build_matcher(vec![
+            parsed("/same", "https://same.example.com", 301),
+            parsed("/changed", "https://changed.example.com/v2", 301),
+            parsed("/failed", "https://failed.example.com", 301),
+            parsed("/stale", "https://stale.example.com", 301),
+        ])
+        .unwrap();
+        let entry = |error: Option<&str>, checked_at| CacheEntry {
+            status: Some(200),
+            error: error.map(str::to_string),
+            checked_at,
+        };
+        let mut cache = ValidationCache::default();
+        cache
+            .entries
+            .insert("https://same.example.com".to_string(), entry(None, 1000));
+        cache.entries.insert(
+            "https://changed.example.com/v1".to_string(),
+            entry(None, 1000),
+        );
+        cache.entries.insert(
+            "https://failed.example.com".to_string(),
+            entry(Some("HTTP 500"), 1000),
+        );
+        cache
+            .entries
+            .insert("https://stale.example.com".to_string(), entry(None, 0));
+        let freshness = CacheFreshness {
+            now: 1500,
+            ttl: 1000,
+        };
+
+        let client = RecordingClient::default();
+        let options = ValidateOptions {
+            cache: Some((&cache, freshness)),
+            ..Default::default()
+        };
+        let results = validate_rules(&rules, &client, options).await;
+
+        // Only the unchanged, passing and fresh target is skipped
+        assert_eq!(
+            *client.0.lock().unwrap(),
+            [
+                "https://changed.example.com/v2",
+                "https://failed.example.com",
+                "https://stale.example.com",
+            ]
+        );
+        let same = results.iter().find(|r| r.slug == "/same").unwrap();
+        assert!(same.cached && same.is_ok());
+
+        cache.update(&results, 1500);
+        assert_eq!(cache.entries.len(), 4);
+        assert!(!cache.entries.contains_key("https://changed.example.com/v1"));
+        assert_eq!(cache.entries["https://same.example.com"].checked_at, 1000);
+        assert_eq!(
+            cache.entries["https://failed.example.com"],
+            entry(None, 1500)
+        );
+    }
+
+    /// Counts GETs per URL, failing the ones containing "down"
+    #[derive(Clone, Default)]
+    struct CountingClient(Arc<Mutex<HashMap<String, usize>>>);
+
+    impl Fetch for CountingClient {
+        async fn get(&self, url: &str) -> Result<StatusCode, String> {
+            *self.0.lock().unwrap().entry(url.to_string()).or_default() += 1;
+            if url.contains("down") {
+                Err(format!("connection refused: {url}"))
+            } else {
+                Ok(StatusCode::OK)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warmup() {
+        let rules = build_matcher(vec![
+            parsed("/a", "https://a.example.com", 301),
+            parsed("/b", "https://b.example.com", 302),
+            parsed("/also-a", "https://a.example.com", 301),
+            parsed("/down", "https://down.example.com", 301),
+            parsed("/retired", "", 410),
+            parsed("/docs/*", "https://docs.example.com/*", 301),
+        ])
+        .unwrap();
+
+        let client = CountingClient::default();
+        assert_eq!(warmup(&rules, &client, 2).await, (2, 3));
+        assert_eq!(
+            *client.0.lock().unwrap(),
+            HashMap::from([
+                ("https://a.example.com".to_string(), 1),
+                ("https://b.example.com".to_string(), 1),
+                ("https://down.example.com".to_string(), 1),
+            ])
+        );
+    }
+
+    /// Records lookups, failing hosts starting with "dead"
+    #[derive(Clone, Default)]
+    struct RecordingResolver(Arc<Mutex<Vec<(String, u16)>>>);
+
+    impl Resolve for RecordingResolver {
+        async fn resolve(&self, host: &str, port: u16) -> Result<(), String> {
+            self.0.lock().unwrap().push((host.to_string(), port));
+            if host.starts_with("dead") {
+                Err("no such host".to_string())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prewarm_dns() {
+        let rules = build_matcher(vec![
+            parsed("/a", "https://a.example.com/one", 301),
+            parsed("/also-a", "https://a.example.com/two", 301),
+            parsed("/a-http", "http://a.example.com", 301),
+            parsed("/b", "https://b.example.com:8443/", 302),
+            parsed("/dead", "https://dead.example.com", 301),
+            parsed("/local", "/docs", 301),
+            parsed("/retired", "", 410),
+        ])
+        .unwrap();
+
+        let resolver = RecordingResolver::default();
+        let (failed, total) = prewarm_dns(&rules, &resolver, 2).await;
+        assert_eq!(total, 4);
+        assert_eq!(
+            failed,
+            vec![("dead.example.com".to_string(), "no such host".to_string())]
+        );
+
+        let mut resolved = resolver.0.lock().unwrap().clone();
+        resolved.sort();
+        assert_eq!(
+            resolved,
+            vec![
+                ("a.example.com".to_string(), 80),
+                ("a.example.com".to_string(), 443),
+                ("b.example.com".to_string(), 8443),
+                ("dead.example.com".to_string(), 443),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validation_cache_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        assert!(ValidationCache::load(&path).unwrap().entries.is_empty());
+
+        let mut cache = ValidationCache::default();
+        cache.entries.insert(
+            "https://example.com".to_string(),
+            CacheEntry {
+                status: Some(200),
+                error: None,
+                checked_at: 42,
+            },
+        );
+        cache.save(&path).unwrap();
+        assert_eq!(ValidationCache::load(&path).unwrap().entries, cache.entries);
+    }
+}
@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Events waiting for the webhook before new ones are dropped
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// One redirect, as POSTed to `--webhook`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RedirectEvent {
+    /// The rule's slug as written in the config
+    pub slug: String,
+    pub target: String,
+    pub status: u16,
+    /// Unix time of the redirect
+    pub ts: u64,
+    pub ua: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Queue in front of the background task delivering [`RedirectEvent`]s, so a
+/// slow webhook can never hold up a redirect
+#[derive(Debug)]
+pub struct Webhook {
+    queue: mpsc::Sender<RedirectEvent>,
+}
+
+impl Webhook {
+    /// A webhook whose events are read from the returned receiver
+    pub fn channel(capacity: usize) -> (Self, mpsc::Receiver<RedirectEvent>) {
+        let (queue, events) = mpsc::channel(capacity.max(1));
+        (Self { queue }, events)
+    }
+
+    /// Starts delivering events to `url`, one at a time
+    pub fn spawn(url: String, capacity: usize) -> Self {
+        let (webhook, events) = Self::channel(capacity);
+        tokio::spawn(deliver(url, events));
+        webhook
+    }
+
+    /// Queues `event` without waiting, returning false when it was dropped
+    /// because the queue is full
+    pub fn send(&self, event: RedirectEvent) -> bool {
+        self.queue.try_send(event).is_ok()
+    }
+}
+
+async fn deliver(url: String, mut events: mpsc::Receiver<RedirectEvent>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap_or_default();
+
+    while let Some(event) = events.recv().await {
+        let sent = client
+            .post(&url)
+            .json(&event)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Err(e) = sent {
+            eprintln!("Webhook: failed to deliver {}: {e}", event.slug);
+        }
+    }
+}